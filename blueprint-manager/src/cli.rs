@@ -1,4 +1,6 @@
 use blueprint_manager::config::BlueprintManagerConfig;
+use blueprint_manager::executor::cache_maintenance::run_cache_maintenance;
+use blueprint_manager::executor::self_test::run_self_test;
 use blueprint_manager::run_blueprint_manager;
 use blueprint_manager::sdk;
 use blueprint_manager::sdk::utils::msg_to_error;
@@ -17,10 +19,22 @@ fn main() -> color_eyre::Result<()> {
             "gadget",
         )?;
 
+        // Cache maintenance does not need to connect to the network
+        if blueprint_manager_config.list_binary_cache
+            || !blueprint_manager_config.clear_binary_cache.is_empty()
+        {
+            return run_cache_maintenance(&blueprint_manager_config).await;
+        }
+
         if let Some(gadget_config) = blueprint_manager_config.gadget_config.as_ref() {
             let gadget_config_settings = std::fs::read_to_string(gadget_config)?;
-            let gadget_config: GadgetConfig = toml::from_str(&gadget_config_settings)
+            let mut gadget_config: GadgetConfig = toml::from_str(&gadget_config_settings)
                 .map_err(|err| msg_to_error(err.to_string()))?;
+            if blueprint_manager_config.self_test {
+                blueprint_manager_config.resolve_keystore_uri(&mut gadget_config)?;
+                blueprint_manager_config.import_keys(&gadget_config)?;
+                return run_self_test(&gadget_config).await;
+            }
 
             // Allow CTRL-C to shutdown this CLI application instance
             let shutdown_signal = async move {
@@ -1,4 +1,14 @@
-use std::path::PathBuf;
+use crate::gadget::{RestartBackoff, RestartLimit, RestartPolicy};
+use crate::sources::cache::DEFAULT_CACHE_DIR;
+use crate::sources::download::DownloadRetry;
+use gadget_io::GadgetConfig;
+use gadget_sdk::info;
+use gadget_sdk::keystore::backend::fs::FilesystemKeystore;
+use gadget_sdk::keystore::import::import_keys_from_file;
+use gadget_sdk::keystore::KeystoreUriSanitizer;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -13,6 +23,11 @@ pub struct BlueprintManagerConfig {
     /// The path to the keystore
     #[structopt(short = "k", long = "keystore-uri")]
     pub keystore_uri: String,
+    /// A JSON file listing keys to import into the keystore on startup, for automated
+    /// provisioning, as `[{ "key_type": "sr25519", "seed": "0x..." }, ...]`. Only sr25519, ecdsa
+    /// and ed25519 keys can be imported
+    #[structopt(long = "import-keys", parse(from_os_str))]
+    pub key_import_file: Option<PathBuf>,
     /// The verbosity level, can be used multiple times
     #[structopt(long, short = "v", parse(from_occurrences))]
     pub verbose: i32,
@@ -25,4 +40,407 @@ pub struct BlueprintManagerConfig {
     pub instance_id: Option<String>,
     #[structopt(long, short = "t")]
     pub test_mode: bool,
+    /// Overrides the template used to build binary download URLs, e.g. for GitHub Enterprise or a mirror.
+    /// Supports the `{owner}`, `{repo}`, `{tag}`, `{name}`, `{os}`, `{arch}` and `{ext}` placeholders.
+    /// Release artifacts that are gzipped tarballs or zip archives, as detected by their content,
+    /// are archives the binary named `{name}` is extracted from
+    #[structopt(long)]
+    pub download_url_template: Option<String>,
+    /// Resolve release assets through the GitHub API, allowing binaries to be fetched from
+    /// pre-releases and draft releases. With a download URL template, the asset named like its
+    /// last segment is fetched
+    #[structopt(long)]
+    pub github_api: bool,
+    /// The token used to authenticate against the GitHub API. Required for draft releases
+    #[structopt(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    pub github_token: Option<String>,
+    /// The hex encoded ed25519 public key the GitHub release binaries of a blueprint must be signed
+    /// with, as `<blueprint_id>:<public key>`. The detached signature of the raw SHA-256 digest of
+    /// the release artifact is fetched from the download URL of the binary with a `.sig` suffix,
+    /// and a binary that is not signed by the key is never run. Can be used multiple times
+    #[structopt(long = "binary-signing-key")]
+    pub binary_signing_keys: Vec<BinarySigningKey>,
+    /// The IPFS gateway binaries published to IPFS are fetched from. They are verified against
+    /// their CID, so the gateway does not need to be trusted
+    #[structopt(long, default_value = "https://ipfs.io")]
+    pub ipfs_gateway: String,
+    /// Verify that keys, connectivity and extrinsic submission work, then exit
+    #[structopt(long)]
+    pub self_test: bool,
+    /// List the binaries in the binary cache, with their size, when they were last used and the
+    /// blueprints using them, then exit
+    #[structopt(long)]
+    pub list_binary_cache: bool,
+    /// Delete the cached binary with the given name or digest, as listed by `--list-binary-cache`,
+    /// or every cached binary with `all`, then exit. The binaries of running services are kept.
+    /// Can be used multiple times
+    #[structopt(long)]
+    pub clear_binary_cache: Vec<String>,
+    /// An environment variable to set for a single service, as `<service>:<KEY>=<VALUE>`, where
+    /// `<service>` is `{blueprint_name}-{service_id}`. Can be used multiple times
+    #[structopt(long = "service-env")]
+    pub service_env: Vec<ServiceEnvVar>,
+    /// The window, in seconds, within which a service must crash repeatedly before it is reported
+    /// as failed. Services that exit early, e.g. during startup, are restarted either way
+    #[structopt(long, default_value = "60")]
+    pub failure_window_secs: u64,
+    /// The number of times in a row a service may be restarted after exiting. Once used up, the
+    /// service is left down for the restart cooldown. 0 disables restart throttling
+    #[structopt(long, default_value = "5")]
+    pub restart_burst: u32,
+    /// The time, in seconds, after which a service may be restarted once more, up to the burst
+    #[structopt(long, default_value = "60")]
+    pub restart_interval_secs: u64,
+    /// The time, in seconds, a service that used up its restarts is left down for
+    #[structopt(long, default_value = "300")]
+    pub restart_cooldown_secs: u64,
+    /// Whether a service is restarted once its process exits: `never`, `always`, or
+    /// `on-failure:<max restarts>` to only restart it after it exits with an error, a limited
+    /// number of times. A service that is not restarted is reported as dead until it is restarted
+    /// on-chain
+    #[structopt(long, default_value = "always")]
+    pub restart_policy: RestartPolicy,
+    /// The delay, in seconds, before restarting a service that exited. It is doubled after every
+    /// restart, up to the maximum restart delay, and starts over once the service stays up for the
+    /// whole failure window. 0 restarts services right away
+    #[structopt(long, default_value = "1")]
+    pub restart_delay_secs: u64,
+    /// The longest delay, in seconds, before restarting a service that keeps exiting
+    #[structopt(long, default_value = "300")]
+    pub max_restart_delay_secs: u64,
+    /// A command to run the gadget binaries through, for namespace and filesystem isolation,
+    /// e.g. `bwrap --unshare-all --share-net --ro-bind / / --dev /dev`. Disabled by default
+    #[structopt(long)]
+    pub sandbox_helper: Option<String>,
+    /// A seccomp profile restricting the syscalls of the gadget binaries, in the seccompiler JSON
+    /// format with a filter named `gadget`. Linux only, disabled by default
+    #[structopt(long, parse(from_os_str))]
+    pub seccomp_profile: Option<PathBuf>,
+    /// The base directory all the state of the blueprint manager and its gadgets is kept under:
+    /// the binary cache, the downloaded binaries, relative keystore paths and the working directory
+    /// of each gadget. Each gadget is run from its own `services/<service>` directory, which it is
+    /// also given as `SERVICE_DATA_DIR`, while `DATA_DIR` still holds its keystore. Defaults to the
+    /// current directory
+    #[structopt(long, parse(from_os_str))]
+    pub data_dir: Option<PathBuf>,
+    /// Whether to keep the binary of a service once it is stopped on-chain, for a fast restart.
+    /// When `false`, the binary and its cache entry are deleted to reclaim disk space, unless
+    /// another service still runs it
+    #[structopt(long, parse(try_from_str), default_value = "true")]
+    pub keep_binaries_on_stop: bool,
+    /// The largest binary, in bytes, buffered in memory while it is downloaded. Larger binaries,
+    /// and those whose size is not known upfront, are streamed to disk instead
+    #[structopt(long, default_value = "16777216")]
+    pub max_in_memory_binary_size: u64,
+    /// The number of times a binary download failing on a network or server error is retried
+    #[structopt(long, default_value = "3")]
+    pub download_retries: usize,
+    /// The delay, in milliseconds, before retrying a failed binary download. It is doubled after
+    /// every attempt, with some jitter
+    #[structopt(long, default_value = "500")]
+    pub download_retry_delay_ms: u64,
+    /// The maximum number of blueprints whose services are started at once, so that a slow
+    /// download or start does not hold up the services of other blueprints
+    #[structopt(long, default_value = "4")]
+    pub max_concurrent_starts: usize,
+}
+
+impl BlueprintManagerConfig {
+    /// The environment variables configured for `service_str` only
+    pub fn service_env_vars<'a>(
+        &'a self,
+        service_str: &'a str,
+    ) -> impl Iterator<Item = (String, String)> + 'a {
+        self.service_env
+            .iter()
+            .filter(move |var| var.service == service_str)
+            .map(|var| (var.key.clone(), var.value.clone()))
+    }
+
+    /// The public key the binaries of `blueprint_id` must be signed with, if any
+    pub fn signing_key(&self, blueprint_id: u64) -> Option<[u8; 32]> {
+        self.binary_signing_keys
+            .iter()
+            .find(|key| key.blueprint_id == blueprint_id)
+            .map(|key| key.public_key)
+    }
+
+    /// The configured delay before restarting a service, if enabled
+    pub fn restart_backoff(&self) -> Option<RestartBackoff> {
+        (self.restart_delay_secs > 0).then(|| RestartBackoff {
+            base: Duration::from_secs(self.restart_delay_secs),
+            max: Duration::from_secs(self.max_restart_delay_secs),
+        })
+    }
+
+    /// The configured restart throttling, if enabled
+    pub fn restart_limit(&self) -> Option<RestartLimit> {
+        (self.restart_burst > 0).then(|| RestartLimit {
+            burst: self.restart_burst,
+            refill_interval: Duration::from_secs(self.restart_interval_secs),
+            cooldown: Duration::from_secs(self.restart_cooldown_secs),
+        })
+    }
+
+    /// How failed binary downloads are retried
+    pub fn download_retry(&self) -> DownloadRetry {
+        DownloadRetry {
+            max_retries: self.download_retries,
+            base_delay: Duration::from_millis(self.download_retry_delay_ms),
+        }
+    }
+
+    /// Resolves the keystore of `gadget_config` against the data directory, once at startup.
+    ///
+    /// Every gadget runs from its own service directory, where a relative keystore path would
+    /// point somewhere else than for the blueprint manager.
+    pub fn resolve_keystore_uri(&self, gadget_config: &mut GadgetConfig) -> std::io::Result<()> {
+        gadget_config.keystore_uri = self.data_dir()?.keystore_uri(&gadget_config.keystore_uri);
+        Ok(())
+    }
+
+    /// Imports the keys listed in the key import file, if any, into the keystore of
+    /// `gadget_config`
+    pub fn import_keys(&self, gadget_config: &GadgetConfig) -> color_eyre::Result<()> {
+        let Some(path) = &self.key_import_file else {
+            return Ok(());
+        };
+        let keystore = FilesystemKeystore::open(&gadget_config.keystore_uri)?;
+        import_keys_from_file(&keystore, path)?;
+        info!(
+            "Imported the keys listed in {} into {}",
+            path.display(),
+            gadget_config.keystore_uri
+        );
+        Ok(())
+    }
+
+    /// The configured data directory, or the current directory if none is set
+    pub fn data_dir(&self) -> std::io::Result<DataDir> {
+        match &self.data_dir {
+            Some(root) => Ok(DataDir::new(std::path::absolute(root)?)),
+            None => Ok(DataDir::new(std::env::current_dir()?)),
+        }
+    }
+}
+
+/// The layout of the directory holding the state of the blueprint manager and its gadgets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDir {
+    root: PathBuf,
+}
+
+impl DataDir {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The content-addressed cache of downloaded binaries
+    pub fn binary_cache(&self) -> PathBuf {
+        self.root.join(DEFAULT_CACHE_DIR)
+    }
+
+    /// The directory the binary of each blueprint is linked to from the cache
+    pub fn binaries(&self) -> PathBuf {
+        self.root.join("binaries")
+    }
+
+    /// The working directory of the gadget running `service_str`, which is also passed to it as
+    /// `SERVICE_DATA_DIR`
+    pub fn service(&self, service_str: &str) -> PathBuf {
+        self.root.join("services").join(service_str)
+    }
+
+    /// Resolves a keystore given as a relative path against the data directory, leaving absolute
+    /// paths untouched
+    pub fn keystore_uri(&self, keystore_uri: &str) -> String {
+        let path = keystore_uri.sanitize_file_path();
+        if path.is_absolute() {
+            keystore_uri.to_string()
+        } else {
+            let path = path.strip_prefix(".").unwrap_or(&path);
+            self.root.join(path).display().to_string()
+        }
+    }
+}
+
+/// An environment variable passed to the process of a single service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceEnvVar {
+    pub service: String,
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for ServiceEnvVar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid service env var `{s}`, expected <service>:<KEY>=<VALUE>");
+        let (service, var) = s.split_once(':').ok_or_else(invalid)?;
+        let (key, value) = var.split_once('=').ok_or_else(invalid)?;
+        if service.is_empty() || key.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            service: service.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// The public key the binaries of a blueprint must be signed with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinarySigningKey {
+    pub blueprint_id: u64,
+    /// The ed25519 public key
+    pub public_key: [u8; 32],
+}
+
+impl FromStr for BinarySigningKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!("Invalid binary signing key `{s}`, expected <blueprint_id>:<hex ed25519 public key>")
+        };
+        let (blueprint_id, public_key) = s.split_once(':').ok_or_else(invalid)?;
+        let blueprint_id = blueprint_id.parse().map_err(|_| invalid())?;
+        let public_key = hex::decode(public_key.trim_start_matches("0x"))
+            .ok()
+            .and_then(|public_key| <[u8; 32]>::try_from(public_key).ok())
+            .ok_or_else(invalid)?;
+
+        Ok(Self {
+            blueprint_id,
+            public_key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_env_vars_only_apply_to_their_service() {
+        let config = BlueprintManagerConfig::from_iter([
+            "blueprint-manager",
+            "--keystore-uri",
+            "./keystore",
+            "--service-env",
+            "incredible-squaring-1:RUST_LOG=debug",
+            "--service-env",
+            "incredible-squaring-1:CONFIG_PATH=/etc/squaring.toml",
+            "--service-env",
+            "incredible-squaring-2:RUST_LOG=trace",
+        ]);
+
+        assert_eq!(
+            config
+                .service_env_vars("incredible-squaring-1")
+                .collect::<Vec<_>>(),
+            vec![
+                ("RUST_LOG".to_string(), "debug".to_string()),
+                ("CONFIG_PATH".to_string(), "/etc/squaring.toml".to_string()),
+            ]
+        );
+        assert_eq!(
+            config
+                .service_env_vars("incredible-squaring-2")
+                .collect::<Vec<_>>(),
+            vec![("RUST_LOG".to_string(), "trace".to_string())]
+        );
+        assert_eq!(config.service_env_vars("incredible-squaring-3").count(), 0);
+    }
+
+    #[test]
+    fn test_state_is_kept_under_the_data_dir() {
+        let config = BlueprintManagerConfig::from_iter([
+            "blueprint-manager",
+            "--keystore-uri",
+            "./keystore",
+            "--data-dir",
+            "/var/lib/gadget",
+        ]);
+        let data_dir = config.data_dir().unwrap();
+        assert_eq!(data_dir.root(), Path::new("/var/lib/gadget"));
+        assert_eq!(
+            data_dir.binary_cache(),
+            Path::new("/var/lib/gadget/binary-cache")
+        );
+        assert_eq!(data_dir.binaries(), Path::new("/var/lib/gadget/binaries"));
+        assert_eq!(
+            data_dir.service("incredible-squaring-1"),
+            Path::new("/var/lib/gadget/services/incredible-squaring-1")
+        );
+
+        // Relative keystores are moved under the data directory, absolute ones are kept
+        assert_eq!(
+            data_dir.keystore_uri("./keystore"),
+            "/var/lib/gadget/keystore"
+        );
+        assert_eq!(
+            data_dir.keystore_uri("file:keystore"),
+            "/var/lib/gadget/keystore"
+        );
+        assert_eq!(
+            data_dir.keystore_uri("file:///etc/gadget/keystore"),
+            "file:///etc/gadget/keystore"
+        );
+
+        // Without an override, the state is kept in the current directory as before
+        let config = BlueprintManagerConfig::from_iter([
+            "blueprint-manager",
+            "--keystore-uri",
+            "./keystore",
+        ]);
+        assert_eq!(
+            config.data_dir().unwrap().root(),
+            std::env::current_dir().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_signing_keys_are_pinned_per_blueprint() {
+        let key = hex::encode([7u8; 32]);
+        let config = BlueprintManagerConfig::from_iter([
+            "blueprint-manager",
+            "--keystore-uri",
+            "./keystore",
+            "--binary-signing-key",
+            &format!("1:0x{key}"),
+        ]);
+        assert_eq!(config.signing_key(1), Some([7u8; 32]));
+        assert_eq!(config.signing_key(2), None);
+
+        assert!("1".parse::<BinarySigningKey>().is_err());
+        assert!("one:00".parse::<BinarySigningKey>().is_err());
+        assert!(format!("1:{}", &key[2..])
+            .parse::<BinarySigningKey>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_service_env_var_is_rejected() {
+        assert!("RUST_LOG=debug".parse::<ServiceEnvVar>().is_err());
+        assert!("incredible-squaring-1:RUST_LOG"
+            .parse::<ServiceEnvVar>()
+            .is_err());
+        assert!(":RUST_LOG=debug".parse::<ServiceEnvVar>().is_err());
+        assert_eq!(
+            "incredible-squaring-1:URL=http://a=b".parse::<ServiceEnvVar>(),
+            Ok(ServiceEnvVar {
+                service: "incredible-squaring-1".to_string(),
+                key: "URL".to_string(),
+                value: "http://a=b".to_string(),
+            })
+        );
+    }
 }
@@ -0,0 +1,119 @@
+use crate::config::BlueprintManagerConfig;
+use crate::sdk::utils::msg_to_error;
+use crate::sources::cache::{BinaryCache, CacheEntry};
+use gadget_sdk::{error, info};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Lists and clears the binary cache, as requested by `--list-binary-cache` and
+/// `--clear-binary-cache`.
+///
+/// This is safe to run while the manager is running: the binaries of running services are never
+/// cleared.
+///
+/// # Errors
+///
+/// Returns an error if the cache could not be read, or if any of the requested binaries could not
+/// be cleared.
+pub async fn run_cache_maintenance(config: &BlueprintManagerConfig) -> color_eyre::Result<()> {
+    let data_dir = config.data_dir()?;
+    let cache = BinaryCache::new(data_dir.binary_cache());
+    let binaries_dir = data_dir.binaries();
+    let entries = cache.entries(&binaries_dir).await?;
+
+    if config.list_binary_cache {
+        if entries.is_empty() {
+            println!("The binary cache at {} is empty", cache.root().display());
+        }
+        for entry in &entries {
+            println!("{}", describe_entry(entry));
+        }
+    }
+
+    if config.clear_binary_cache.is_empty() {
+        return Ok(());
+    }
+
+    let to_clear = if config.clear_binary_cache.iter().any(|hash| hash == "all") {
+        entries.iter().map(CacheEntry::name).collect()
+    } else {
+        config.clear_binary_cache.clone()
+    };
+    let in_use = in_use_binaries(&entries);
+
+    let mut failures = 0;
+    for name in &to_clear {
+        match cache.clear(name, &binaries_dir, &in_use).await {
+            Ok(()) => info!("Cleared cached binary {name}"),
+            Err(err) => {
+                error!("Failed to clear cached binary {name}: {err}");
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(msg_to_error(format!(
+            "{failures} of {} cached binaries could not be cleared",
+            to_clear.len()
+        )));
+    }
+    Ok(())
+}
+
+/// A one-line description of `entry`, e.g.
+/// `<entry name>  12345678 bytes  last used 42s ago  blueprint 1 @ v0.1.0`
+fn describe_entry(entry: &CacheEntry) -> String {
+    let last_used = entry
+        .last_used
+        .and_then(|last_used| SystemTime::now().duration_since(last_used).ok())
+        .map_or_else(
+            || "never used".to_string(),
+            |elapsed| format!("last used {}s ago", elapsed.as_secs()),
+        );
+    let links = if entry.links.is_empty() {
+        "unused".to_string()
+    } else {
+        entry
+            .links
+            .iter()
+            .map(|link| match (link.blueprint_id, &link.tag) {
+                (Some(blueprint_id), Some(tag)) => format!("blueprint {blueprint_id} @ {tag}"),
+                _ => link.path.display().to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    format!(
+        "{}  {} bytes  {last_used}  {links}",
+        entry.name(),
+        entry.size
+    )
+}
+
+/// The service binaries that must not be cleared because a process may be running them.
+///
+/// Running processes can only be inspected on Linux. Elsewhere, every binary linked to a cache
+/// entry is considered in use.
+#[cfg_attr(target_os = "linux", allow(unused_variables))]
+fn in_use_binaries(entries: &[CacheEntry]) -> Vec<PathBuf> {
+    #[cfg(target_os = "linux")]
+    {
+        let Ok(processes) = std::fs::read_dir("/proc") else {
+            return vec![];
+        };
+        processes
+            .flatten()
+            .filter_map(|process| std::fs::read_link(process.path().join("exe")).ok())
+            .collect()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        entries
+            .iter()
+            .flat_map(|entry| entry.links.iter().map(|link| link.path.clone()))
+            .collect()
+    }
+}
@@ -1,17 +1,25 @@
 use crate::config::BlueprintManagerConfig;
 use crate::gadget::native::FilteredBlueprint;
-use crate::gadget::ActiveGadgets;
+use crate::gadget::{
+    binaries_to_remove, ActiveGadgets, CrashHistory, ExitReport, RestartPolicy,
+    CRASHES_BEFORE_FAILURE,
+};
+use crate::sdk::shutdown::CancellationToken;
 use crate::sdk::utils::bounded_string_to_string;
-use crate::sources::github::GithubBinaryFetcher;
+use crate::sources::cache::BinaryCache;
+use crate::sources::github::{GithubBinaryFetcher, GITHUB_API_URL};
+use crate::sources::ipfs::IpfsBinaryFetcher;
 use crate::sources::BinarySourceFetcher;
 use color_eyre::eyre::OptionExt;
+use futures::StreamExt;
 use gadget_io::GadgetConfig;
 use gadget_sdk::clients::tangle::runtime::{TangleConfig, TangleEvent};
 use gadget_sdk::clients::tangle::services::{RpcServicesWithBlueprint, ServicesClient};
 use gadget_sdk::config::Protocol;
 use gadget_sdk::{error, info, trace, warn};
 use std::fmt::Debug;
-use std::sync::atomic::Ordering;
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
 use tangle_subxt::subxt::utils::AccountId32;
 use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::{
     Gadget, GadgetSourceFetcher,
@@ -35,24 +43,38 @@ impl Debug for VerifiedBlueprint<'_> {
     }
 }
 
+/// Starts the services of every blueprint, up to `--max-concurrent-starts` blueprints at a time,
+/// so that a slow download or start does not hold up the others
 pub async fn handle_services<'a>(
     blueprints: &[VerifiedBlueprint<'a>],
     gadget_config: &GadgetConfig,
     blueprint_manager_opts: &BlueprintManagerConfig,
     active_gadgets: &mut ActiveGadgets,
 ) -> color_eyre::Result<()> {
-    for blueprint in blueprints {
-        if let Err(err) = crate::sources::handle(
-            blueprint,
-            gadget_config,
-            blueprint_manager_opts,
-            active_gadgets,
+    let shared_gadgets = Mutex::new(std::mem::take(active_gadgets));
+    futures::stream::iter(blueprints)
+        .for_each_concurrent(
+            blueprint_manager_opts.max_concurrent_starts.max(1),
+            |blueprint| {
+                let shared_gadgets = &shared_gadgets;
+                async move {
+                    if let Err(err) = crate::sources::handle(
+                        blueprint,
+                        gadget_config,
+                        blueprint_manager_opts,
+                        shared_gadgets,
+                    )
+                    .await
+                    {
+                        error!("{err}");
+                    }
+                }
+            },
         )
-        .await
-        {
-            error!("{err}");
-        }
-    }
+        .await;
+    *active_gadgets = shared_gadgets
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
 
     Ok(())
 }
@@ -169,8 +191,10 @@ pub(crate) async fn handle_tangle_event(
     gadget_config: &GadgetConfig,
     gadget_manager_opts: &BlueprintManagerConfig,
     active_gadgets: &mut ActiveGadgets,
+    crash_history: &mut CrashHistory,
     poll_result: EventPollResult,
     client: &ServicesClient<TangleConfig>,
+    cancel: &CancellationToken,
 ) -> color_eyre::Result<()> {
     info!("Received notification {}", event.number);
     const DEFAULT_PROTOCOL: Protocol = Protocol::Tangle;
@@ -224,6 +248,33 @@ pub(crate) async fn handle_tangle_event(
                             fetcher: gh.clone(),
                             blueprint_id: blueprint.blueprint_id,
                             gadget_name: blueprint.name.clone(),
+                            download_url_template: gadget_manager_opts
+                                .download_url_template
+                                .clone(),
+                            use_github_api: gadget_manager_opts.github_api,
+                            github_token: gadget_manager_opts.github_token.clone(),
+                            api_url: GITHUB_API_URL.to_string(),
+                            signing_key: gadget_manager_opts.signing_key(blueprint.blueprint_id),
+                            data_dir: gadget_manager_opts.data_dir()?,
+                            max_in_memory_size: gadget_manager_opts.max_in_memory_binary_size,
+                            retry: gadget_manager_opts.download_retry(),
+                            cancel: cancel.clone(),
+                            extracted_digest: Mutex::default(),
+                        };
+
+                        fetcher_candidates.push(Box::new(fetcher));
+                    }
+
+                    GadgetSourceFetcher::IPFS(cid) => {
+                        let fetcher = IpfsBinaryFetcher {
+                            cid: cid.0.clone(),
+                            blueprint_id: blueprint.blueprint_id,
+                            gadget_name: blueprint.name.clone(),
+                            gateway: gadget_manager_opts.ipfs_gateway.clone(),
+                            data_dir: gadget_manager_opts.data_dir()?,
+                            max_in_memory_size: gadget_manager_opts.max_in_memory_binary_size,
+                            retry: gadget_manager_opts.download_retry(),
+                            cancel: cancel.clone(),
                         };
 
                         fetcher_candidates.push(Box::new(fetcher));
@@ -315,6 +366,7 @@ pub(crate) async fn handle_tangle_event(
 
     // Check to see if local is running services that are not on-chain
     let mut to_remove: Vec<(u64, u64)> = vec![];
+    let mut stopped_on_chain: Vec<(u64, u64)> = vec![];
 
     // Loop through every (blueprint_id, service_id) running. See if the service is still on-chain. If not, kill it and add it to to_remove
     for (blueprint_id, process_handles) in &mut *active_gadgets {
@@ -331,7 +383,9 @@ pub(crate) async fn handle_tangle_event(
                 let fetcher = &verified_blueprints.fetcher;
                 if fetcher.blueprint_id() == *blueprint_id && !services.contains(service_id) {
                     warn!("Killing service that is no longer on-chain: bid={blueprint_id}//sid={service_id}");
+                    crash_history.forget(*blueprint_id, *service_id);
                     to_remove.push((*blueprint_id, *service_id));
+                    stopped_on_chain.push((*blueprint_id, *service_id));
                 }
             }
         }
@@ -340,26 +394,92 @@ pub(crate) async fn handle_tangle_event(
     // Check to see if any process handles have died
     for (blueprint_id, process_handles) in &mut *active_gadgets {
         for (service_id, process_handle) in process_handles {
-            if !to_remove.contains(&(*blueprint_id, *service_id))
-                && !process_handle.0.load(Ordering::Relaxed)
-            {
+            if !to_remove.contains(&(*blueprint_id, *service_id)) && !process_handle.is_running() {
+                // A dead process is kept around too, until the service is restarted on-chain
+                if crash_history.is_dead(*blueprint_id, *service_id) {
+                    continue;
+                }
+
+                let now = Instant::now();
+                // The restart policy is only asked once per exit, so a restart that is throttled
+                // does not count as another attempt
+                if !crash_history.is_restart_scheduled(*blueprint_id, *service_id) {
+                    let service_str = process_handle.metadata().service_str();
+                    let exit_report = process_handle.exit_report();
+                    let succeeded = exit_report.as_ref().is_some_and(ExitReport::success);
+                    let exit = exit_report.map_or_else(
+                        || "unknown exit status".to_string(),
+                        |report| report.to_string(),
+                    );
+                    if !crash_history.policy_allows_restart(*blueprint_id, *service_id, succeeded) {
+                        error!(
+                            "Service {service_str} exited ({exit}), giving up on it as per the `{}` restart policy",
+                            gadget_manager_opts.restart_policy
+                        );
+                        continue;
+                    }
+
+                    if crash_history.record_exit(*blueprint_id, *service_id, now) {
+                        error!(
+                            "Service {service_str} has failed, crashing {CRASHES_BEFORE_FAILURE} times within {}s",
+                            gadget_manager_opts.failure_window_secs
+                        );
+                    }
+                    let delay = crash_history.schedule_restart(*blueprint_id, *service_id, now);
+                    if let RestartPolicy::OnFailure { max_restarts } =
+                        gadget_manager_opts.restart_policy
+                    {
+                        info!(
+                            "Restarting service {service_str} ({exit}) in {delay:?}, attempt {}/{max_restarts}",
+                            crash_history.restart_count(*blueprint_id, *service_id)
+                        );
+                    } else {
+                        info!(
+                            "Restarting service {service_str} ({exit}) in {delay:?}, as per the `{}` restart policy",
+                            gadget_manager_opts.restart_policy
+                        );
+                    }
+                }
+
+                if !crash_history.is_restart_due(*blueprint_id, *service_id, now) {
+                    continue;
+                }
+                // A throttled process is kept around, so it is neither restarted nor re-downloaded.
+                // Only a restart that actually happens takes a token
+                let was_throttled = crash_history.is_throttled(*blueprint_id, *service_id, now);
+                if !crash_history.try_restart(*blueprint_id, *service_id, now) {
+                    if !was_throttled {
+                        error!(
+                            "Service {} is restarting too often, leaving it down for {}s",
+                            process_handle.metadata().service_str(),
+                            gadget_manager_opts.restart_cooldown_secs
+                        );
+                    }
+                    continue;
+                }
+
                 // By removing any killed processes, we will auto-restart them on the next finality notification if required
                 warn!("Killing service that has died to allow for auto-restart");
+                crash_history.record_restart(*blueprint_id, *service_id, now);
                 to_remove.push((*blueprint_id, *service_id));
             }
         }
     }
 
+    let mut stopped_binaries = vec![];
     for (blueprint_id, service_id) in to_remove {
         warn!("Removing service that is no longer active on-chain or killed: bid={blueprint_id}//sid={service_id}");
         let mut should_delete_blueprint = false;
         if let Some(gadgets) = active_gadgets.get_mut(&blueprint_id) {
-            if let Some((_, mut process_handle)) = gadgets.remove(&service_id) {
-                if let Some(abort_handle) = process_handle.take() {
-                    if abort_handle.send(()).is_err() {
-                        error!("Failed to send abort signal to service: bid={blueprint_id}//sid={service_id}");
-                    } else {
+            if let Some(mut process_handle) = gadgets.remove(&service_id) {
+                if stopped_on_chain.contains(&(blueprint_id, service_id)) {
+                    stopped_binaries.push(process_handle.metadata().binary_path.clone());
+                }
+                if process_handle.abort_handle.is_some() {
+                    if process_handle.abort() {
                         warn!("Sent abort signal to service: bid={blueprint_id}//sid={service_id}");
+                    } else {
+                        error!("Failed to send abort signal to service: bid={blueprint_id}//sid={service_id}");
                     }
                 }
             }
@@ -374,5 +494,136 @@ pub(crate) async fn handle_tangle_event(
         }
     }
 
+    let cache = BinaryCache::new(gadget_manager_opts.data_dir()?.binary_cache());
+    for binary in binaries_to_remove(
+        stopped_binaries,
+        active_gadgets,
+        gadget_manager_opts.keep_binaries_on_stop,
+    ) {
+        info!(
+            "Removing the binary of a stopped service: {}",
+            binary.display()
+        );
+        if let Err(err) = cache.remove_linked(&binary).await {
+            warn!("Failed to remove binary {}: {err}", binary.display());
+        }
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use crate::sources::tests::ScriptGadget;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::bounded_collections::bounded_vec::BoundedVec;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::NativeGadget;
+    use tokio::sync::Notify;
+
+    /// Fails to fetch its binary, but only once the fast fetcher was asked for its own, or after
+    /// a few seconds
+    struct SlowFetcher {
+        fast_started: Arc<Notify>,
+    }
+
+    #[async_trait::async_trait]
+    impl BinarySourceFetcher for SlowFetcher {
+        async fn get_binary(&self) -> color_eyre::Result<PathBuf> {
+            tokio::time::timeout(Duration::from_secs(5), self.fast_started.notified())
+                .await
+                .map_err(|_| color_eyre::Report::msg("the fast blueprint was held up"))?;
+            Err(color_eyre::Report::msg("the slow binary is not available"))
+        }
+
+        fn blueprint_id(&self) -> u64 {
+            1
+        }
+
+        fn name(&self) -> String {
+            "slow".into()
+        }
+
+        fn source_description(&self) -> String {
+            "test:slow".into()
+        }
+    }
+
+    struct FastFetcher {
+        binary: PathBuf,
+        started: Arc<Notify>,
+    }
+
+    #[async_trait::async_trait]
+    impl BinarySourceFetcher for FastFetcher {
+        async fn get_binary(&self) -> color_eyre::Result<PathBuf> {
+            self.started.notify_one();
+            Ok(self.binary.clone())
+        }
+
+        fn blueprint_id(&self) -> u64 {
+            2
+        }
+
+        fn name(&self) -> String {
+            "fast".into()
+        }
+
+        fn source_description(&self) -> String {
+            "test:fast".into()
+        }
+    }
+
+    fn verified(fetcher: Box<dyn BinarySourceFetcher>) -> VerifiedBlueprint<'static> {
+        VerifiedBlueprint {
+            blueprint: FilteredBlueprint {
+                blueprint_id: fetcher.blueprint_id(),
+                services: vec![0],
+                gadget: Gadget::Native(NativeGadget {
+                    sources: BoundedVec(Vec::new()),
+                }),
+                name: fetcher.name(),
+                registration_mode: false,
+                protocol: Protocol::Tangle,
+            },
+            fetcher,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_slow_start_does_not_delay_other_blueprints() {
+        let gadget = ScriptGadget::new();
+        let _ = gadget.write("exit 0\n");
+
+        let fast_started = Arc::new(Notify::new());
+        let blueprints = [
+            verified(Box::new(SlowFetcher {
+                fast_started: fast_started.clone(),
+            })),
+            verified(Box::new(FastFetcher {
+                binary: gadget.binary.clone(),
+                started: fast_started,
+            })),
+        ];
+
+        let started_at = Instant::now();
+        let mut active_gadgets = ActiveGadgets::new();
+        handle_services(
+            &blueprints,
+            &gadget.gadget_config,
+            &gadget.opts,
+            &mut active_gadgets,
+        )
+        .await
+        .unwrap();
+
+        // The slow blueprint only gave up once the fast one was being started
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        assert!(active_gadgets[&2].contains_key(&0));
+        assert!(!active_gadgets.contains_key(&1));
+    }
+}
@@ -1,30 +1,36 @@
 use crate::config::BlueprintManagerConfig;
-use crate::gadget::ActiveGadgets;
+use crate::gadget::{ActiveGadgets, CrashHistory};
 use crate::sdk::entry::SendFuture;
+use crate::sdk::sandbox::Sandbox;
+use crate::sdk::shutdown::CancellationToken;
 use crate::sdk::utils;
 use crate::sdk::utils::msg_to_error;
 use color_eyre::eyre::OptionExt;
 use color_eyre::Report;
 use gadget_io::GadgetConfig;
-use gadget_sdk::clients::tangle::runtime::{TangleConfig, TangleRuntimeClient};
+use gadget_sdk::clients::tangle::runtime::{account_id_to_ss58, TangleConfig, TangleRuntimeClient};
 use gadget_sdk::clients::tangle::services::{RpcServicesWithBlueprint, ServicesClient};
 use gadget_sdk::clients::Client;
-use gadget_sdk::info;
 use gadget_sdk::keystore::backend::fs::FilesystemKeystore;
 use gadget_sdk::keystore::backend::GenericKeyStore;
-use gadget_sdk::keystore::{sp_core_subxt, BackendExt, TanglePairSigner};
+use gadget_sdk::keystore::{sp_core_subxt, BackendExt, KeyType, TanglePairSigner};
+use gadget_sdk::{info, warn};
+use itertools::Itertools;
 use sp_core::H256;
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tangle_subxt::subxt::blocks::BlockRef;
 use tangle_subxt::subxt::tx::Signer;
 use tangle_subxt::subxt::utils::AccountId32;
 use tangle_subxt::subxt::Config;
 use tokio::task::JoinHandle;
 
+pub mod cache_maintenance;
 pub(crate) mod event_handler;
+pub mod self_test;
 
 pub async fn get_blueprints<C: Config>(
     runtime: &ServicesClient<C>,
@@ -133,7 +139,7 @@ impl Future for BlueprintManagerHandle {
 
 pub async fn run_blueprint_manager<F: SendFuture<'static, ()>>(
     blueprint_manager_config: BlueprintManagerConfig,
-    gadget_config: GadgetConfig,
+    mut gadget_config: GadgetConfig,
     shutdown_cmd: F,
 ) -> color_eyre::Result<BlueprintManagerHandle> {
     let logger_id = if let Some(custom_id) = &blueprint_manager_config.instance_id {
@@ -147,24 +153,62 @@ pub async fn run_blueprint_manager<F: SendFuture<'static, ()>>(
     let _span = span.enter();
     info!("Starting blueprint manager ... waiting for start signal ...");
 
+    let data_dir = blueprint_manager_config.data_dir()?;
+    blueprint_manager_config.resolve_keystore_uri(&mut gadget_config)?;
+    info!("Keeping state under {}", data_dir.root().display());
+    blueprint_manager_config.import_keys(&gadget_config)?;
+
     let (tangle_key, ecdsa_key) = {
         let keystore = GenericKeyStore::<parking_lot::RawRwLock>::Fs(FilesystemKeystore::open(
             &gadget_config.keystore_uri,
         )?);
-        let sr_key = keystore.sr25519_key()?;
+        let missing = keystore.missing_key_types(&[KeyType::Sr25519, KeyType::Ecdsa]);
+        if !missing.is_empty() {
+            return Err(Report::msg(format!(
+                "Missing keys in keystore {}: {}. Insert them before starting the blueprint manager",
+                gadget_config.keystore_uri,
+                missing.iter().map(ToString::to_string).join(", ")
+            )));
+        }
+        let sr_key = keystore
+            .sr25519_key()?
+            .with_ss58_prefix(gadget_config.chain.ss58_prefix());
         let ecdsa_key = keystore.ecdsa_key()?;
         (sr_key, ecdsa_key)
     };
 
     let sub_account_id = tangle_key.account_id().clone();
+    info!(
+        "Loaded operator account {tangle_key} for chain {}",
+        gadget_config.chain
+    );
+
+    // Fail fast on an invalid sandbox configuration, rather than when spawning the first gadget
+    if !Sandbox::from_config(&blueprint_manager_config)?.is_disabled() {
+        info!("Gadget binaries will be spawned in a sandbox");
+    }
 
     let tangle_client =
         TangleRuntimeClient::from_url(gadget_config.url.as_str(), sub_account_id.clone()).await?;
     let services_client = ServicesClient::new(tangle_client.client());
     let mut active_gadgets = HashMap::new();
+    let mut crash_history = CrashHistory::new(Duration::from_secs(
+        blueprint_manager_config.failure_window_secs,
+    ))
+    .with_restart_policy(blueprint_manager_config.restart_policy);
+    if let Some(limit) = blueprint_manager_config.restart_limit() {
+        crash_history = crash_history.with_restart_limit(limit);
+    }
+    if let Some(backoff) = blueprint_manager_config.restart_backoff() {
+        crash_history = crash_history.with_restart_backoff(backoff);
+    }
 
     let keystore_uri = gadget_config.keystore_uri.clone();
 
+    // Cancelled on shutdown, aborting any in-flight binary downloads
+    let cancel = CancellationToken::new();
+    let manager_cancel = cancel.clone();
+
     let manager_task = async move {
         // With the basics setup, we must now implement the main logic of the Blueprint Manager
         // Handle initialization logic
@@ -175,8 +219,10 @@ pub async fn run_blueprint_manager<F: SendFuture<'static, ()>>(
             &services_client,
             &sub_account_id,
             &mut active_gadgets,
+            &mut crash_history,
             &gadget_config,
             &blueprint_manager_config,
+            &manager_cancel,
         )
         .await?;
 
@@ -203,8 +249,10 @@ pub async fn run_blueprint_manager<F: SendFuture<'static, ()>>(
                 &gadget_config,
                 &blueprint_manager_config,
                 &mut active_gadgets,
+                &mut crash_history,
                 result,
                 &services_client,
+                &manager_cancel,
             )
             .await?;
         }
@@ -224,6 +272,7 @@ pub async fn run_blueprint_manager<F: SendFuture<'static, ()>>(
                 info!("Manual shutdown signal received, closing application");
             }
         }
+        cancel.cancel();
     };
 
     let (start_tx, start_rx) = tokio::sync::oneshot::channel::<()>();
@@ -264,13 +313,16 @@ pub async fn run_blueprint_manager<F: SendFuture<'static, ()>>(
 /// * For each RpcServicesWithBlueprint, fetch the associated gadget binary (fetch/download)
 ///   -> If the services field is empty, just emit and log inside the executed binary "that states a new service instance got created by one of these blueprints"
 ///   -> If the services field is not empty, for each service in RpcServicesWithBlueprint.services, spawn the gadget binary, using params to set the job type to listen to (in terms of our old language, each spawned service represents a single "RoleType")
+#[allow(clippy::too_many_arguments)]
 async fn handle_init(
     tangle_runtime: &TangleRuntimeClient,
     services_client: &ServicesClient<TangleConfig>,
     sub_account_id: &AccountId32,
     active_gadgets: &mut ActiveGadgets,
+    crash_history: &mut CrashHistory,
     gadget_config: &GadgetConfig,
     blueprint_manager_config: &BlueprintManagerConfig,
+    cancel: &CancellationToken,
 ) -> color_eyre::Result<Vec<RpcServicesWithBlueprint>> {
     info!("Beginning initialization of Blueprint Manager");
 
@@ -290,6 +342,11 @@ async fn handle_init(
         "Received {} initial blueprints this operator is registered to",
         operator_subscribed_blueprints.len()
     );
+    validate_operator_registrations(
+        &operator_subscribed_blueprints,
+        sub_account_id,
+        gadget_config,
+    );
 
     // Immediately poll, handling the initial state
     let poll_result =
@@ -301,10 +358,39 @@ async fn handle_init(
         gadget_config,
         blueprint_manager_config,
         active_gadgets,
+        crash_history,
         poll_result,
         services_client,
+        cancel,
     )
     .await?;
 
     Ok(operator_subscribed_blueprints)
 }
+
+/// Warns about common registration mistakes that cause an operator to never be selected for jobs,
+/// e.g. keys being present in the keystore while the account was never registered on-chain.
+fn validate_operator_registrations(
+    blueprints: &[RpcServicesWithBlueprint],
+    sub_account_id: &AccountId32,
+    gadget_config: &GadgetConfig,
+) {
+    let address = account_id_to_ss58(sub_account_id, gadget_config.chain.ss58_prefix());
+    if blueprints.is_empty() {
+        warn!(
+            "Account {address} is not registered as an operator for any blueprint on {}. \
+            No services will be started until it registers",
+            gadget_config.chain
+        );
+        return;
+    }
+
+    for blueprint in blueprints {
+        if blueprint.services.is_empty() {
+            warn!(
+                "Account {address} is registered for blueprint {} but no service instance has selected it yet",
+                blueprint.blueprint_id
+            );
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use color_eyre::Report;
+use gadget_io::GadgetConfig;
+use gadget_sdk::clients::tangle::runtime::{
+    account_id_to_ss58, connect, DEFAULT_CONNECTION_TIMEOUT,
+};
+use gadget_sdk::info;
+use gadget_sdk::keystore::backend::fs::FilesystemKeystore;
+use gadget_sdk::keystore::backend::GenericKeyStore;
+use gadget_sdk::keystore::BackendExt;
+use gadget_sdk::tx::tangle::{validate_with_options, SendOptions};
+use tangle_subxt::subxt::tx::{Signer, ValidationResult};
+use tangle_subxt::tangle_testnet_runtime::api;
+
+/// Verifies that this operator is able to submit extrinsics to the Tangle network.
+///
+/// The check walks the same path a real job result takes: it loads the sr25519 key from the
+/// keystore, connects to the node, checks the account has funds, then signs a `system.remark`
+/// through the same transaction path as job results and asks the node to validate it. The
+/// extrinsic is never submitted, so this costs nothing.
+///
+/// # Errors
+///
+/// Returns an error describing the first step that failed, along with a hint on how to fix it.
+pub async fn run_self_test(gadget_config: &GadgetConfig) -> color_eyre::Result<()> {
+    info!("Running self-test against {}", gadget_config.url);
+
+    let keystore = FilesystemKeystore::open(&gadget_config.keystore_uri).map_err(|err| {
+        Report::msg(format!(
+            "Self-test failed: unable to open keystore at {}: {err}. Check the keystore URI",
+            gadget_config.keystore_uri
+        ))
+    })?;
+    let signer = GenericKeyStore::<parking_lot::RawRwLock>::Fs(keystore)
+        .sr25519_key()
+        .map_err(|err| {
+            Report::msg(format!(
+                "Self-test failed: no usable sr25519 key in the keystore: {err}. Insert the operator key first"
+            ))
+        })?;
+    let account_id = signer.account_id();
+    let address = account_id_to_ss58(&account_id, gadget_config.chain.ss58_prefix());
+    info!("[1/4] Loaded operator key {address}");
+
+    let client = connect(gadget_config.url.as_str(), DEFAULT_CONNECTION_TIMEOUT)
+        .await
+        .map_err(|err| {
+            Report::msg(format!(
+                "Self-test failed: unable to reach node at {}: {err}. Check the RPC URL and that the node is running",
+                gadget_config.url
+            ))
+        })?;
+    info!("[2/4] Connected to node");
+
+    let account = client
+        .storage()
+        .at_latest()
+        .await?
+        .fetch(&api::storage().system().account(account_id.clone()))
+        .await?;
+    let free = check_funds(&address, account.map(|info| info.data.free))?;
+    info!("[3/4] Account {address} has a free balance of {free}");
+
+    let remark = api::tx().system().remark(Vec::new());
+    let validation = validate_with_options(&client, &signer, &remark, &SendOptions::default())
+        .await
+        .map_err(|err| {
+            Report::msg(format!(
+                "Self-test failed: unable to have the node validate a signed extrinsic: {err}"
+            ))
+        })?;
+    check_validation(validation)?;
+    info!("[4/4] Node accepted a signed dry-run extrinsic");
+
+    info!("Self-test passed");
+    Ok(())
+}
+
+/// The free balance of the operator account at `address`, if it has any funds
+fn check_funds(address: &str, free: Option<u128>) -> color_eyre::Result<u128> {
+    match free {
+        Some(free) if free > 0 => Ok(free),
+        _ => Err(Report::msg(format!(
+            "Self-test failed: account {address} has no funds. Transfer some tokens to it before running jobs"
+        ))),
+    }
+}
+
+/// Whether the node considered the dry-run extrinsic valid
+fn check_validation(validation: ValidationResult) -> color_eyre::Result<()> {
+    match validation {
+        ValidationResult::Valid(_) => Ok(()),
+        ValidationResult::Invalid(err) => Err(Report::msg(format!(
+            "Self-test failed: node rejected the extrinsic as invalid: {err:?}"
+        ))),
+        ValidationResult::Unknown(err) => Err(Report::msg(format!(
+            "Self-test failed: node could not validate the extrinsic: {err:?}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tangle_subxt::subxt::tx::{TransactionInvalid, TransactionUnknown};
+
+    #[test]
+    fn test_only_funded_accounts_pass() {
+        assert_eq!(check_funds("5Operator", Some(10)).unwrap(), 10);
+        for free in [None, Some(0)] {
+            let err = check_funds("5Operator", free).unwrap_err().to_string();
+            assert!(err.contains("account 5Operator has no funds"), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_rejected_extrinsics_explain_why() {
+        let err = check_validation(ValidationResult::Invalid(TransactionInvalid::Payment))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("rejected the extrinsic as invalid: Payment"),
+            "{err}"
+        );
+
+        let err = check_validation(ValidationResult::Unknown(TransactionUnknown::CannotLookup))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("could not validate the extrinsic: CannotLookup"),
+            "{err}"
+        );
+    }
+}
@@ -1,7 +1,845 @@
-use std::collections::HashMap;
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
-pub type ActiveGadgets =
-    HashMap<u64, HashMap<u64, (Arc<AtomicBool>, Option<tokio::sync::oneshot::Sender<()>>)>>;
+pub type ActiveGadgets = HashMap<u64, HashMap<u64, ActiveGadget>>;
 pub mod native;
+
+/// A running gadget process for a single service instance
+pub struct ActiveGadget {
+    /// Set to `false` by the process watcher once the process exits
+    pub status: Arc<AtomicBool>,
+    /// Set by the process watcher once the process exits, with its exit code and stderr tail
+    pub exit_report: Arc<Mutex<Option<ExitReport>>>,
+    /// Sends the abort signal to the process watcher, killing the process
+    pub abort_handle: Option<tokio::sync::oneshot::Sender<()>>,
+    /// Where the running process came from
+    pub metadata: ActiveGadgetMetadata,
+}
+
+impl ActiveGadget {
+    /// Whether the underlying process is still running
+    pub fn is_running(&self) -> bool {
+        self.status.load(Ordering::Relaxed)
+    }
+
+    /// The blueprint and binary this gadget was started from
+    pub fn metadata(&self) -> &ActiveGadgetMetadata {
+        &self.metadata
+    }
+
+    /// Why the underlying process exited, if it did
+    pub fn exit_report(&self) -> Option<ExitReport> {
+        self.exit_report
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Whether the underlying process is still running or has exited
+    pub fn state(&self) -> ActiveGadgetState {
+        if self.is_running() {
+            ActiveGadgetState::Running
+        } else {
+            ActiveGadgetState::Exited
+        }
+    }
+
+    /// Sends the abort signal to the process, returning `false` if it could not be delivered
+    pub fn abort(&mut self) -> bool {
+        match self.abort_handle.take() {
+            Some(abort_handle) => abort_handle.send(()).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// The observed state of an active gadget process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ActiveGadgetState {
+    Running,
+    /// The process exited or was killed, and has not been restarted yet
+    Exited,
+    /// The process keeps crashing, see [`CrashHistory`]
+    Failed,
+    /// The process exited and is not restarted anymore, as per the [`RestartPolicy`]
+    Dead,
+}
+
+/// The number of trailing stderr lines of a process kept in its [`ExitReport`]
+pub const STDERR_TAIL_LINES: usize = 20;
+
+/// Why a gadget process exited
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitReport {
+    /// The exit code of the process, or `None` if it was terminated by a signal
+    pub code: Option<i32>,
+    /// The last [`STDERR_TAIL_LINES`] lines the process wrote to stderr, if it was piped
+    pub stderr_tail: Vec<String>,
+}
+
+impl ExitReport {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl fmt::Display for ExitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "exit code {code}")?,
+            None => write!(f, "terminated by a signal")?,
+        }
+        if !self.stderr_tail.is_empty() {
+            write!(f, ", last stderr lines:\n{}", self.stderr_tail.join("\n"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists every active service as `(service_str, state, exit_report)`, sorted by service string.
+///
+/// The service string is the same `{blueprint_name}-{service_id}` used in the manager's logs.
+pub fn active_services(
+    active_gadgets: &ActiveGadgets,
+    crash_history: &CrashHistory,
+) -> Vec<(String, ActiveGadgetState, Option<ExitReport>)> {
+    let now = Instant::now();
+    let mut services = active_gadgets
+        .values()
+        .flat_map(HashMap::values)
+        .map(|gadget| {
+            let metadata = &gadget.metadata;
+            let state = if crash_history.is_dead(metadata.blueprint_id, metadata.service_id) {
+                ActiveGadgetState::Dead
+            } else if crash_history.is_failed(metadata.blueprint_id, metadata.service_id, now) {
+                ActiveGadgetState::Failed
+            } else {
+                gadget.state()
+            };
+            (metadata.service_str(), state, gadget.exit_report())
+        })
+        .collect::<Vec<_>>();
+    services.sort();
+    services
+}
+
+/// The number of crashes within the failure window after which a service is considered failed
+pub const CRASHES_BEFORE_FAILURE: usize = 3;
+
+/// Remembers when each service recently exited, across restarts.
+///
+/// A service that exits early, e.g. while the node RPC is not reachable yet, is restarted like
+/// any other. It is only reported as failed once it crashed [`CRASHES_BEFORE_FAILURE`] times
+/// within the failure window, so the health status doesn't flap during normal startup races.
+///
+/// With a [`RestartLimit`], the restarts of each service are also throttled, so a crash-looping
+/// service cannot keep the node busy re-spawning it. Whether a service is restarted at all is up
+/// to its [`RestartPolicy`], and with a [`RestartBackoff`] a service that keeps exiting is
+/// restarted later and later.
+#[derive(Debug)]
+pub struct CrashHistory {
+    window: Duration,
+    exits: HashMap<(u64, u64), VecDeque<Instant>>,
+    restart_limit: Option<RestartLimit>,
+    restarts: HashMap<(u64, u64), RestartBucket>,
+    restart_policy: RestartPolicy,
+    restart_counts: HashMap<(u64, u64), u32>,
+    dead: HashSet<(u64, u64)>,
+    scheduled_restarts: HashMap<(u64, u64), Instant>,
+    restart_backoff: Option<RestartBackoff>,
+    backoff_attempts: HashMap<(u64, u64), u32>,
+    restarted_at: HashMap<(u64, u64), Instant>,
+}
+
+impl CrashHistory {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            exits: HashMap::new(),
+            restart_limit: None,
+            restarts: HashMap::new(),
+            restart_policy: RestartPolicy::default(),
+            restart_counts: HashMap::new(),
+            dead: HashSet::new(),
+            scheduled_restarts: HashMap::new(),
+            restart_backoff: None,
+            backoff_attempts: HashMap::new(),
+            restarted_at: HashMap::new(),
+        }
+    }
+
+    /// Decides whether exited services are restarted according to `policy`
+    pub fn with_restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.restart_policy = policy;
+        self
+    }
+
+    /// Throttles the restarts of each service according to `limit`
+    pub fn with_restart_limit(mut self, limit: RestartLimit) -> Self {
+        self.restart_limit = Some(limit);
+        self
+    }
+
+    /// Delays the restarts of each service according to `backoff`
+    pub fn with_restart_backoff(mut self, backoff: RestartBackoff) -> Self {
+        self.restart_backoff = Some(backoff);
+        self
+    }
+
+    /// Takes a restart token for the given service, returning whether it may be restarted at
+    /// `now`. Always `true` without a [`RestartLimit`]
+    pub fn try_restart(&mut self, blueprint_id: u64, service_id: u64, now: Instant) -> bool {
+        let Some(limit) = self.restart_limit else {
+            return true;
+        };
+        self.restarts
+            .entry((blueprint_id, service_id))
+            .or_insert_with(|| RestartBucket::full(&limit, now))
+            .try_take(&limit, now)
+    }
+
+    /// Whether the restart policy allows restarting the given service, whose process exited
+    /// successfully or not. Once it does not, the service is dead until it is forgotten
+    pub fn policy_allows_restart(
+        &mut self,
+        blueprint_id: u64,
+        service_id: u64,
+        succeeded: bool,
+    ) -> bool {
+        let key = (blueprint_id, service_id);
+        let allowed = match self.restart_policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure { max_restarts } => {
+                let restarts = self.restart_counts.entry(key).or_default();
+                let allowed = !succeeded && *restarts < max_restarts;
+                if allowed {
+                    *restarts += 1;
+                }
+                allowed
+            }
+            RestartPolicy::Always => true,
+        };
+        if !allowed {
+            let _ = self.dead.insert(key);
+        }
+        allowed
+    }
+
+    /// Schedules the restart of the given service, once the restart policy allowed it at `now`,
+    /// returning the delay before it is due. Without a [`RestartBackoff`], it is due right away
+    pub fn schedule_restart(
+        &mut self,
+        blueprint_id: u64,
+        service_id: u64,
+        now: Instant,
+    ) -> Duration {
+        let key = (blueprint_id, service_id);
+        let delay = match self.restart_backoff {
+            Some(backoff) => {
+                // A service that stayed up for the whole window since its last restart starts over
+                let stable = self
+                    .restarted_at
+                    .get(&key)
+                    .is_some_and(|at| now.saturating_duration_since(*at) >= self.window);
+                let attempts = self.backoff_attempts.entry(key).or_default();
+                if stable {
+                    *attempts = 0;
+                }
+                let delay = backoff.delay(*attempts);
+                *attempts = attempts.saturating_add(1);
+                delay
+            }
+            None => Duration::ZERO,
+        };
+        let _ = self.scheduled_restarts.insert(key, now + delay);
+        delay
+    }
+
+    /// Whether the restart of the given service was already scheduled
+    pub fn is_restart_scheduled(&self, blueprint_id: u64, service_id: u64) -> bool {
+        self.scheduled_restarts
+            .contains_key(&(blueprint_id, service_id))
+    }
+
+    /// Whether the scheduled restart of the given service is due at `now`
+    pub fn is_restart_due(&self, blueprint_id: u64, service_id: u64, now: Instant) -> bool {
+        self.scheduled_restarts
+            .get(&(blueprint_id, service_id))
+            .is_some_and(|due| now >= *due)
+    }
+
+    /// Records that the scheduled restart of the given service happened at `now`
+    pub fn record_restart(&mut self, blueprint_id: u64, service_id: u64, now: Instant) {
+        let _ = self.scheduled_restarts.remove(&(blueprint_id, service_id));
+        let _ = self.restarted_at.insert((blueprint_id, service_id), now);
+    }
+
+    /// Whether the given service is not restarted anymore, as per the restart policy
+    pub fn is_dead(&self, blueprint_id: u64, service_id: u64) -> bool {
+        self.dead.contains(&(blueprint_id, service_id))
+    }
+
+    /// The number of times the given service was restarted under an on-failure restart policy
+    pub fn restart_count(&self, blueprint_id: u64, service_id: u64) -> u32 {
+        self.restart_counts
+            .get(&(blueprint_id, service_id))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Whether the given service exhausted its restarts, and is left down until its cooldown ends
+    pub fn is_throttled(&self, blueprint_id: u64, service_id: u64, now: Instant) -> bool {
+        self.restarts
+            .get(&(blueprint_id, service_id))
+            .is_some_and(|bucket| bucket.is_cooling_down(now))
+    }
+
+    /// Records that the process of the given service exited at `now`, returning whether the
+    /// service is now considered failed
+    pub fn record_exit(&mut self, blueprint_id: u64, service_id: u64, now: Instant) -> bool {
+        let window = self.window;
+        let exits = self.exits.entry((blueprint_id, service_id)).or_default();
+        exits.push_back(now);
+        while exits
+            .front()
+            .is_some_and(|exit| now.duration_since(*exit) > window)
+        {
+            let _ = exits.pop_front();
+        }
+        exits.len() >= CRASHES_BEFORE_FAILURE
+    }
+
+    /// Whether the given service crashed [`CRASHES_BEFORE_FAILURE`] times within the window
+    /// before `now`, or is throttled
+    pub fn is_failed(&self, blueprint_id: u64, service_id: u64, now: Instant) -> bool {
+        if self.is_throttled(blueprint_id, service_id, now) {
+            return true;
+        }
+        self.exits
+            .get(&(blueprint_id, service_id))
+            .is_some_and(|exits| {
+                exits
+                    .iter()
+                    .filter(|exit| now.duration_since(**exit) <= self.window)
+                    .count()
+                    >= CRASHES_BEFORE_FAILURE
+            })
+    }
+
+    /// Forgets the crashes of a service that is no longer running, e.g. because it was
+    /// terminated on-chain
+    pub fn forget(&mut self, blueprint_id: u64, service_id: u64) {
+        let _ = self.exits.remove(&(blueprint_id, service_id));
+        let _ = self.restarts.remove(&(blueprint_id, service_id));
+        let _ = self.restart_counts.remove(&(blueprint_id, service_id));
+        let _ = self.dead.remove(&(blueprint_id, service_id));
+        let _ = self.scheduled_restarts.remove(&(blueprint_id, service_id));
+        let _ = self.backoff_attempts.remove(&(blueprint_id, service_id));
+        let _ = self.restarted_at.remove(&(blueprint_id, service_id));
+    }
+}
+
+/// Whether a service is restarted once its process exits. The restarts are still throttled by the
+/// [`RestartLimit`], if any
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// The service is left dead
+    Never,
+    /// The service is restarted after exiting with an error, up to `max_restarts` times
+    OnFailure { max_restarts: u32 },
+    /// The service is always restarted
+    #[default]
+    Always,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid restart policy `{s}`, expected never, on-failure:<max restarts> or always"
+            )
+        };
+        match s.split_once(':') {
+            None if s == "never" => Ok(Self::Never),
+            None if s == "always" => Ok(Self::Always),
+            Some(("on-failure", max_restarts)) => Ok(Self::OnFailure {
+                max_restarts: max_restarts.parse().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Never => write!(f, "never"),
+            Self::OnFailure { max_restarts } => write!(f, "on-failure:{max_restarts}"),
+            Self::Always => write!(f, "always"),
+        }
+    }
+}
+
+/// The delay before restarting a single service, doubled after every restart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartBackoff {
+    /// The delay before the first restart
+    pub base: Duration,
+    /// The longest delay before a restart
+    pub max: Duration,
+}
+
+impl RestartBackoff {
+    /// The delay before restarting a service that was already restarted `attempts` times in a row
+    pub fn delay(&self, attempts: u32) -> Duration {
+        self.base
+            .saturating_mul(2u32.saturating_pow(attempts))
+            .min(self.max)
+    }
+}
+
+/// A token bucket limiting how often a single service is restarted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartLimit {
+    /// The number of restarts allowed in a row
+    pub burst: u32,
+    /// The time after which a restart token is given back
+    pub refill_interval: Duration,
+    /// How long a service is left down once it used up all its restarts
+    pub cooldown: Duration,
+}
+
+/// The restart tokens left to a single service
+#[derive(Debug)]
+struct RestartBucket {
+    tokens: u32,
+    refilled_at: Instant,
+    cooldown_until: Option<Instant>,
+}
+
+impl RestartBucket {
+    fn full(limit: &RestartLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            refilled_at: now,
+            cooldown_until: None,
+        }
+    }
+
+    fn is_cooling_down(&self, now: Instant) -> bool {
+        self.cooldown_until.is_some_and(|until| now < until)
+    }
+
+    fn try_take(&mut self, limit: &RestartLimit, now: Instant) -> bool {
+        if self.is_cooling_down(now) {
+            return false;
+        }
+        if self.cooldown_until.take().is_some() {
+            *self = Self::full(limit, now);
+        }
+
+        self.refill(limit, now);
+        if self.tokens == 0 {
+            self.cooldown_until = Some(now + limit.cooldown);
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+
+    /// Gives back one token per elapsed refill interval, up to the burst
+    fn refill(&mut self, limit: &RestartLimit, now: Instant) {
+        let interval = limit.refill_interval.as_nanos().max(1);
+        let refills = now.saturating_duration_since(self.refilled_at).as_nanos() / interval;
+        let missing = u128::from(limit.burst.saturating_sub(self.tokens));
+        if refills >= missing {
+            self.tokens = limit.burst;
+            self.refilled_at = now;
+        } else {
+            // Less than `burst` refills, so the conversions cannot truncate
+            self.tokens += refills as u32;
+            self.refilled_at += limit.refill_interval * refills as u32;
+        }
+    }
+}
+
+/// The binaries of `stopped` services to delete, i.e. none if they are kept, or only those no
+/// service in `active_gadgets` still runs otherwise
+pub fn binaries_to_remove(
+    stopped: Vec<PathBuf>,
+    active_gadgets: &ActiveGadgets,
+    keep_binaries_on_stop: bool,
+) -> Vec<PathBuf> {
+    if keep_binaries_on_stop {
+        return Vec::new();
+    }
+
+    let mut binaries = stopped
+        .into_iter()
+        .filter(|binary| {
+            !active_gadgets
+                .values()
+                .flat_map(HashMap::values)
+                .any(|gadget| gadget.metadata.binary_path == *binary)
+        })
+        .collect::<Vec<_>>();
+    binaries.sort();
+    binaries.dedup();
+    binaries
+}
+
+/// Describes the on-chain blueprint and local binary a gadget process was started from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveGadgetMetadata {
+    pub blueprint_id: u64,
+    pub service_id: u64,
+    pub blueprint_name: String,
+    /// A human-readable description of the binary source, e.g. `github:owner/repo@tag`
+    pub source: String,
+    pub binary_path: PathBuf,
+    pub arguments: Vec<String>,
+}
+
+impl ActiveGadgetMetadata {
+    /// The name of this service, as used in the manager's logs
+    pub fn service_str(&self) -> String {
+        format!("{}-{}", self.blueprint_name, self.service_id)
+    }
+
+    /// Whether a gadget started from this metadata no longer matches the desired on-chain
+    /// `source` and process `arguments`, and should therefore be restarted
+    pub fn is_outdated(&self, source: &str, arguments: &[String]) -> bool {
+        self.source != source || self.arguments != arguments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_gadget_metadata_round_trips() {
+        let metadata = ActiveGadgetMetadata {
+            blueprint_id: 1,
+            service_id: 2,
+            blueprint_name: "incredible-squaring".to_string(),
+            source: "github:webb-tools/gadget@0.1.0".to_string(),
+            binary_path: PathBuf::from("/tmp/protocol-0.1.0"),
+            arguments: vec!["run".to_string(), "--blueprint-id=1".to_string()],
+        };
+
+        let mut active_gadgets = ActiveGadgets::new();
+        let _ = active_gadgets.entry(1).or_default().insert(
+            2,
+            ActiveGadget {
+                status: Arc::new(AtomicBool::new(true)),
+                exit_report: Arc::default(),
+                abort_handle: None,
+                metadata: metadata.clone(),
+            },
+        );
+
+        let gadget = &active_gadgets[&1][&2];
+        assert!(gadget.is_running());
+        assert_eq!(gadget.metadata(), &metadata);
+    }
+
+    #[test]
+    fn test_only_changed_configuration_is_outdated() {
+        let metadata = ActiveGadgetMetadata {
+            blueprint_id: 1,
+            service_id: 2,
+            blueprint_name: "incredible-squaring".to_string(),
+            source: "github:webb-tools/gadget@0.1.0".to_string(),
+            binary_path: PathBuf::from("/tmp/protocol-0.1.0"),
+            arguments: vec!["run".to_string(), "--blueprint-id=1".to_string()],
+        };
+
+        assert!(!metadata.is_outdated(&metadata.source, &metadata.arguments));
+        assert!(metadata.is_outdated("github:webb-tools/gadget@0.2.0", &metadata.arguments));
+        assert!(metadata.is_outdated(
+            &metadata.source,
+            &["run".to_string(), "--blueprint-id=2".to_string()]
+        ));
+    }
+
+    #[test]
+    fn test_active_services_are_sorted_with_their_state() {
+        let gadget = |blueprint_name: &str, blueprint_id, service_id, running| ActiveGadget {
+            status: Arc::new(AtomicBool::new(running)),
+            exit_report: Arc::new(Mutex::new((!running).then(|| ExitReport {
+                code: Some(1),
+                stderr_tail: vec!["Error: connection refused".to_string()],
+            }))),
+            abort_handle: None,
+            metadata: ActiveGadgetMetadata {
+                blueprint_id,
+                service_id,
+                blueprint_name: blueprint_name.to_string(),
+                source: "github:webb-tools/gadget@0.1.0".to_string(),
+                binary_path: PathBuf::from("/tmp/protocol-0.1.0"),
+                arguments: vec![],
+            },
+        };
+
+        let mut active_gadgets = ActiveGadgets::new();
+        let squaring = active_gadgets.entry(1).or_default();
+        let _ = squaring.insert(3, gadget("incredible-squaring", 1, 3, true));
+        let _ = squaring.insert(1, gadget("incredible-squaring", 1, 1, false));
+        let _ = active_gadgets
+            .entry(0)
+            .or_default()
+            .insert(2, gadget("frost", 0, 2, true));
+
+        assert_eq!(
+            active_services(&active_gadgets, &CrashHistory::new(Duration::from_secs(60))),
+            vec![
+                ("frost-2".to_string(), ActiveGadgetState::Running, None),
+                (
+                    "incredible-squaring-1".to_string(),
+                    ActiveGadgetState::Exited,
+                    Some(ExitReport {
+                        code: Some(1),
+                        stderr_tail: vec!["Error: connection refused".to_string()],
+                    })
+                ),
+                (
+                    "incredible-squaring-3".to_string(),
+                    ActiveGadgetState::Running,
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_service_is_only_failed_after_repeated_crashes() {
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut history = CrashHistory::new(window);
+
+        // Exits once during startup, then stays up after the restart
+        assert!(!history.record_exit(1, 2, start));
+        assert!(!history.is_failed(1, 2, start + Duration::from_secs(1)));
+        assert!(!history.is_failed(1, 2, start + Duration::from_secs(600)));
+
+        // Crashes repeatedly within the window
+        assert!(!history.record_exit(1, 3, start));
+        assert!(!history.record_exit(1, 3, start + Duration::from_secs(10)));
+        assert!(history.record_exit(1, 3, start + Duration::from_secs(20)));
+        assert!(history.is_failed(1, 3, start + Duration::from_secs(30)));
+        // And recovers once it stays up for the whole window
+        assert!(!history.is_failed(1, 3, start + Duration::from_secs(120)));
+
+        history.forget(1, 3);
+        assert!(!history.is_failed(1, 3, start + Duration::from_secs(30)));
+
+        // Crashes spread out over more than the window never fail the service
+        for i in 0..5 {
+            assert!(!history.record_exit(1, 4, start + window * (i + 1) * 2));
+        }
+    }
+
+    #[test]
+    fn test_binaries_of_stopped_services_are_only_removed_if_unused() {
+        let gadget = |service_id, binary_path: &str| ActiveGadget {
+            status: Arc::new(AtomicBool::new(true)),
+            exit_report: Arc::default(),
+            abort_handle: None,
+            metadata: ActiveGadgetMetadata {
+                blueprint_id: 1,
+                service_id,
+                blueprint_name: "incredible-squaring".to_string(),
+                source: "github:webb-tools/gadget@0.1.0".to_string(),
+                binary_path: PathBuf::from(binary_path),
+                arguments: vec![],
+            },
+        };
+        let mut active_gadgets = ActiveGadgets::new();
+        let _ = active_gadgets
+            .entry(1)
+            .or_default()
+            .insert(3, gadget(3, "/tmp/protocol-1-0.2.0"));
+        let stopped = vec![
+            PathBuf::from("/tmp/protocol-1-0.1.0"),
+            PathBuf::from("/tmp/protocol-1-0.2.0"),
+            PathBuf::from("/tmp/protocol-1-0.1.0"),
+        ];
+
+        // Binaries are kept by default
+        assert!(binaries_to_remove(stopped.clone(), &active_gadgets, true).is_empty());
+
+        // Otherwise, only those no running service needs are removed, once
+        assert_eq!(
+            binaries_to_remove(stopped, &active_gadgets, false),
+            vec![PathBuf::from("/tmp/protocol-1-0.1.0")]
+        );
+    }
+
+    #[test]
+    fn test_rapid_restarts_are_throttled() {
+        let start = Instant::now();
+        let mut history =
+            CrashHistory::new(Duration::from_secs(60)).with_restart_limit(RestartLimit {
+                burst: 3,
+                refill_interval: Duration::from_secs(10),
+                cooldown: Duration::from_secs(300),
+            });
+
+        // Crash-looping every second only gets the burst of restarts
+        for i in 0..3 {
+            assert!(history.try_restart(1, 2, start + Duration::from_secs(i)));
+        }
+        assert!(!history.try_restart(1, 2, start + Duration::from_secs(3)));
+        assert!(history.is_throttled(1, 2, start + Duration::from_secs(3)));
+        assert!(history.is_failed(1, 2, start + Duration::from_secs(3)));
+
+        // And stays down for the whole cooldown, even though tokens were refilled meanwhile
+        assert!(!history.try_restart(1, 2, start + Duration::from_secs(200)));
+        assert!(history.try_restart(1, 2, start + Duration::from_secs(303)));
+        assert!(!history.is_failed(1, 2, start + Duration::from_secs(303)));
+
+        // Other services are throttled independently
+        assert!(!history.is_throttled(1, 3, start + Duration::from_secs(3)));
+        assert!(history.try_restart(1, 3, start + Duration::from_secs(3)));
+
+        // A service restarting no faster than the refill rate is never throttled
+        for i in 0..10 {
+            assert!(history.try_restart(1, 4, start + Duration::from_secs(10 * i)));
+        }
+
+        history.forget(1, 2);
+        assert!(!history.is_throttled(1, 2, start + Duration::from_secs(3)));
+
+        // Without a limit, restarts are never throttled
+        let mut history = CrashHistory::new(Duration::from_secs(60));
+        for i in 0..10 {
+            assert!(history.try_restart(1, 2, start + Duration::from_millis(i)));
+        }
+    }
+
+    #[test]
+    fn test_restart_policy_gives_up_after_max_restarts() {
+        assert_eq!("never".parse(), Ok(RestartPolicy::Never));
+        assert_eq!("always".parse(), Ok(RestartPolicy::Always));
+        assert_eq!(
+            "on-failure:2".parse(),
+            Ok(RestartPolicy::OnFailure { max_restarts: 2 })
+        );
+        assert!("on-failure".parse::<RestartPolicy>().is_err());
+        assert!("sometimes".parse::<RestartPolicy>().is_err());
+
+        let mut history = CrashHistory::new(Duration::from_secs(60))
+            .with_restart_policy(RestartPolicy::OnFailure { max_restarts: 2 });
+        assert!(history.policy_allows_restart(1, 2, false));
+        assert!(history.policy_allows_restart(1, 2, false));
+        assert_eq!(history.restart_count(1, 2), 2);
+        assert!(!history.is_dead(1, 2));
+        assert!(!history.policy_allows_restart(1, 2, false));
+        assert!(history.is_dead(1, 2));
+
+        // A service exiting successfully is done, rather than failed
+        assert!(!history.policy_allows_restart(1, 3, true));
+        assert!(history.is_dead(1, 3));
+
+        // A service restarted on-chain starts over
+        history.forget(1, 2);
+        assert!(!history.is_dead(1, 2));
+        assert!(history.policy_allows_restart(1, 2, false));
+
+        let mut history =
+            CrashHistory::new(Duration::from_secs(60)).with_restart_policy(RestartPolicy::Never);
+        assert!(!history.policy_allows_restart(1, 2, false));
+        assert!(history.is_dead(1, 2));
+
+        // Services are always restarted by default
+        let mut history = CrashHistory::new(Duration::from_secs(60));
+        for _ in 0..10 {
+            assert!(history.policy_allows_restart(1, 2, true));
+        }
+        assert!(!history.is_dead(1, 2));
+    }
+
+    #[test]
+    fn test_throttled_restart_is_not_another_attempt() {
+        let start = Instant::now();
+        let mut history = CrashHistory::new(Duration::from_secs(60))
+            .with_restart_policy(RestartPolicy::OnFailure { max_restarts: 2 })
+            .with_restart_limit(RestartLimit {
+                burst: 1,
+                refill_interval: Duration::from_secs(10),
+                cooldown: Duration::from_secs(300),
+            });
+
+        assert!(history.policy_allows_restart(1, 2, false));
+        let _ = history.schedule_restart(1, 2, start);
+        assert!(history.is_restart_due(1, 2, start));
+        assert!(history.try_restart(1, 2, start));
+        history.record_restart(1, 2, start);
+        assert!(!history.is_restart_scheduled(1, 2));
+
+        // The second exit is throttled, and waits for the cooldown without asking the policy again
+        assert!(history.policy_allows_restart(1, 2, false));
+        let _ = history.schedule_restart(1, 2, start + Duration::from_secs(1));
+        assert!(!history.try_restart(1, 2, start + Duration::from_secs(1)));
+        assert!(history.is_restart_scheduled(1, 2));
+        assert!(history.try_restart(1, 2, start + Duration::from_secs(301)));
+        history.record_restart(1, 2, start + Duration::from_secs(301));
+        assert_eq!(history.restart_count(1, 2), 2);
+
+        // A service the policy gives up on never takes a token
+        let mut history = CrashHistory::new(Duration::from_secs(60))
+            .with_restart_policy(RestartPolicy::Never)
+            .with_restart_limit(RestartLimit {
+                burst: 1,
+                refill_interval: Duration::from_secs(10),
+                cooldown: Duration::from_secs(300),
+            });
+        assert!(!history.policy_allows_restart(1, 2, false));
+        assert!(history.restarts.is_empty());
+    }
+
+    #[test]
+    fn test_restarts_back_off_until_the_service_is_stable() {
+        let start = Instant::now();
+        let mut history =
+            CrashHistory::new(Duration::from_secs(60)).with_restart_backoff(RestartBackoff {
+                base: Duration::from_secs(1),
+                max: Duration::from_secs(10),
+            });
+
+        // Each crash right after its restart doubles the delay, up to the maximum
+        let mut now = start;
+        for expected in [1, 2, 4, 8, 10, 10] {
+            let delay = history.schedule_restart(1, 2, now);
+            assert_eq!(delay, Duration::from_secs(expected));
+            assert!(!history.is_restart_due(1, 2, now + delay - Duration::from_millis(1)));
+            assert!(history.is_restart_due(1, 2, now + delay));
+            now += delay;
+            history.record_restart(1, 2, now);
+        }
+
+        // Other services back off independently
+        assert_eq!(history.schedule_restart(1, 3, now), Duration::from_secs(1));
+
+        // Once the service stays up for the whole window, it is restarted quickly again
+        now += Duration::from_secs(60);
+        assert_eq!(history.schedule_restart(1, 2, now), Duration::from_secs(1));
+
+        history.forget(1, 2);
+        assert!(!history.is_restart_scheduled(1, 2));
+        assert_eq!(history.schedule_restart(1, 2, now), Duration::from_secs(1));
+
+        // Without a backoff, restarts are due right away
+        let mut history = CrashHistory::new(Duration::from_secs(60));
+        assert_eq!(history.schedule_restart(1, 2, start), Duration::ZERO);
+        assert!(history.is_restart_due(1, 2, start));
+    }
+}
@@ -14,24 +14,119 @@ pub struct FilteredBlueprint {
 }
 
 pub fn get_gadget_binary(gadget_binaries: &[GadgetBinary]) -> Option<&GadgetBinary> {
-    let os = get_formatted_os_string().to_lowercase();
-    let arch = std::env::consts::ARCH.to_lowercase();
+    let (os, arch) = host_os_and_arch();
+    gadget_binaries
+        .iter()
+        .find(|binary| binary_mismatch(binary, &os, &arch).is_none())
+}
+
+/// Explains why none of the `gadget_binaries` can run on this host, listing each candidate's
+/// declared OS and architecture.
+pub fn binary_selection_report(gadget_binaries: &[GadgetBinary]) -> String {
+    let (os, arch) = host_os_and_arch();
+    describe_binary_selection(gadget_binaries, &os, &arch)
+}
+
+fn host_os_and_arch() -> (String, String) {
+    (
+        get_formatted_os_string().to_lowercase(),
+        std::env::consts::ARCH.to_lowercase(),
+    )
+}
+
+fn describe_binary_selection(gadget_binaries: &[GadgetBinary], os: &str, arch: &str) -> String {
+    let mut report = format!("No binary matches the host (os: {os}, arch: {arch})");
+    if gadget_binaries.is_empty() {
+        report.push_str(", as the blueprint declares no binaries");
+        return report;
+    }
+
     for binary in gadget_binaries {
-        let binary_str = format!("{:?}", binary.os).to_lowercase();
-        if binary_str.contains(&os) || os.contains(&binary_str) || binary_str == os {
-            let mut arch_str = format!("{:?}", binary.arch).to_lowercase();
-
-            if arch_str == "amd" {
-                arch_str = "x86".to_string()
-            } else if arch_str == "amd64" {
-                arch_str = "x86_64".to_string()
-            }
-
-            if arch_str == arch {
-                return Some(binary);
-            }
-        }
+        let decision = match binary_mismatch(binary, os, arch) {
+            Some(reason) => format!("skipped, {reason}"),
+            None => "selected".to_string(),
+        };
+        report.push_str(&format!(
+            "\n  - {} (os: {:?}, arch: {:?}): {decision}",
+            String::from_utf8_lossy(&binary.name.0 .0),
+            binary.os,
+            binary.arch
+        ));
+    }
+    report
+}
+
+/// Why `binary` cannot run on the host `os` and `arch`, or `None` if it can
+fn binary_mismatch(binary: &GadgetBinary, os: &str, arch: &str) -> Option<String> {
+    let binary_os = format!("{:?}", binary.os).to_lowercase();
+    if !(binary_os.contains(os) || os.contains(&binary_os)) {
+        return Some(format!("OS {binary_os} does not match {os}"));
+    }
+
+    let mut binary_arch = format!("{:?}", binary.arch).to_lowercase();
+    if binary_arch == "amd" {
+        binary_arch = "x86".to_string()
+    } else if binary_arch == "amd64" {
+        binary_arch = "x86_64".to_string()
+    }
+
+    if binary_arch != arch {
+        return Some(format!("architecture {binary_arch} does not match {arch}"));
     }
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::bounded_collections::bounded_vec::BoundedVec;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::field::BoundedString;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::{
+        Architecture, OperatingSystem,
+    };
+
+    fn binary(name: &str, os: OperatingSystem, arch: Architecture) -> GadgetBinary {
+        GadgetBinary {
+            arch,
+            os,
+            name: BoundedString(BoundedVec(name.as_bytes().to_vec())),
+            sha256: [0u8; 32],
+        }
+    }
+
+    #[test]
+    fn test_binary_selection_report_explains_each_candidate() {
+        let binaries = vec![
+            binary(
+                "squaring-macos",
+                OperatingSystem::MacOS,
+                Architecture::Arm64,
+            ),
+            binary(
+                "squaring-linux-arm",
+                OperatingSystem::Linux,
+                Architecture::Arm64,
+            ),
+        ];
+
+        let report = describe_binary_selection(&binaries, "unknown-linux-gnu", "x86_64");
+        assert_eq!(
+            report,
+            "No binary matches the host (os: unknown-linux-gnu, arch: x86_64)\n  \
+             - squaring-macos (os: MacOS, arch: Arm64): skipped, OS macos does not match unknown-linux-gnu\n  \
+             - squaring-linux-arm (os: Linux, arch: Arm64): skipped, architecture arm64 does not match x86_64"
+        );
+
+        let linux_amd64 = binary(
+            "squaring-linux",
+            OperatingSystem::Linux,
+            Architecture::Amd64,
+        );
+        assert!(binary_mismatch(&linux_amd64, "unknown-linux-gnu", "x86_64").is_none());
+        assert!(
+            describe_binary_selection(&[], "unknown-linux-gnu", "x86_64")
+                .ends_with("declares no binaries")
+        );
+    }
+}
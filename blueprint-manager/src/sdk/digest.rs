@@ -0,0 +1,354 @@
+//! Self-describing digests of gadget binaries.
+//!
+//! Digests are accepted in the [multihash](https://multiformats.io/multihash/) format, a varint
+//! algorithm code and a varint length followed by the digest itself, so supporting a new hash
+//! function only requires a new [`HashAlgorithm`] variant.
+
+use crate::sdk::utils::msg_to_error;
+use sha2::Digest;
+use std::fmt;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// A hash function a binary digest can be computed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// The multicodec code identifying this algorithm in a multihash
+    pub fn code(self) -> u64 {
+        match self {
+            Self::Sha256 => 0x12,
+            Self::Sha512 => 0x13,
+            Self::Blake3 => 0x1e,
+        }
+    }
+
+    /// The multicodec name of this algorithm
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha2-256",
+            Self::Sha512 => "sha2-512",
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_code(code: u64) -> Option<Self> {
+        [Self::Sha256, Self::Sha512, Self::Blake3]
+            .into_iter()
+            .find(|algorithm| algorithm.code() == code)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Sha256, Self::Sha512, Self::Blake3]
+            .into_iter()
+            .find(|algorithm| algorithm.name() == name)
+    }
+
+    /// Whether this algorithm can produce a digest of `len` bytes
+    fn supports_len(self, len: usize) -> bool {
+        match self {
+            Self::Sha256 => len == 32,
+            Self::Sha512 => len == 64,
+            // BLAKE3 is an extendable-output function
+            Self::Blake3 => len > 0,
+        }
+    }
+
+    /// Hashes `data`, producing a digest of `len` bytes
+    fn hash(self, data: &[u8], len: usize) -> Vec<u8> {
+        match self {
+            Self::Sha256 => sha2::Sha256::digest(data).to_vec(),
+            Self::Sha512 => sha2::Sha512::digest(data).to_vec(),
+            Self::Blake3 => {
+                let mut digest = vec![0u8; len];
+                blake3::Hasher::new()
+                    .update(data)
+                    .finalize_xof()
+                    .fill(&mut digest);
+                digest
+            }
+        }
+    }
+}
+
+/// The expected digest of a gadget binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryDigest {
+    algorithm: HashAlgorithm,
+    digest: Vec<u8>,
+}
+
+impl BinaryDigest {
+    /// A raw sha256 digest, as stored in the on-chain binary metadata
+    pub fn sha256(digest: [u8; 32]) -> Self {
+        Self {
+            algorithm: HashAlgorithm::Sha256,
+            digest: digest.to_vec(),
+        }
+    }
+
+    /// A digest computed with `algorithm`, failing if `algorithm` cannot produce it
+    pub fn new(algorithm: HashAlgorithm, digest: Vec<u8>) -> color_eyre::Result<Self> {
+        if !algorithm.supports_len(digest.len()) {
+            return Err(msg_to_error(format!(
+                "Invalid {} digest length: {}",
+                algorithm.name(),
+                digest.len()
+            )));
+        }
+
+        Ok(Self { algorithm, digest })
+    }
+
+    /// Computes the digest of `data` with `algorithm`, using its default length
+    pub fn compute(algorithm: HashAlgorithm, data: &[u8]) -> Self {
+        let len = match algorithm {
+            HashAlgorithm::Sha512 => 64,
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 32,
+        };
+        Self {
+            algorithm,
+            digest: algorithm.hash(data, len),
+        }
+    }
+
+    /// Decodes a multihash-encoded digest
+    pub fn from_multihash(bytes: &[u8]) -> color_eyre::Result<Self> {
+        let mut rest = bytes;
+        let code = read_varint(&mut rest)?;
+        let algorithm = HashAlgorithm::from_code(code)
+            .ok_or_else(|| msg_to_error(format!("Unsupported multihash code: {code:#x}")))?;
+        let len = usize::try_from(read_varint(&mut rest)?)
+            .map_err(|_| msg_to_error("Multihash digest length overflows"))?;
+
+        if rest.len() != len {
+            return Err(msg_to_error(format!(
+                "Multihash declares a {len}-byte digest, but contains {} bytes",
+                rest.len()
+            )));
+        }
+        if !algorithm.supports_len(len) {
+            return Err(msg_to_error(format!(
+                "Invalid {} digest length: {len}",
+                algorithm.name()
+            )));
+        }
+
+        Ok(Self {
+            algorithm,
+            digest: rest.to_vec(),
+        })
+    }
+
+    /// Encodes this digest as a multihash
+    pub fn to_multihash(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.digest.len() + 4);
+        write_varint(&mut bytes, self.algorithm.code());
+        write_varint(&mut bytes, self.digest.len() as u64);
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+
+    /// The lowercase hex encoding of the digest alone, without the algorithm
+    pub fn hex(&self) -> String {
+        hex::encode(&self.digest)
+    }
+
+    /// Whether `data` hashes to this digest
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.algorithm.hash(data, self.digest.len()) == self.digest
+    }
+
+    /// A hasher producing a digest comparable to this one, for data that is not held in memory
+    /// all at once
+    pub fn hasher(&self) -> DigestHasher {
+        let state = match self.algorithm {
+            HashAlgorithm::Sha256 => HasherState::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Sha512 => HasherState::Sha512(sha2::Sha512::new()),
+            HashAlgorithm::Blake3 => HasherState::Blake3(Box::new(blake3::Hasher::new())),
+        };
+        DigestHasher {
+            state,
+            len: self.digest.len(),
+        }
+    }
+}
+
+/// Hashes the file at `path` into a digest comparable to `like`, without holding the whole file in
+/// memory
+pub async fn hash_file(path: &Path, like: &BinaryDigest) -> std::io::Result<BinaryDigest> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = like.hasher();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Incrementally computes a [`BinaryDigest`], see [`BinaryDigest::hasher`]
+pub struct DigestHasher {
+    state: HasherState,
+    len: usize,
+}
+
+enum HasherState {
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl DigestHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.state {
+            HasherState::Sha256(hasher) => hasher.update(data),
+            HasherState::Sha512(hasher) => hasher.update(data),
+            HasherState::Blake3(hasher) => {
+                let _ = hasher.update(data);
+            }
+        }
+    }
+
+    /// The digest of all the data passed to [`Self::update`]
+    pub fn finalize(self) -> BinaryDigest {
+        let (algorithm, digest) = match self.state {
+            HasherState::Sha256(hasher) => (HashAlgorithm::Sha256, hasher.finalize().to_vec()),
+            HasherState::Sha512(hasher) => (HashAlgorithm::Sha512, hasher.finalize().to_vec()),
+            HasherState::Blake3(hasher) => {
+                let mut digest = vec![0u8; self.len];
+                hasher.finalize_xof().fill(&mut digest);
+                (HashAlgorithm::Blake3, digest)
+            }
+        };
+        BinaryDigest { algorithm, digest }
+    }
+}
+
+/// Formats the digest as `<algorithm>:<hex>`, e.g. `sha2-256:9f86d0...`
+impl fmt::Display for BinaryDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm.name(), self.hex())
+    }
+}
+
+/// Reads an unsigned LEB128 varint from the start of `bytes`, advancing past it
+fn read_varint(bytes: &mut &[u8]) -> color_eyre::Result<u64> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(9) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Ok(value);
+        }
+    }
+
+    Err(msg_to_error("Truncated or oversized multihash varint"))
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINARY: &[u8] = b"incredible-squaring";
+
+    fn multihash(code: u8, digest: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![code, digest.len() as u8];
+        bytes.extend_from_slice(digest);
+        bytes
+    }
+
+    #[test]
+    fn test_multihash_variants_are_decoded() {
+        let sha256 = sha2::Sha256::digest(BINARY);
+        let sha512 = sha2::Sha512::digest(BINARY);
+        let blake3 = blake3::hash(BINARY);
+
+        for (bytes, algorithm) in [
+            (multihash(0x12, &sha256), HashAlgorithm::Sha256),
+            (multihash(0x13, &sha512), HashAlgorithm::Sha512),
+            (multihash(0x1e, blake3.as_bytes()), HashAlgorithm::Blake3),
+        ] {
+            let digest = BinaryDigest::from_multihash(&bytes).unwrap();
+            assert_eq!(digest.algorithm(), algorithm);
+            assert_eq!(digest, BinaryDigest::compute(algorithm, BINARY));
+            assert!(digest.matches(BINARY));
+            assert!(!digest.matches(b"tampered"));
+            assert_eq!(digest.to_multihash(), bytes);
+        }
+
+        // The on-chain sha256 is formatted as before, along with its algorithm
+        let on_chain = BinaryDigest::sha256(sha256.into());
+        assert_eq!(on_chain.hex(), hex::encode(sha256));
+        assert_eq!(
+            on_chain.to_string(),
+            format!("sha2-256:{}", hex::encode(sha256))
+        );
+    }
+
+    #[test]
+    fn test_incremental_digest_matches_the_whole_data() {
+        let blake3_64 = {
+            let mut digest = vec![0u8; 64];
+            blake3::Hasher::new()
+                .update(BINARY)
+                .finalize_xof()
+                .fill(&mut digest);
+            multihash(0x1e, &digest)
+        };
+        for expected in [
+            BinaryDigest::compute(HashAlgorithm::Sha256, BINARY),
+            BinaryDigest::compute(HashAlgorithm::Sha512, BINARY),
+            BinaryDigest::compute(HashAlgorithm::Blake3, BINARY),
+            BinaryDigest::from_multihash(&blake3_64).unwrap(),
+        ] {
+            let mut hasher = expected.hasher();
+            for chunk in BINARY.chunks(5) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finalize(), expected);
+
+            let mut hasher = expected.hasher();
+            hasher.update(b"tampered");
+            assert_ne!(hasher.finalize(), expected);
+        }
+    }
+
+    #[test]
+    fn test_invalid_multihashes_are_rejected() {
+        let sha256 = sha2::Sha256::digest(BINARY);
+
+        // Unknown algorithm
+        assert!(BinaryDigest::from_multihash(&multihash(0x55, &sha256)).is_err());
+        // Truncated digest
+        assert!(BinaryDigest::from_multihash(&multihash(0x12, &sha256)[..20]).is_err());
+        // Length that does not match the algorithm
+        assert!(BinaryDigest::from_multihash(&multihash(0x13, &sha256)).is_err());
+        // Unterminated varint
+        assert!(BinaryDigest::from_multihash(&[0x80]).is_err());
+    }
+}
@@ -1,4 +1,8 @@
 pub mod config;
+pub mod digest;
 pub mod entry;
+pub mod sandbox;
 pub mod setup;
+pub mod shutdown;
+pub mod signature;
 pub mod utils;
@@ -0,0 +1,228 @@
+//! Optional confinement of the gadget binaries spawned by the manager.
+//!
+//! Two independent mechanisms are supported, both disabled by default:
+//!
+//! * A sandbox helper, a command the gadget binary is run through, e.g.
+//!   `bwrap --unshare-all --share-net --ro-bind / / --dev /dev`, for namespace and filesystem
+//!   isolation.
+//! * A seccomp profile, restricting the syscalls the gadget binary may use.
+//!
+//! Seccomp is only available on Linux. Configuring a profile on any other platform is an error
+//! rather than silently running the binary unconfined. The helper works on any platform, as long
+//! as the helper itself is installed.
+//!
+//! Seccomp profiles use the [seccompiler](https://github.com/rust-vmm/seccompiler) JSON format,
+//! and must define a filter named [`SECCOMP_FILTER_NAME`]. For example, to deny creating
+//! directories on x86_64:
+//!
+//! ```json
+//! {
+//!     "gadget": {
+//!         "default_action": "allow",
+//!         "filter_action": { "errno": 1 },
+//!         "filter": [{ "syscall": "mkdir" }, { "syscall": "mkdirat" }]
+//!     }
+//! }
+//! ```
+//!
+//! The filter is installed right before the gadget binary is executed, so it also applies to the
+//! sandbox helper, which must therefore be allowed by the profile.
+
+use crate::config::BlueprintManagerConfig;
+use crate::sdk::utils::msg_to_error;
+use std::path::Path;
+
+/// The name of the filter used from a seccomp profile
+pub const SECCOMP_FILTER_NAME: &str = "gadget";
+
+/// How to confine a spawned gadget binary
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    /// The helper command, and its arguments, the binary is run through
+    helper: Vec<String>,
+    #[cfg(target_os = "linux")]
+    seccomp: Option<std::sync::Arc<seccompiler::BpfProgram>>,
+}
+
+impl Sandbox {
+    /// Loads the sandbox configured for the manager, which does nothing unless a sandbox helper or
+    /// a seccomp profile is set
+    pub fn from_config(config: &BlueprintManagerConfig) -> color_eyre::Result<Self> {
+        Self::new(
+            config.sandbox_helper.as_deref(),
+            config.seccomp_profile.as_deref(),
+        )
+    }
+
+    /// Creates a sandbox running binaries through the whitespace-separated `helper` command, and
+    /// restricting their syscalls with the seccomp profile at `seccomp_profile`
+    pub fn new(helper: Option<&str>, seccomp_profile: Option<&Path>) -> color_eyre::Result<Self> {
+        let helper = helper
+            .map(|helper| helper.split_whitespace().map(String::from).collect())
+            .unwrap_or_default();
+
+        #[cfg(target_os = "linux")]
+        let seccomp = seccomp_profile
+            .map(|path| load_seccomp_profile(path).map(std::sync::Arc::new))
+            .transpose()?;
+        #[cfg(not(target_os = "linux"))]
+        if let Some(path) = seccomp_profile {
+            return Err(msg_to_error(format!(
+                "Cannot apply the seccomp profile {}: seccomp is only supported on Linux",
+                path.display()
+            )));
+        }
+
+        Ok(Self {
+            helper,
+            #[cfg(target_os = "linux")]
+            seccomp,
+        })
+    }
+
+    /// Whether binaries are spawned without any confinement
+    pub fn is_disabled(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if self.seccomp.is_some() {
+            return false;
+        }
+        self.helper.is_empty()
+    }
+
+    /// A command running `binary` within this sandbox
+    pub fn command(&self, binary: &Path) -> tokio::process::Command {
+        let mut command = match self.helper.split_first() {
+            Some((helper, helper_args)) => {
+                let mut command = tokio::process::Command::new(helper);
+                let _ = command.args(helper_args).arg(binary);
+                command
+            }
+            None => tokio::process::Command::new(binary),
+        };
+
+        #[cfg(target_os = "linux")]
+        if let Some(program) = self.seccomp.clone() {
+            // SAFETY: `apply_filter` only issues the `prctl` and `seccomp` syscalls, so it is
+            // safe to call between `fork` and `exec`
+            unsafe {
+                let _ = command.pre_exec(move || {
+                    seccompiler::apply_filter(&program).map_err(std::io::Error::other)
+                });
+            }
+        }
+
+        command
+    }
+}
+
+/// Compiles the [`SECCOMP_FILTER_NAME`] filter of the seccomp profile at `path` for this
+/// architecture
+#[cfg(target_os = "linux")]
+fn load_seccomp_profile(path: &Path) -> color_eyre::Result<seccompiler::BpfProgram> {
+    let invalid = |err: &dyn std::fmt::Display| {
+        msg_to_error(format!("Invalid seccomp profile {}: {err}", path.display()))
+    };
+
+    let arch =
+        seccompiler::TargetArch::try_from(std::env::consts::ARCH).map_err(|err| invalid(&err))?;
+    let profile = std::fs::File::open(path).map_err(|err| invalid(&err))?;
+    let mut filters = seccompiler::compile_from_json(profile, arch).map_err(|err| invalid(&err))?;
+    filters
+        .remove(SECCOMP_FILTER_NAME)
+        .ok_or_else(|| invalid(&format!("missing the `{SECCOMP_FILTER_NAME}` filter")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+
+    #[test]
+    fn test_sandbox_is_disabled_by_default() {
+        let sandbox = Sandbox::new(None, None).unwrap();
+        assert!(sandbox.is_disabled());
+
+        let command = sandbox.command(Path::new("/usr/bin/gadget"));
+        assert_eq!(command.as_std().get_program(), "/usr/bin/gadget");
+    }
+
+    #[test]
+    fn test_binary_is_run_through_the_helper() {
+        let sandbox = Sandbox::new(Some("bwrap --unshare-all  --ro-bind / /"), None).unwrap();
+        assert!(!sandbox.is_disabled());
+
+        let command = sandbox.command(Path::new("/usr/bin/gadget"));
+        let command = command.as_std();
+        assert_eq!(command.get_program(), "bwrap");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["--unshare-all", "--ro-bind", "/", "/", "/usr/bin/gadget"]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_denied_syscall_is_blocked() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let profile = dir.join("profile.json");
+        // Newer architectures, such as aarch64, only have `mkdirat`
+        let denied = if cfg!(target_arch = "x86_64") {
+            r#"{ "syscall": "mkdir" }, { "syscall": "mkdirat" }"#
+        } else {
+            r#"{ "syscall": "mkdirat" }"#
+        };
+        std::fs::write(
+            &profile,
+            format!(
+                r#"{{
+                    "gadget": {{
+                        "default_action": "allow",
+                        "filter_action": {{ "errno": 1 }},
+                        "filter": [{denied}]
+                    }}
+                }}"#
+            ),
+        )
+        .unwrap();
+
+        let mkdir = |sandbox: &Sandbox, name: &str| {
+            let mut command = sandbox.command(Path::new("/bin/mkdir"));
+            let _ = command
+                .arg(dir.join(name))
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            command
+        };
+
+        // The same binary can create directories outside of the sandbox
+        let unconfined = Sandbox::new(None, None).unwrap();
+        assert!(mkdir(&unconfined, "allowed")
+            .status()
+            .await
+            .unwrap()
+            .success());
+        assert!(dir.join("allowed").is_dir());
+
+        // But not once the profile denies it
+        let sandbox = Sandbox::new(None, Some(&profile)).unwrap();
+        assert!(!sandbox.is_disabled());
+        assert!(!mkdir(&sandbox, "denied").status().await.unwrap().success());
+        assert!(!dir.join("denied").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_profile_without_the_gadget_filter_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let profile = dir.path().join("unnamed.json");
+        std::fs::write(
+            &profile,
+            r#"{ "other": { "default_action": "allow", "filter_action": "trap", "filter": [] } }"#,
+        )
+        .unwrap();
+
+        let err = Sandbox::new(None, Some(&profile)).unwrap_err();
+        assert!(err.to_string().contains("missing the `gadget` filter"));
+    }
+}
@@ -0,0 +1,42 @@
+//! Cancellation of in-flight work, such as binary downloads, when the manager shuts down.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// A token shared by the manager and the work it spawns, cancelled once on shutdown.
+///
+/// Clones share the same state, so cancelling any of them cancels them all.
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    cancelled: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self {
+            cancelled: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Cancels the token, waking up every task waiting in [`Self::cancelled`]
+    pub fn cancel(&self) {
+        let _ = self.cancelled.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    /// Completes once the token is cancelled, immediately if it already is
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.cancelled.subscribe();
+        // The sender is owned by `self`, so it cannot be dropped while waiting
+        let _ = cancelled.wait_for(|cancelled| *cancelled).await;
+    }
+}
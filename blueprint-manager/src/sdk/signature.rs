@@ -0,0 +1,97 @@
+//! Detached ed25519 signatures of gadget binaries.
+//!
+//! A digest only protects a binary against corruption. Pinning the public key a blueprint's
+//! binaries are signed with also protects them against a compromised release host. Signatures
+//! are accepted either as their raw 64 bytes, or hex encoded, e.g. as written by
+//! `xxd -p -c 64`.
+//!
+//! A binary is signed through its raw digest, the one it is already verified against, so a large
+//! binary never has to be read into memory again to check its signature.
+
+use crate::sdk::digest::BinaryDigest;
+use crate::sdk::utils::msg_to_error;
+use sp_core::{ed25519, Pair};
+
+/// Checks that `signature` is a signature of `data` by the ed25519 `public_key`
+pub fn verify_detached_signature(
+    public_key: &[u8; 32],
+    signature: &[u8],
+    data: &[u8],
+) -> color_eyre::Result<()> {
+    let signature = parse_signature(signature)?;
+    let public_key = ed25519::Public::from_raw(*public_key);
+    if !ed25519::Pair::verify(&signature, data, &public_key) {
+        return Err(msg_to_error(format!(
+            "The binary is not signed by {}",
+            hex::encode(public_key.0)
+        )));
+    }
+
+    Ok(())
+}
+
+/// Checks that `signature` is a signature of the raw bytes of `digest` by the ed25519 `public_key`
+pub fn verify_digest_signature(
+    public_key: &[u8; 32],
+    signature: &[u8],
+    digest: &BinaryDigest,
+) -> color_eyre::Result<()> {
+    verify_detached_signature(public_key, signature, digest.digest())
+}
+
+fn parse_signature(signature: &[u8]) -> color_eyre::Result<ed25519::Signature> {
+    if let Ok(raw) = <[u8; 64]>::try_from(signature) {
+        return Ok(ed25519::Signature::from_raw(raw));
+    }
+
+    let hex_signature = std::str::from_utf8(signature)
+        .map(|signature| signature.trim().trim_start_matches("0x"))
+        .map_err(|_| msg_to_error("The signature is neither raw nor hex encoded"))?;
+    let raw = hex::decode(hex_signature)
+        .ok()
+        .and_then(|raw| <[u8; 64]>::try_from(raw).ok())
+        .ok_or_else(|| msg_to_error("The signature is not a hex encoded ed25519 signature"))?;
+    Ok(ed25519::Signature::from_raw(raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdk::digest::HashAlgorithm;
+
+    #[test]
+    fn test_signature_is_verified_against_the_pinned_key() {
+        let pair = ed25519::Pair::from_seed(&[7; 32]);
+        let public_key = pair.public().0;
+        let binary = b"gadget binary";
+        let signature = pair.sign(binary).0;
+
+        verify_detached_signature(&public_key, &signature, binary).unwrap();
+        let hex_signature = format!("{}\n", hex::encode(signature));
+        verify_detached_signature(&public_key, hex_signature.as_bytes(), binary).unwrap();
+
+        // A tampered binary, or one signed by another key, is rejected
+        assert!(verify_detached_signature(&public_key, &signature, b"tampered binary").is_err());
+        let other = ed25519::Pair::from_seed(&[8; 32]);
+        let other_signature = other.sign(binary).0;
+        assert!(verify_detached_signature(&public_key, &other_signature, binary).is_err());
+
+        assert!(verify_detached_signature(&public_key, b"not a signature", binary).is_err());
+    }
+
+    #[test]
+    fn test_binary_is_signed_through_its_digest() {
+        let pair = ed25519::Pair::from_seed(&[7; 32]);
+        let public_key = pair.public().0;
+        let digest = BinaryDigest::compute(HashAlgorithm::Sha256, b"gadget binary");
+        let signature = pair.sign(digest.digest()).0;
+
+        verify_digest_signature(&public_key, &signature, &digest).unwrap();
+
+        // Signing the binary itself is not enough
+        let binary_signature = pair.sign(b"gadget binary").0;
+        assert!(verify_digest_signature(&public_key, &binary_signature, &digest).is_err());
+        let tampered = BinaryDigest::compute(HashAlgorithm::Sha256, b"tampered binary");
+        assert!(verify_digest_signature(&public_key, &signature, &tampered).is_err());
+    }
+}
@@ -1,13 +1,17 @@
 use crate::config::BlueprintManagerConfig;
+use crate::gadget::{ExitReport, STDERR_TAIL_LINES};
 use crate::protocols::resolver::NativeGithubMetadata;
+use crate::sdk::digest::BinaryDigest;
+use gadget_io::tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use gadget_io::GadgetConfig;
 use gadget_sdk::config::Protocol;
-use gadget_sdk::{info, warn};
+use gadget_sdk::{error, info, warn};
 use sha2::Digest;
+use std::collections::VecDeque;
 use std::path::Path;
 use std::string::FromUtf8Error;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError};
 use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::field::BoundedString;
 use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::{
     GadgetBinary, GithubFetcher,
@@ -111,7 +115,23 @@ pub fn get_formatted_os_string() -> String {
     }
 }
 
+/// The default template used to build binary download URLs, pointing at public GitHub releases
+pub const DEFAULT_DOWNLOAD_URL_TEMPLATE: &str =
+    "https://github.com/{owner}/{repo}/releases/download/v{tag}/{name}-{os}-{arch}{ext}";
+
 pub fn get_download_url(binary: &GadgetBinary, fetcher: &GithubFetcher) -> String {
+    get_download_url_with_template(binary, fetcher, DEFAULT_DOWNLOAD_URL_TEMPLATE)
+}
+
+/// Builds the download URL for `binary` from `template`.
+///
+/// The template may contain the placeholders `{owner}`, `{repo}`, `{tag}`, `{name}`, `{os}`,
+/// `{arch}` and `{ext}`, which allows pointing at GitHub Enterprise hosts or release mirrors.
+pub fn get_download_url_with_template(
+    binary: &GadgetBinary,
+    fetcher: &GithubFetcher,
+    template: &str,
+) -> String {
     let os = get_formatted_os_string();
     let ext = if os == "windows" { ".exe" } else { "" };
     let owner = String::from_utf8(fetcher.owner.0 .0.clone()).expect("Should be a valid owner");
@@ -121,8 +141,14 @@ pub fn get_download_url(binary: &GadgetBinary, fetcher: &GithubFetcher) -> Strin
         String::from_utf8(binary.name.0 .0.clone()).expect("Should be a valid binary name");
     let os_name = format!("{:?}", binary.os).to_lowercase();
     let arch_name = format!("{:?}", binary.arch).to_lowercase();
-    // https://github.com/<owner>/<repo>/releases/download/v<tag>/<path>
-    format!("https://github.com/{owner}/{repo}/releases/download/v{tag}/{binary_name}-{os_name}-{arch_name}{ext}")
+    template
+        .replace("{owner}", &owner)
+        .replace("{repo}", &repo)
+        .replace("{tag}", &tag)
+        .replace("{name}", &binary_name)
+        .replace("{os}", &os_name)
+        .replace("{arch}", &arch_name)
+        .replace("{ext}", ext)
 }
 
 pub fn msg_to_error<T: Into<String>>(msg: T) -> color_eyre::Report {
@@ -159,19 +185,50 @@ pub fn is_windows() -> bool {
     std::env::consts::OS == "windows"
 }
 
+/// Watches a spawned gadget process, returning its running status, its exit report once it
+/// exits, and a handle to kill it.
+///
+/// If the stderr of the process is piped, it is forwarded to the logs of the manager, with the
+/// service as a field, and its last [`STDERR_TAIL_LINES`] lines are kept in the exit report.
 pub fn generate_running_process_status_handle(
-    process: gadget_io::tokio::process::Child,
+    mut process: gadget_io::tokio::process::Child,
     service_name: &str,
-) -> (Arc<AtomicBool>, gadget_io::tokio::sync::oneshot::Sender<()>) {
+) -> (
+    Arc<AtomicBool>,
+    Arc<Mutex<Option<ExitReport>>>,
+    gadget_io::tokio::sync::oneshot::Sender<()>,
+) {
     let (stop_tx, stop_rx) = gadget_io::tokio::sync::oneshot::channel::<()>();
     let status = Arc::new(AtomicBool::new(true));
     let status_clone = status.clone();
+    let exit_report = Arc::new(Mutex::new(None));
+    let exit_report_clone = exit_report.clone();
     let service_name = service_name.to_string();
+    let stderr = process.stderr.take();
 
     let task = async move {
         info!("Starting process execution for {service_name}");
-        let output = process.wait_with_output().await;
-        warn!("Process for {service_name} exited: {output:?}");
+        let stderr_tail = match stderr {
+            Some(stderr) => forward_stderr_tail(stderr, &service_name, STDERR_TAIL_LINES).await,
+            None => Vec::new(),
+        };
+        let code = match process.wait().await {
+            Ok(status) => status.code(),
+            Err(err) => {
+                warn!("Failed to wait for the process of {service_name}: {err}");
+                None
+            }
+        };
+
+        let report = ExitReport { code, stderr_tail };
+        if report.success() {
+            info!("Process for {service_name} exited: {report}");
+        } else {
+            error!("Process for {service_name} exited: {report}");
+        }
+        *exit_report_clone
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(report);
         status_clone.store(false, Ordering::Relaxed);
     };
 
@@ -183,17 +240,134 @@ pub fn generate_running_process_status_handle(
     };
 
     gadget_io::tokio::spawn(task);
-    (status, stop_tx)
+    (status, exit_report, stop_tx)
+}
+
+/// Logs every line of `stderr` as a warning of `service_name` until it is closed, returning the
+/// last `max_lines` lines
+async fn forward_stderr_tail<R: AsyncRead + Unpin>(
+    stderr: R,
+    service_name: &str,
+    max_lines: usize,
+) -> Vec<String> {
+    let mut lines = BufReader::new(stderr).lines();
+    let mut tail = VecDeque::with_capacity(max_lines);
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => {
+                warn!(service = %service_name, "{line}");
+                if tail.len() == max_lines {
+                    let _ = tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+            Ok(None) => break,
+            Err(err) => {
+                warn!(service = %service_name, "Failed to read the stderr of the process: {err}");
+                break;
+            }
+        }
+    }
+    tail.into()
 }
 
 pub fn bytes_to_utf8_string<T: Into<Vec<u8>>>(input: T) -> color_eyre::Result<String> {
     String::from_utf8(input.into()).map_err(|err| msg_to_error(err.to_string()))
 }
 
+#[deprecated(note = "use `BinaryDigest::hex`, which supports other hash algorithms")]
 pub fn slice_32_to_sha_hex_string(hash: [u8; 32]) -> String {
-    use std::fmt::Write;
-    hash.iter().fold(String::new(), |mut acc, byte| {
-        write!(&mut acc, "{:02x}", byte).expect("Should be able to write");
-        acc
-    })
+    BinaryDigest::sha256(hash).hex()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::bounded_collections::bounded_vec::BoundedVec;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::{
+        Architecture, OperatingSystem,
+    };
+
+    #[tokio::test]
+    async fn test_non_zero_exit_records_code_and_stderr_tail() {
+        let script = format!(
+            "for i in $(seq 1 {}); do echo \"line $i\" >&2; done; exit 3",
+            STDERR_TAIL_LINES + 5
+        );
+        let process = gadget_io::tokio::process::Command::new("sh")
+            .args(["-c", &script])
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let (status, exit_report, _abort) =
+            generate_running_process_status_handle(process, "crashing-service-0");
+        gadget_io::tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            while status.load(Ordering::Relaxed) {
+                gadget_io::tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The process should have exited");
+
+        let report = exit_report.lock().unwrap().clone().unwrap();
+        assert_eq!(report.code, Some(3));
+        assert!(!report.success());
+        // Only the last lines are kept
+        let expected = (6..=STDERR_TAIL_LINES + 5)
+            .map(|i| format!("line {i}"))
+            .collect::<Vec<_>>();
+        assert_eq!(report.stderr_tail, expected);
+        assert!(report
+            .to_string()
+            .starts_with("exit code 3, last stderr lines:\nline 6\n"));
+    }
+
+    fn bounded_string(value: &str) -> BoundedString {
+        BoundedString(BoundedVec(value.as_bytes().to_vec()))
+    }
+
+    fn test_fetcher() -> (GadgetBinary, GithubFetcher) {
+        let binary = GadgetBinary {
+            arch: Architecture::Amd64,
+            os: OperatingSystem::Linux,
+            name: bounded_string("incredible-squaring"),
+            sha256: [0u8; 32],
+        };
+        let fetcher = GithubFetcher {
+            owner: bounded_string("webb-tools"),
+            repo: bounded_string("gadget"),
+            tag: bounded_string("0.1.0"),
+            binaries: BoundedVec(vec![binary.clone()]),
+        };
+        (binary, fetcher)
+    }
+
+    #[test]
+    fn test_download_url_uses_distinct_owner_and_repo() {
+        let (binary, fetcher) = test_fetcher();
+        assert_eq!(
+            get_download_url(&binary, &fetcher),
+            "https://github.com/webb-tools/gadget/releases/download/v0.1.0/incredible-squaring-linux-amd64"
+        );
+
+        let metadata = github_fetcher_to_native_github_metadata(&fetcher, 0);
+        assert_eq!(metadata.owner, "webb-tools");
+        assert_eq!(metadata.repo, "gadget");
+        assert_eq!(metadata.git, "https://github.com/webb-tools/gadget");
+    }
+
+    #[test]
+    fn test_custom_download_url_template() {
+        let (binary, fetcher) = test_fetcher();
+        let url = get_download_url_with_template(
+            &binary,
+            &fetcher,
+            "https://git.example.com/mirror/{owner}/{repo}/{tag}/{name}-{os}-{arch}",
+        );
+        assert_eq!(
+            url,
+            "https://git.example.com/mirror/webb-tools/gadget/0.1.0/incredible-squaring-linux-amd64"
+        );
+    }
 }
@@ -0,0 +1,231 @@
+//! Extraction of gadget binaries from the archives some releases ship them in.
+//!
+//! Gzipped tarballs and zip archives are supported. Whether a release artifact is an archive, and
+//! its format, are detected from its content, however it was named or resolved.
+
+use crate::sdk::digest::{BinaryDigest, DigestHasher};
+use crate::sdk::utils::msg_to_error;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+/// Whether the release artifact at `path` is an archive the binary must be extracted from, rather
+/// than the binary itself
+pub fn is_archive(path: &Path) -> std::io::Result<bool> {
+    let mut header = [0u8; 4];
+    let read = std::fs::File::open(path)?.read(&mut header)?;
+    Ok(ArchiveFormat::detect(&header[..read]).is_some())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The format of the archive starting with `header`, by its magic bytes
+    fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if header.starts_with(b"PK\x03\x04") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extracts the file named `name`, or `{name}.exe`, from anywhere within the archive at
+/// `archive` into `dest`, replacing it, and returns its digest as hashed by `hasher`.
+///
+/// The binary is first written next to `dest` and then renamed over it, so `dest` never holds a
+/// partially extracted binary.
+pub fn extract_binary(
+    archive: &Path,
+    name: &str,
+    dest: &Path,
+    hasher: DigestHasher,
+) -> color_eyre::Result<BinaryDigest> {
+    let mut file = std::fs::File::open(archive)?;
+    let mut header = [0u8; 4];
+    let read = file.read(&mut header)?;
+    file.rewind()?;
+    let format = ArchiveFormat::detect(&header[..read]).ok_or_else(|| {
+        msg_to_error(format!(
+            "{} is neither a gzipped tarball nor a zip archive",
+            archive.display()
+        ))
+    })?;
+
+    let file_name = dest
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp = dest.with_file_name(format!(".{file_name}.{}.extract", std::process::id()));
+    let result = std::fs::File::create(&tmp)
+        .map_err(color_eyre::Report::from)
+        .and_then(|out_file| {
+            let mut out = HashingWriter {
+                file: out_file,
+                hasher,
+            };
+            let found = match format {
+                ArchiveFormat::TarGz => extract_from_tar_gz(file, name, &mut out)?,
+                ArchiveFormat::Zip => extract_from_zip(file, name, &mut out)?,
+            };
+            if !found {
+                return Err(msg_to_error(format!(
+                    "No file named {name} found in the archive {}",
+                    archive.display()
+                )));
+            }
+            out.flush()?;
+            std::fs::rename(&tmp, dest)?;
+            Ok(out.hasher.finalize())
+        });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Writes the extracted binary to its file, hashing it on the way
+struct HashingWriter {
+    file: std::fs::File,
+    hasher: DigestHasher,
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Whether the archive entry at `path` is the binary named `name`
+fn is_binary(path: &Path, name: &str) -> bool {
+    path.file_name()
+        .and_then(|file_name| file_name.to_str())
+        .is_some_and(|file_name| file_name == name || file_name.strip_suffix(".exe") == Some(name))
+}
+
+fn extract_from_tar_gz(
+    archive: std::fs::File,
+    name: &str,
+    out: &mut HashingWriter,
+) -> color_eyre::Result<bool> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() && is_binary(&entry.path()?, name) {
+            let _ = std::io::copy(&mut entry, out)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn extract_from_zip(
+    archive: std::fs::File,
+    name: &str,
+    out: &mut HashingWriter,
+) -> color_eyre::Result<bool> {
+    let mut archive = zip::ZipArchive::new(archive)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let is_match = entry
+            .enclosed_name()
+            .is_some_and(|path| is_binary(&path, name));
+        if entry.is_file() && is_match {
+            let _ = std::io::copy(&mut entry, out)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::sdk::digest::HashAlgorithm;
+
+    const BINARY: &[u8] = b"#!/bin/sh\necho gadget\n";
+
+    /// A gzipped tarball of `files`, as `(path, data)`
+    pub(crate) fn tar_gz(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn zip_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (path, data) in files {
+            writer
+                .start_file(*path, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_binary_is_extracted_from_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("protocol-1-0.1.0");
+        let files: &[(&str, &[u8])] = &[
+            ("release/README.md", b"readme"),
+            ("release/incredible-squaring", BINARY),
+        ];
+
+        let expected = BinaryDigest::compute(HashAlgorithm::Sha256, BINARY);
+
+        // Archives are detected by their content, rather than by their name
+        for (archive_name, bytes) in [
+            ("gadget-tar", tar_gz(files)),
+            ("gadget-zip", zip_archive(files)),
+        ] {
+            let archive = dir.path().join(archive_name);
+            std::fs::write(&archive, bytes).unwrap();
+            assert!(is_archive(&archive).unwrap());
+            let digest =
+                extract_binary(&archive, "incredible-squaring", &dest, expected.hasher()).unwrap();
+            assert_eq!(std::fs::read(&dest).unwrap(), BINARY);
+            assert_eq!(digest, expected);
+            std::fs::remove_file(&dest).unwrap();
+
+            // A missing binary aborts the extraction, without leaving anything behind
+            let err = extract_binary(&archive, "missing", &dest, expected.hasher()).unwrap_err();
+            assert!(err.to_string().contains("No file named missing"));
+            assert!(!dest.exists());
+        }
+
+        let raw = dir.path().join("raw");
+        std::fs::write(&raw, BINARY).unwrap();
+        assert!(!is_archive(&raw).unwrap());
+        let err =
+            extract_binary(&raw, "incredible-squaring", &dest, expected.hasher()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("neither a gzipped tarball nor a zip"));
+
+        assert_eq!(
+            std::fs::read_dir(dir.path()).unwrap().count(),
+            3,
+            "only the archives and the raw binary should be left"
+        );
+    }
+}
@@ -0,0 +1,587 @@
+use crate::sdk::digest::{hash_file, BinaryDigest, HashAlgorithm};
+use gadget_sdk::{trace, warn};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// The directory, relative to the data directory, that holds cached binaries
+pub const DEFAULT_CACHE_DIR: &str = "binary-cache";
+
+/// A content-addressed store of downloaded gadget binaries, keyed by their digest.
+///
+/// Entries are named `<algorithm>-<hex digest>`, e.g. `blake3-af13...`, except for sha256 entries
+/// which are named by their hex digest alone, as before other algorithms were supported.
+///
+/// Services pinned to the same binary share one cache entry. Each service gets its own
+/// hard link to the entry, so the binary is only downloaded and stored once.
+#[derive(Debug, Clone)]
+pub struct BinaryCache {
+    root: PathBuf,
+}
+
+impl BinaryCache {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The path at which the binary with the given digest is stored
+    pub fn entry_path(&self, digest: &BinaryDigest) -> PathBuf {
+        self.root.join(entry_name(digest))
+    }
+
+    /// A unique path for downloading the binary with the given digest, before it is verified and
+    /// inserted into the cache
+    pub fn partial_path(&self, digest: &BinaryDigest) -> PathBuf {
+        self.root.join(format!(
+            ".{}.{}.{}.part",
+            entry_name(digest),
+            std::process::id(),
+            next_tmp_id()
+        ))
+    }
+
+    /// Returns the cached binary with the given digest, if present and intact
+    pub async fn get(&self, digest: &BinaryDigest) -> Option<PathBuf> {
+        let path = self.entry_path(digest);
+        match hash_file(&path, digest).await {
+            Ok(actual) if actual == *digest => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Stores `bytes` under `digest`, which the caller must have already verified.
+    ///
+    /// The bytes are first written to a uniquely named temporary file and then renamed into place.
+    /// If two services race to populate the same entry, both renames succeed and the entry ends up
+    /// holding identical contents either way.
+    pub async fn insert(&self, digest: &BinaryDigest, bytes: &[u8]) -> std::io::Result<PathBuf> {
+        tokio::fs::create_dir_all(&self.root).await?;
+        let path = self.entry_path(digest);
+        let tmp_path = self.root.join(format!(
+            ".{}.{}.{}.tmp",
+            entry_name(digest),
+            std::process::id(),
+            next_tmp_id()
+        ));
+
+        if let Err(err) = tokio::fs::write(&tmp_path, bytes).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(err);
+        }
+
+        if let Err(err) = tokio::fs::rename(&tmp_path, &path).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(err);
+        }
+
+        trace!("Cached binary {digest} at {}", path.display());
+        Ok(path)
+    }
+
+    /// Moves the file at `path`, which the caller must have already verified, into the cache as
+    /// `digest`.
+    ///
+    /// `path` must be on the same filesystem as the cache, e.g. a [`Self::partial_path`], so that
+    /// the binary is renamed into place rather than copied.
+    pub async fn insert_file(
+        &self,
+        digest: &BinaryDigest,
+        path: &Path,
+    ) -> std::io::Result<PathBuf> {
+        let entry = self.entry_path(digest);
+        if let Err(err) = tokio::fs::rename(path, &entry).await {
+            let _ = tokio::fs::remove_file(path).await;
+            return Err(err);
+        }
+
+        trace!("Cached binary {digest} at {}", entry.display());
+        Ok(entry)
+    }
+
+    /// Makes the cached binary `digest` available at `link`, replacing anything already there.
+    ///
+    /// A hard link is used where possible, falling back to a copy if the cache and `link`
+    /// live on different filesystems. Either way the binary is first placed next to `link` and
+    /// then renamed over it, so `link` never holds a partially written binary.
+    pub async fn link(&self, digest: &BinaryDigest, link: &Path) -> std::io::Result<()> {
+        let entry = self.entry_path(digest);
+        let file_name = link
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let tmp_link = link.with_file_name(format!(
+            ".{file_name}.{}.{}.tmp",
+            std::process::id(),
+            next_tmp_id()
+        ));
+
+        let result = match tokio::fs::hard_link(&entry, &tmp_link).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(err),
+            Err(err) => {
+                warn!(
+                    "Failed to hard link {} to {}, copying instead: {err}",
+                    entry.display(),
+                    link.display()
+                );
+                tokio::fs::copy(&entry, &tmp_link).await.map(|_| ())
+            }
+        };
+        let result = match result {
+            Ok(()) => tokio::fs::rename(&tmp_link, link).await,
+            Err(err) => Err(err),
+        };
+
+        // Renaming over a hard link to the same file is a no-op that leaves `tmp_link` behind
+        let _ = tokio::fs::remove_file(&tmp_link).await;
+        result
+    }
+
+    /// Removes the binary linked at `link`. Its cache entry is removed too once no other binary
+    /// is linked to it anymore, so that it no longer takes up disk space
+    pub async fn remove_linked(&self, link: &Path) -> std::io::Result<()> {
+        let link_metadata = tokio::fs::metadata(link).await?;
+        let mut entry = None;
+        for (digest, metadata) in self.cached_entries().await? {
+            if is_same_binary(link, &link_metadata, &metadata, &digest).await {
+                entry = Some(self.entry_path(&digest));
+                break;
+            }
+        }
+
+        tokio::fs::remove_file(link).await?;
+        trace!("Removed binary {}", link.display());
+
+        let Some(entry) = entry else {
+            return Ok(());
+        };
+        let result = match tokio::fs::metadata(&entry).await {
+            Ok(metadata) if is_unlinked(&metadata) => tokio::fs::remove_file(&entry).await,
+            Ok(_) => return Ok(()),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(()) => {
+                trace!("Removed unused binary {} from the cache", entry.display());
+                Ok(())
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Lists the binaries held in the cache, along with the service binaries in `binaries_dir`
+    /// linked to them
+    pub async fn entries(&self, binaries_dir: &Path) -> std::io::Result<Vec<CacheEntry>> {
+        let cached = self.cached_entries().await?;
+        let linked = cached_files(binaries_dir).await?;
+
+        let mut entries = Vec::with_capacity(cached.len());
+        for (digest, metadata) in cached {
+            let mut last_used = last_access(&metadata);
+            let mut links = vec![];
+            for (name, link_metadata) in &linked {
+                let link = binaries_dir.join(name);
+                if is_same_binary(&link, link_metadata, &metadata, &digest).await {
+                    last_used = last_used.max(last_access(link_metadata));
+                    links.push(LinkedBinary::new(link));
+                }
+            }
+
+            entries.push(CacheEntry {
+                digest,
+                size: metadata.len(),
+                last_used,
+                links,
+            });
+        }
+
+        entries.sort_by_key(CacheEntry::name);
+        Ok(entries)
+    }
+
+    /// The entries of the cache along with their digest, skipping any file that is not named after
+    /// a digest
+    async fn cached_entries(&self) -> std::io::Result<Vec<(BinaryDigest, std::fs::Metadata)>> {
+        Ok(cached_files(&self.root)
+            .await?
+            .into_iter()
+            .filter_map(|(name, metadata)| Some((parse_entry_name(&name)?, metadata)))
+            .collect())
+    }
+
+    /// Deletes the cached binary named `name`, as listed in [`CacheEntry::name`] or formatted as
+    /// its digest, along with the service binaries in `binaries_dir` linked to it.
+    ///
+    /// Deleting a binary that is linked at any of the `in_use` paths, e.g. the binaries of running
+    /// services, is refused.
+    pub async fn clear(
+        &self,
+        name: &str,
+        binaries_dir: &Path,
+        in_use: &[PathBuf],
+    ) -> std::io::Result<()> {
+        let name = name.trim().to_lowercase();
+        let entry = self
+            .entries(binaries_dir)
+            .await?
+            .into_iter()
+            .find(|entry| entry.name() == name || entry.digest.to_string() == name)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("No binary {name} in the cache"),
+                )
+            })?;
+
+        let in_use = in_use
+            .iter()
+            .map(|path| canonical(path))
+            .collect::<Vec<_>>();
+        if let Some(link) = entry
+            .links
+            .iter()
+            .find(|link| in_use.contains(&canonical(&link.path)))
+        {
+            return Err(std::io::Error::other(format!(
+                "Cached binary {name} is in use by the running service binary {}, stop the service first",
+                link.path.display()
+            )));
+        }
+
+        for link in &entry.links {
+            tokio::fs::remove_file(&link.path).await?;
+        }
+        tokio::fs::remove_file(self.entry_path(&entry.digest)).await?;
+        trace!("Cleared binary {name} from the cache");
+        Ok(())
+    }
+}
+
+/// The name of the cache entry holding the binary with the given digest
+fn entry_name(digest: &BinaryDigest) -> String {
+    match digest.algorithm() {
+        HashAlgorithm::Sha256 => digest.hex(),
+        algorithm => format!("{}-{}", algorithm.name(), digest.hex()),
+    }
+}
+
+/// The digest of the binary held by the cache entry named `name`, see [`entry_name`]
+fn parse_entry_name(name: &str) -> Option<BinaryDigest> {
+    let (algorithm, hex_digest) = match name.rsplit_once('-') {
+        Some((algorithm, hex_digest)) => (HashAlgorithm::from_name(algorithm)?, hex_digest),
+        None => (HashAlgorithm::Sha256, name),
+    };
+    BinaryDigest::new(algorithm, hex::decode(hex_digest).ok()?).ok()
+}
+
+/// A binary held in the cache, see [`BinaryCache::entries`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub digest: BinaryDigest,
+    /// The size of the binary, in bytes
+    pub size: u64,
+    /// The last time the binary, or any of its links, was accessed
+    pub last_used: Option<SystemTime>,
+    /// The binaries of services linked to this entry
+    pub links: Vec<LinkedBinary>,
+}
+
+/// A service binary linked to a [`CacheEntry`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedBinary {
+    pub path: PathBuf,
+    /// The blueprint the binary was fetched for, if the link is named `protocol-{blueprint_id}-{tag}`
+    pub blueprint_id: Option<u64>,
+    /// The release tag the binary was fetched from, if the link is named
+    /// `protocol-{blueprint_id}-{tag}`
+    pub tag: Option<String>,
+}
+
+impl CacheEntry {
+    /// The name of the entry in the cache
+    pub fn name(&self) -> String {
+        entry_name(&self.digest)
+    }
+}
+
+impl LinkedBinary {
+    fn new(path: PathBuf) -> Self {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (blueprint_id, tag) = name
+            .strip_prefix("protocol-")
+            .and_then(|rest| rest.split_once('-'))
+            .and_then(|(blueprint_id, tag)| {
+                let tag = tag.strip_suffix(".exe").unwrap_or(tag);
+                Some((blueprint_id.parse().ok()?, tag.to_string()))
+            })
+            .unzip();
+        Self {
+            path,
+            blueprint_id,
+            tag,
+        }
+    }
+}
+
+/// The regular files directly in `dir`, by name, skipping the hidden temporary files of
+/// downloads and links in progress
+async fn cached_files(dir: &Path) -> std::io::Result<Vec<(String, std::fs::Metadata)>> {
+    let mut read_dir = match tokio::fs::read_dir(dir).await {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+
+    let mut files = vec![];
+    while let Some(entry) = read_dir.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let metadata = entry.metadata().await?;
+        if !name.starts_with('.') && metadata.is_file() {
+            files.push((name, metadata));
+        }
+    }
+    Ok(files)
+}
+
+/// Whether the file at `link` holds the cached binary `digest`, either as a hard link to its
+/// cache entry or as a copy of it
+async fn is_same_binary(
+    link: &Path,
+    link_metadata: &std::fs::Metadata,
+    entry_metadata: &std::fs::Metadata,
+    digest: &BinaryDigest,
+) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if link_metadata.dev() == entry_metadata.dev()
+            && link_metadata.ino() == entry_metadata.ino()
+        {
+            return true;
+        }
+    }
+
+    // Binaries are copied rather than linked across filesystems
+    link_metadata.len() == entry_metadata.len()
+        && hash_file(link, digest)
+            .await
+            .is_ok_and(|actual| actual == *digest)
+}
+
+/// Whether no binary is hard linked to the cache entry with `metadata` anymore. Binaries copied
+/// out of the cache are not tracked, and on platforms without link counts, entries are kept
+fn is_unlinked(metadata: &std::fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.nlink() <= 1
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+fn last_access(metadata: &std::fs::Metadata) -> Option<SystemTime> {
+    metadata.accessed().or_else(|_| metadata.modified()).ok()
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn next_tmp_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(bytes: &[u8]) -> BinaryDigest {
+        BinaryDigest::compute(HashAlgorithm::Sha256, bytes)
+    }
+
+    /// A cache in a temporary directory, which is removed once the returned guard is dropped
+    fn test_cache() -> (tempfile::TempDir, BinaryCache) {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BinaryCache::new(dir.path());
+        (dir, cache)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_inserts_share_one_entry() {
+        let (_dir, cache) = test_cache();
+        let bytes = b"gadget binary".to_vec();
+        let hash = sha256(&bytes);
+
+        let (a, b) = tokio::join!(cache.insert(&hash, &bytes), cache.insert(&hash, &bytes));
+        assert_eq!(a.unwrap(), b.unwrap());
+        assert_eq!(cache.get(&hash).await, Some(cache.entry_path(&hash)));
+        assert_eq!(std::fs::read_dir(cache.root()).unwrap().count(), 1);
+
+        let link = cache.root().join("protocol-link");
+        cache.link(&hash, &link).await.unwrap();
+        assert_eq!(std::fs::read(&link).unwrap(), bytes);
+    }
+
+    #[tokio::test]
+    async fn test_non_sha256_entries_are_hit() {
+        let (_dir, cache) = test_cache();
+        let bytes = b"gadget binary".to_vec();
+        let digest = BinaryDigest::compute(HashAlgorithm::Blake3, &bytes);
+        let entry = cache.insert(&digest, &bytes).await.unwrap();
+        assert!(entry.ends_with(format!("blake3-{}", digest.hex())));
+        assert_eq!(cache.get(&digest).await, Some(entry));
+
+        // The same bytes hashed with another algorithm are another entry
+        let sha512 = BinaryDigest::compute(HashAlgorithm::Sha512, &bytes);
+        assert_eq!(cache.get(&sha512).await, None);
+
+        let link = cache.root().join("protocol-link");
+        cache.link(&digest, &link).await.unwrap();
+        let entries = cache.entries(cache.root()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].digest, digest);
+
+        cache.remove_linked(&link).await.unwrap();
+        #[cfg(unix)]
+        assert_eq!(cache.get(&digest).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_entry_is_not_returned() {
+        let (_dir, cache) = test_cache();
+        let hash = sha256(b"expected");
+        std::fs::write(cache.entry_path(&hash), b"tampered").unwrap();
+
+        assert_eq!(cache.get(&hash).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_shared_entry_is_only_removed_with_its_last_link() {
+        let (_dir, cache) = test_cache();
+        let bytes = b"gadget binary".to_vec();
+        let hash = sha256(&bytes);
+        let entry = cache.insert(&hash, &bytes).await.unwrap();
+        let link = cache.root().join("protocol-link");
+        let other_link = cache.root().join("protocol-other-link");
+        cache.link(&hash, &link).await.unwrap();
+        cache.link(&hash, &other_link).await.unwrap();
+
+        // Another service still runs the binary, so only the link is removed
+        cache.remove_linked(&link).await.unwrap();
+        assert!(!link.exists());
+        assert_eq!(std::fs::read(&other_link).unwrap(), bytes);
+        assert_eq!(cache.get(&hash).await, Some(entry));
+        assert!(cache.remove_linked(&link).await.is_err());
+
+        cache.remove_linked(&other_link).await.unwrap();
+        assert!(!other_link.exists());
+        #[cfg(unix)]
+        assert_eq!(cache.get(&hash).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_entries_are_listed_and_selectively_cleared() {
+        let (_dir, cache) = test_cache();
+        let binaries = cache.root().join("binaries");
+        std::fs::create_dir_all(&binaries).unwrap();
+
+        let squaring = b"incredible-squaring".to_vec();
+        let squaring_hash = sha256(&squaring);
+        let _ = cache.insert(&squaring_hash, &squaring).await.unwrap();
+        let squaring_link = binaries.join("protocol-1-v0.1.0");
+        cache.link(&squaring_hash, &squaring_link).await.unwrap();
+
+        let avs = b"tangle-avs".to_vec();
+        let avs_hash = sha256(&avs);
+        let _ = cache.insert(&avs_hash, &avs).await.unwrap();
+        let avs_link = binaries.join("protocol-2-v0.2.0-rc1");
+        cache.link(&avs_hash, &avs_link).await.unwrap();
+
+        // The in-progress download is not listed
+        std::fs::write(cache.partial_path(&avs_hash), b"tangle").unwrap();
+
+        let entries = cache.entries(&binaries).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        let squaring_entry = entries
+            .iter()
+            .find(|entry| entry.digest == squaring_hash)
+            .unwrap();
+        assert_eq!(squaring_entry.size, squaring.len() as u64);
+        assert!(squaring_entry.last_used.is_some());
+        assert_eq!(
+            squaring_entry.links,
+            vec![LinkedBinary {
+                path: squaring_link.clone(),
+                blueprint_id: Some(1),
+                tag: Some("v0.1.0".to_string()),
+            }]
+        );
+
+        // A binary in use by a running service is kept
+        let err = cache
+            .clear(&squaring_hash.hex(), &binaries, &[squaring_link.clone()])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("in use"));
+        assert!(squaring_link.exists());
+        assert!(cache.get(&squaring_hash).await.is_some());
+
+        // While the others can be cleared, along with their links
+        cache
+            .clear(&avs_hash.to_string(), &binaries, &[squaring_link.clone()])
+            .await
+            .unwrap();
+        assert!(!avs_link.exists());
+        let entries = cache.entries(&binaries).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].digest, squaring_hash);
+        assert_eq!(
+            cache
+                .clear(&avs_hash.hex(), &binaries, &[])
+                .await
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_link_leaves_no_partial_binary() {
+        let (_dir, cache) = test_cache();
+        let bytes = b"gadget binary".to_vec();
+        let hash = sha256(&bytes);
+        let links = cache.root().join("links");
+        std::fs::create_dir_all(&links).unwrap();
+        let link = links.join("protocol-link");
+
+        // Linking a missing entry neither creates the binary nor leaves a temporary file around
+        assert!(cache.link(&hash, &link).await.is_err());
+        assert!(!link.exists());
+        assert_eq!(std::fs::read_dir(&links).unwrap().count(), 0);
+
+        // Nor does it clobber a binary that was already in place
+        std::fs::write(&link, b"previous binary").unwrap();
+        assert!(cache.link(&hash, &link).await.is_err());
+        assert_eq!(std::fs::read(&link).unwrap(), b"previous binary");
+
+        // Which is replaced as a whole once the entry exists, even when linked twice
+        let _ = cache.insert(&hash, &bytes).await.unwrap();
+        cache.link(&hash, &link).await.unwrap();
+        cache.link(&hash, &link).await.unwrap();
+        assert_eq!(std::fs::read(&link).unwrap(), bytes);
+        assert_eq!(std::fs::read_dir(&links).unwrap().count(), 1);
+    }
+}
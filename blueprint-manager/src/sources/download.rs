@@ -0,0 +1,493 @@
+//! Downloading binaries into the binary cache, shared by the fetchers of every source.
+//!
+//! A fetcher only resolves the request serving its binary, and the digest the binary is expected
+//! to have. [`fetch_verified_binary`] then downloads it, unless it is already cached, verifies it
+//! and links it where the service is run from. Downloads failing on a transient error, such as a
+//! dropped connection or a server error, are retried with an exponential backoff.
+
+use crate::config::DataDir;
+use crate::sdk::digest::{BinaryDigest, DigestHasher};
+use crate::sdk::shutdown::CancellationToken;
+use crate::sdk::utils::msg_to_error;
+use crate::sources::cache::BinaryCache;
+use backon::{ExponentialBuilder, Retryable};
+use gadget_sdk::{error, info, warn};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// How downloads failing on a transient error are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadRetry {
+    /// The number of times a failed download is retried before giving up
+    pub max_retries: usize,
+    /// The delay before the first retry, doubled after every attempt, with some jitter
+    pub base_delay: Duration,
+}
+
+impl DownloadRetry {
+    fn backoff(&self) -> ExponentialBuilder {
+        ExponentialBuilder::default()
+            .with_min_delay(self.base_delay)
+            .with_max_times(self.max_retries)
+            .with_jitter()
+    }
+}
+
+/// Links the binary with `expected_digest` to `link_path`, downloading it into the binary cache
+/// of `data_dir` first unless another service already cached it.
+///
+/// See [`fetch_into_cache`] for how the binary is downloaded.
+#[allow(clippy::too_many_arguments)]
+pub async fn fetch_verified_binary<F, Fut>(
+    name: &str,
+    request: F,
+    expected_digest: &BinaryDigest,
+    link_path: &Path,
+    data_dir: &DataDir,
+    max_in_memory_size: u64,
+    retry: &DownloadRetry,
+    cancel: &CancellationToken,
+) -> color_eyre::Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = color_eyre::Result<reqwest::RequestBuilder>>,
+{
+    let _ = fetch_into_cache(
+        name,
+        request,
+        expected_digest,
+        data_dir,
+        max_in_memory_size,
+        retry,
+        cancel,
+    )
+    .await?;
+
+    BinaryCache::new(data_dir.binary_cache())
+        .link(expected_digest, link_path)
+        .await?;
+    info!(
+        "Using cached binary {expected_digest} at {}",
+        link_path.display()
+    );
+
+    Ok(())
+}
+
+/// Downloads the file with `expected_digest` into the binary cache of `data_dir`, unless another
+/// service already cached it, returning the path of its cache entry.
+///
+/// `request` resolves the request serving the file, and is only called when the file must be
+/// downloaded, once per attempt. Failed attempts are retried as configured by `retry`, returning
+/// the last error once the retries are used up. The download is aborted as soon as `cancel` is
+/// cancelled, and a file whose digest does not match is never cached.
+pub async fn fetch_into_cache<F, Fut>(
+    name: &str,
+    request: F,
+    expected_digest: &BinaryDigest,
+    data_dir: &DataDir,
+    max_in_memory_size: u64,
+    retry: &DownloadRetry,
+    cancel: &CancellationToken,
+) -> color_eyre::Result<PathBuf>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = color_eyre::Result<reqwest::RequestBuilder>>,
+{
+    let cache = BinaryCache::new(data_dir.binary_cache());
+
+    // Only download the file if no other service has already cached it
+    if let Some(entry) = cache.get(expected_digest).await {
+        return Ok(entry);
+    }
+
+    tokio::fs::create_dir_all(cache.root()).await?;
+    let partial_path = cache.partial_path(expected_digest);
+    let (request, path) = (&request, partial_path.as_path());
+    let downloaded = (move || async move {
+        let request = tokio::select! {
+            biased;
+            () = cancel.cancelled() => return Err(download_cancelled()),
+            request = request() => request?,
+        };
+        download_binary(
+            request,
+            path,
+            expected_digest.hasher(),
+            max_in_memory_size,
+            cancel,
+        )
+        .await
+    })
+    .retry(retry.backoff())
+    .when(|err| !cancel.is_cancelled() && is_transient(err))
+    .notify(|err, delay| {
+        warn!("Failed to download the binary of {name} ({err}), retrying in {delay:?}");
+    })
+    .await?;
+    if downloaded.digest != *expected_digest {
+        let _ = tokio::fs::remove_file(&partial_path).await;
+        error!(
+            "Binary hash {} mismatched expected hash of {expected_digest} for protocol: {name}",
+            downloaded.digest
+        );
+        return Err(color_eyre::Report::msg(
+            "The hash of the downloaded binary did not match",
+        ));
+    }
+
+    let entry = match &downloaded.bytes {
+        Some(bytes) => cache.insert(expected_digest, bytes).await?,
+        None => cache.insert_file(expected_digest, &partial_path).await?,
+    };
+    Ok(entry)
+}
+
+/// A binary downloaded by [`download_binary`]
+#[derive(Debug)]
+pub struct DownloadedBinary {
+    /// The digest of the downloaded bytes
+    pub digest: BinaryDigest,
+    /// The binary itself if it was buffered in memory, otherwise it was written to the file
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Downloads the response to `request`, hashing it with `hasher` as it is received, and aborting
+/// as soon as `cancel` is cancelled.
+///
+/// Responses declaring a length of at most `max_in_memory_size` bytes are buffered in memory.
+/// Any other response is streamed into `path` chunk by chunk, so memory use does not depend on
+/// the size of the binary. The file at `path` is removed unless the download completes, including
+/// when the returned future is dropped, so an interrupted download never leaves a partial binary
+/// behind.
+pub async fn download_binary(
+    request: reqwest::RequestBuilder,
+    path: &Path,
+    mut hasher: DigestHasher,
+    max_in_memory_size: u64,
+    cancel: &CancellationToken,
+) -> color_eyre::Result<DownloadedBinary> {
+    let mut partial = PartialDownload {
+        path: path.to_path_buf(),
+        complete: false,
+    };
+
+    // The file lives within this future, so it is closed before `partial` removes it
+    let download = async {
+        let mut response = request
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)?;
+        let mut sink = match response.content_length() {
+            Some(len) if len <= max_in_memory_size => {
+                DownloadSink::Memory(Vec::with_capacity(usize::try_from(len).unwrap_or_default()))
+            }
+            _ => DownloadSink::File(tokio::fs::File::create(path).await?),
+        };
+
+        while let Some(chunk) = response.chunk().await? {
+            hasher.update(&chunk);
+            match &mut sink {
+                DownloadSink::Memory(bytes) => bytes.extend_from_slice(&chunk),
+                DownloadSink::File(file) => file.write_all(&chunk).await?,
+            }
+        }
+
+        let bytes = match sink {
+            DownloadSink::Memory(bytes) => Some(bytes),
+            DownloadSink::File(mut file) => {
+                file.flush().await?;
+                None
+            }
+        };
+        Ok::<_, color_eyre::Report>(DownloadedBinary {
+            digest: hasher.finalize(),
+            bytes,
+        })
+    };
+
+    let downloaded = tokio::select! {
+        biased;
+        () = cancel.cancelled() => return Err(download_cancelled()),
+        res = download => res?,
+    };
+
+    partial.complete = downloaded.bytes.is_none();
+    Ok(downloaded)
+}
+
+fn download_cancelled() -> color_eyre::Report {
+    msg_to_error("Download cancelled, the blueprint manager is shutting down")
+}
+
+/// Whether a failed download may succeed when retried, which is the case unless the server
+/// rejected the request itself
+fn is_transient(err: &color_eyre::Report) -> bool {
+    let status = err
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status);
+    match status {
+        Some(status) => {
+            !status.is_client_error()
+                || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        }
+        None => true,
+    }
+}
+
+/// Where [`download_binary`] writes the binary as it is received
+enum DownloadSink {
+    Memory(Vec<u8>),
+    File(tokio::fs::File),
+}
+
+/// Removes a downloaded file on drop, unless the download completed
+struct PartialDownload {
+    path: PathBuf,
+    complete: bool,
+}
+
+impl Drop for PartialDownload {
+    fn drop(&mut self) {
+        if !self.complete {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::sdk::digest::HashAlgorithm;
+
+    /// Serves `body` to a single request, returning the URL it is served at
+    pub(crate) fn serve_once(body: Vec<u8>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        serve(vec![(200, body)])
+    }
+
+    /// Serves each of the `(status, body)` responses in turn, to a request each
+    fn serve(responses: Vec<(u16, Vec<u8>)>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let (url, server) = serve_requests(responses);
+        (format!("{url}/gadget"), server)
+    }
+
+    /// Serves each of the `(status, body)` responses in turn, to a request each, whatever its
+    /// path. Returns the base URL they are served at, and the request lines, e.g.
+    /// `GET /gadget HTTP/1.1`, once every response was served
+    pub(crate) fn serve_requests(
+        responses: Vec<(u16, Vec<u8>)>,
+    ) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                let _ = reader.read_line(&mut line).unwrap();
+                requests.push(line.trim_end().to_string());
+                line.clear();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let mut stream = reader.into_inner();
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Status\r\nconnection: close\r\ncontent-length: {}\r\n\r\n",
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_failed_download_is_retried() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let binary = b"gadget binary".to_vec();
+        let digest = BinaryDigest::compute(HashAlgorithm::Sha256, &binary);
+        let link = data_dir.path().join("protocol-1-0.1.0");
+        let retry = DownloadRetry {
+            max_retries: 2,
+            base_delay: Duration::from_millis(10),
+        };
+
+        // The server fails twice, then serves the binary
+        let (url, server) = serve(vec![(500, vec![]), (503, vec![]), (200, binary.clone())]);
+        let client = reqwest::Client::new();
+        fetch_verified_binary(
+            "incredible-squaring",
+            || {
+                let request = client.get(&url);
+                async { Ok(request) }
+            },
+            &digest,
+            &link,
+            &DataDir::new(data_dir.path()),
+            1024,
+            &retry,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        server.join().unwrap();
+        assert_eq!(std::fs::read(&link).unwrap(), binary);
+
+        // Once the retries are used up, the last error is returned
+        let other_digest = BinaryDigest::compute(HashAlgorithm::Sha256, b"another binary");
+        let retry = DownloadRetry {
+            max_retries: 1,
+            ..retry
+        };
+        let (url, server) = serve(vec![(500, vec![]), (503, vec![])]);
+        let err = fetch_verified_binary(
+            "incredible-squaring",
+            || {
+                let request = client.get(&url);
+                async { Ok(request) }
+            },
+            &other_digest,
+            &data_dir.path().join("protocol-1-0.2.0"),
+            &DataDir::new(data_dir.path()),
+            1024,
+            &retry,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+        server.join().unwrap();
+        assert!(err.to_string().contains("503"));
+
+        // Client errors are not retried
+        let (url, server) = serve(vec![(404, vec![])]);
+        let err = fetch_verified_binary(
+            "incredible-squaring",
+            || {
+                let request = client.get(&url);
+                async { Ok(request) }
+            },
+            &other_digest,
+            &data_dir.path().join("protocol-1-0.2.0"),
+            &DataDir::new(data_dir.path()),
+            1024,
+            &retry,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+        server.join().unwrap();
+        assert!(err.to_string().contains("404"));
+    }
+
+    #[tokio::test]
+    async fn test_large_binary_is_streamed_to_disk() {
+        let binary: Vec<u8> = (0..8 * 1024 * 1024).map(|i: u32| i as u8).collect();
+        let expected = BinaryDigest::compute(HashAlgorithm::Sha256, &binary);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gadget.part");
+
+        // Above the threshold, the binary is hashed as it is written, and never held in memory
+        let (url, server) = serve_once(binary.clone());
+        let downloaded = download_binary(
+            reqwest::Client::new().get(url),
+            &path,
+            expected.hasher(),
+            1024 * 1024,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        server.join().unwrap();
+        assert!(downloaded.bytes.is_none());
+        assert_eq!(downloaded.digest, expected);
+        assert_eq!(std::fs::read(&path).unwrap(), binary);
+        std::fs::remove_file(&path).unwrap();
+
+        // Below it, the binary is kept in memory, and no file is created
+        let (url, server) = serve_once(binary.clone());
+        let downloaded = download_binary(
+            reqwest::Client::new().get(url),
+            &path,
+            expected.hasher(),
+            16 * 1024 * 1024,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        server.join().unwrap();
+        assert_eq!(downloaded.bytes.as_deref(), Some(&binary[..]));
+        assert_eq!(downloaded.digest, expected);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_download_removes_partial_file() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::time::Duration;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/gadget", listener.local_addr().unwrap());
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+
+        // Serves the first bytes of a much larger binary, then stalls until the test is done
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let mut stream = reader.into_inner();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 1048576\r\n\r\n")
+                .unwrap();
+            stream.write_all(&[0u8; 1024]).unwrap();
+            stream.flush().unwrap();
+            let _ = release_rx.recv();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gadget.part");
+        let cancel = CancellationToken::new();
+        let download = tokio::spawn({
+            let path = path.clone();
+            let cancel = cancel.clone();
+            async move {
+                let hasher = BinaryDigest::sha256([0; 32]).hasher();
+                download_binary(reqwest::Client::new().get(url), &path, hasher, 0, &cancel).await
+            }
+        });
+
+        // Wait for the download to be in flight
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !tokio::fs::metadata(&path)
+                .await
+                .is_ok_and(|metadata| metadata.len() > 0)
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        cancel.cancel();
+        let result = tokio::time::timeout(Duration::from_secs(1), download)
+            .await
+            .expect("the cancelled download should return promptly")
+            .unwrap();
+        assert!(result.is_err());
+        assert!(!path.exists());
+
+        release_tx.send(()).unwrap();
+        server.join().unwrap();
+    }
+}
@@ -1,72 +1,371 @@
-use crate::gadget::native::get_gadget_binary;
-use crate::sdk;
+use crate::config::DataDir;
+use crate::gadget::native::{binary_selection_report, get_gadget_binary};
+use crate::sdk::digest::BinaryDigest;
+use crate::sdk::shutdown::CancellationToken;
+use crate::sdk::signature::verify_digest_signature;
 use crate::sdk::utils::{
-    get_download_url, hash_bytes_to_hex, is_windows, msg_to_error, valid_file_exists,
+    bounded_string_to_string, get_download_url_with_template,
+    github_fetcher_to_native_github_metadata, is_windows, msg_to_error,
+    DEFAULT_DOWNLOAD_URL_TEMPLATE,
 };
+use crate::sources::archive::{extract_binary, is_archive};
+use crate::sources::cache::BinaryCache;
+use crate::sources::download::{fetch_into_cache, DownloadRetry};
 use crate::sources::BinarySourceFetcher;
 use async_trait::async_trait;
-use color_eyre::eyre::OptionExt;
 use gadget_sdk::{error, info};
-use std::path::PathBuf;
-use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::GithubFetcher;
-use tokio::io::AsyncWriteExt;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::{
+    GadgetBinary, GithubFetcher,
+};
+
+/// The base URL of the public GitHub API
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// The number of releases listed per page while looking for a draft release, the most GitHub allows
+const RELEASES_PER_PAGE: usize = 100;
 
 pub struct GithubBinaryFetcher {
     pub fetcher: GithubFetcher,
     pub blueprint_id: u64,
     pub gadget_name: String,
+    /// Overrides [`DEFAULT_DOWNLOAD_URL_TEMPLATE`] when set. Through the GitHub API, the asset
+    /// named like the last segment of the template is fetched instead
+    pub download_url_template: Option<String>,
+    /// Resolve release assets through the GitHub API instead of the direct download URL,
+    /// which allows fetching assets from pre-releases and (with a token) draft releases
+    pub use_github_api: bool,
+    /// The token used to authenticate against the GitHub API
+    pub github_token: Option<String>,
+    /// The base URL of the GitHub API, [`GITHUB_API_URL`] unless testing
+    pub api_url: String,
+    /// The ed25519 public key the release binaries must be signed with, if any
+    pub signing_key: Option<[u8; 32]>,
+    /// Where the binary is cached and linked to
+    pub data_dir: DataDir,
+    /// The largest binary, in bytes, buffered in memory rather than streamed to disk
+    pub max_in_memory_size: u64,
+    /// How failed downloads are retried
+    pub retry: DownloadRetry,
+    /// Aborts an in-flight download when the manager shuts down
+    pub cancel: CancellationToken,
+    /// The digest of the binary last extracted from a release archive, recorded as it is
+    /// extracted. Empty until then, or if the release artifact is the binary itself
+    pub extracted_digest: Mutex<Option<BinaryDigest>>,
+}
+
+/// A GitHub release, as returned by the `GET /repos/{owner}/{repo}/releases` and
+/// `GET /repos/{owner}/{repo}/releases/tags/{tag}` endpoints
+#[derive(Debug, Deserialize)]
+pub struct GithubRelease {
+    pub tag_name: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub assets: Vec<GithubReleaseAsset>,
+}
+
+/// A single asset attached to a [`GithubRelease`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct GithubReleaseAsset {
+    pub name: String,
+    /// The API URL of the asset, which serves the raw bytes when requested with
+    /// `Accept: application/octet-stream`
+    pub url: String,
+}
+
+/// Finds the asset named `asset_name` in the release tagged `tag` (or `v{tag}`), including
+/// drafts and pre-releases
+pub fn find_release_asset(
+    releases: &[GithubRelease],
+    tag: &str,
+    asset_name: &str,
+) -> Option<GithubReleaseAsset> {
+    let prefixed_tag = format!("v{tag}");
+    releases
+        .iter()
+        .filter(|release| release.tag_name == tag || release.tag_name == prefixed_tag)
+        .flat_map(|release| release.assets.iter())
+        .find(|asset| asset.name == asset_name)
+        .cloned()
+}
+
+impl GithubBinaryFetcher {
+    /// The name of the release asset of `binary` resolved through the GitHub API: the last
+    /// segment of the download URL template if set, so that archives are found too
+    fn asset_name(&self, binary: &GadgetBinary) -> String {
+        let template = self
+            .download_url_template
+            .as_deref()
+            .and_then(|template| template.split(['?', '#']).next())
+            .and_then(|path| path.rsplit('/').next())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("{name}-{os}-{arch}{ext}");
+        get_download_url_with_template(binary, &self.fetcher, template)
+    }
+
+    /// Installs the release `artifact` of `binary` at `dest`: links it from the binary cache if
+    /// it is the binary itself, or extracts the binary from it if it is an archive, recording the
+    /// digest of the extracted binary
+    async fn install_artifact(
+        &self,
+        binary: &GadgetBinary,
+        artifact: PathBuf,
+        artifact_digest: &BinaryDigest,
+        dest: &Path,
+    ) -> color_eyre::Result<()> {
+        let archive = tokio::task::spawn_blocking({
+            let artifact = artifact.clone();
+            move || is_archive(&artifact)
+        })
+        .await??;
+        if !archive {
+            BinaryCache::new(self.data_dir.binary_cache())
+                .link(artifact_digest, dest)
+                .await?;
+            info!(
+                "Using cached binary {artifact_digest} at {}",
+                dest.display()
+            );
+            return Ok(());
+        }
+
+        let binary_name = bounded_string_to_string(binary.name.clone())?;
+        let extracted = tokio::task::spawn_blocking({
+            let binary_name = binary_name.clone();
+            let dest = dest.to_path_buf();
+            let hasher = artifact_digest.hasher();
+            move || extract_binary(&artifact, &binary_name, &dest, hasher)
+        })
+        .await?;
+        let digest = match extracted {
+            Ok(digest) => digest,
+            Err(err) => {
+                error!(
+                    "Failed to extract {binary_name} from the release archive of {}: {err}",
+                    self.gadget_name
+                );
+                return Err(err);
+            }
+        };
+        info!("Extracted {binary_name} ({digest}) to {}", dest.display());
+        *self
+            .extracted_digest
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(digest);
+
+        Ok(())
+    }
+
+    /// The request serving the raw bytes of the release artifact of `binary`, or of the artifact
+    /// named like it with an added `suffix`, such as its `.sig` signature
+    async fn download_request(
+        &self,
+        client: &reqwest::Client,
+        binary: &GadgetBinary,
+        suffix: &str,
+    ) -> color_eyre::Result<reqwest::RequestBuilder> {
+        let request = if self.use_github_api {
+            let metadata =
+                github_fetcher_to_native_github_metadata(&self.fetcher, self.blueprint_id);
+            let asset_name = self.asset_name(binary) + suffix;
+            let asset = self
+                .find_asset(
+                    client,
+                    &metadata.owner,
+                    &metadata.repo,
+                    &metadata.tag,
+                    &asset_name,
+                )
+                .await?
+                .ok_or_else(|| {
+                    msg_to_error(format!(
+                        "No asset named {asset_name} found in release {} of {}/{}",
+                        metadata.tag, metadata.owner, metadata.repo
+                    ))
+                })?;
+
+            info!("Downloading release asset {} via the GitHub API", asset.url);
+            self.github_api_request(client, &asset.url)
+                .header(reqwest::header::ACCEPT, "application/octet-stream")
+        } else {
+            let url = get_download_url_with_template(
+                binary,
+                &self.fetcher,
+                self.download_url_template
+                    .as_deref()
+                    .unwrap_or(DEFAULT_DOWNLOAD_URL_TEMPLATE),
+            ) + suffix;
+            info!("Downloading {url}");
+            client.get(url)
+        };
+
+        Ok(request)
+    }
+
+    /// Checks the detached signature of the release artifact of `binary` against `signing_key`.
+    /// The artifact was verified against `digest` when it was cached, so it is not read again
+    async fn verify_signature(
+        &self,
+        client: &reqwest::Client,
+        binary: &GadgetBinary,
+        digest: &BinaryDigest,
+        signing_key: &[u8; 32],
+    ) -> color_eyre::Result<()> {
+        let signature = self
+            .download_request(client, binary, ".sig")
+            .await?
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| msg_to_error(format!("Failed to fetch the signature: {err}")))?
+            .bytes()
+            .await
+            .map_err(|err| msg_to_error(format!("Failed to fetch the signature: {err}")))?;
+
+        if let Err(err) = verify_digest_signature(signing_key, &signature, digest) {
+            error!(
+                "Refusing to run the binary of {}, its signature is invalid: {err}",
+                self.gadget_name
+            );
+            return Err(err);
+        }
+
+        info!(
+            "Verified the signature of the binary of {}",
+            self.gadget_name
+        );
+        Ok(())
+    }
+
+    /// Finds the asset named `asset_name` in the release tagged `tag` (or `v{tag}`) of
+    /// `owner/repo`, including drafts and pre-releases.
+    ///
+    /// Published releases are looked up by their tag. Draft releases have no tag yet, so they are
+    /// only found by listing every release, page by page
+    async fn find_asset(
+        &self,
+        client: &reqwest::Client,
+        owner: &str,
+        repo: &str,
+        tag: &str,
+        asset_name: &str,
+    ) -> color_eyre::Result<Option<GithubReleaseAsset>> {
+        let releases_url = format!("{}/repos/{owner}/{repo}/releases", self.api_url);
+        for candidate in [tag.to_string(), format!("v{tag}")] {
+            let release: Option<GithubRelease> = self
+                .get_api_json(client, &format!("{releases_url}/tags/{candidate}"))
+                .await?;
+            if let Some(release) = release {
+                return Ok(find_release_asset(&[release], tag, asset_name));
+            }
+        }
+
+        for page in 1.. {
+            let releases: Vec<GithubRelease> = self
+                .get_api_json(
+                    client,
+                    &format!("{releases_url}?per_page={RELEASES_PER_PAGE}&page={page}"),
+                )
+                .await?
+                .unwrap_or_default();
+            let asset = find_release_asset(&releases, tag, asset_name);
+            if asset.is_some() || releases.len() < RELEASES_PER_PAGE {
+                return Ok(asset);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Fetches the JSON document at the GitHub API `url`, or `None` if there is none
+    async fn get_api_json<T: serde::de::DeserializeOwned>(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+    ) -> color_eyre::Result<Option<T>> {
+        let response = self
+            .github_api_request(client, url)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .send()
+            .await
+            .map_err(|err| msg_to_error(err.to_string()))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let document = response
+            .error_for_status()
+            .map_err(|err| msg_to_error(err.to_string()))?
+            .json()
+            .await
+            .map_err(|err| msg_to_error(err.to_string()))?;
+        Ok(Some(document))
+    }
+
+    fn github_api_request(&self, client: &reqwest::Client, url: &str) -> reqwest::RequestBuilder {
+        // The GitHub API rejects requests without a user agent
+        let request = client
+            .get(url)
+            .header(reqwest::header::USER_AGENT, "blueprint-manager");
+        match &self.github_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
 }
 
 #[async_trait]
 impl BinarySourceFetcher for GithubBinaryFetcher {
     async fn get_binary(&self) -> color_eyre::Result<PathBuf> {
-        let relevant_binary = get_gadget_binary(&self.fetcher.binaries.0)
-            .ok_or_eyre("Unable to find matching binary")?;
-        let expected_hash = sdk::utils::slice_32_to_sha_hex_string(relevant_binary.sha256);
-        let current_dir = std::env::current_dir()?;
+        let relevant_binary = get_gadget_binary(&self.fetcher.binaries.0).ok_or_else(|| {
+            color_eyre::Report::msg(format!(
+                "Unable to find matching binary for {}: {}",
+                self.gadget_name,
+                binary_selection_report(&self.fetcher.binaries.0)
+            ))
+        })?;
+        let expected_digest = BinaryDigest::sha256(relevant_binary.sha256);
+        let metadata = github_fetcher_to_native_github_metadata(&self.fetcher, self.blueprint_id);
+        let binaries_dir = self.data_dir.binaries();
+        tokio::fs::create_dir_all(&binaries_dir).await?;
         let mut binary_download_path =
-            format!("{}/protocol-{:?}", current_dir.display(), self.fetcher.tag);
+            binaries_dir.join(format!("protocol-{}-{}", self.blueprint_id, metadata.tag));
 
         if is_windows() {
-            binary_download_path += ".exe"
+            let _ = binary_download_path.set_extension("exe");
         }
 
-        info!("Downloading to {binary_download_path}");
-
-        // Check if the binary exists, if not download it
-        let retrieved_hash = if !valid_file_exists(&binary_download_path, &expected_hash).await {
-            let url = get_download_url(relevant_binary, &self.fetcher);
-
-            let download = reqwest::get(&url)
-                .await
-                .map_err(|err| msg_to_error(err.to_string()))?
-                .bytes()
-                .await
-                .map_err(|err| msg_to_error(err.to_string()))?;
-            let retrieved_hash = hash_bytes_to_hex(&download);
-
-            // Write the binary to disk
-            let mut file = tokio::fs::File::create(&binary_download_path).await?;
-            file.write_all(&download).await?;
-            file.flush().await?;
-            Some(retrieved_hash)
-        } else {
-            None
-        };
+        // The digest is that of the release artifact, which is cached as downloaded
+        let client = &reqwest::Client::new();
+        let artifact = fetch_into_cache(
+            &self.gadget_name,
+            move || self.download_request(client, relevant_binary, ""),
+            &expected_digest,
+            &self.data_dir,
+            self.max_in_memory_size,
+            &self.retry,
+            &self.cancel,
+        )
+        .await?;
 
-        if let Some(retrieved_hash) = retrieved_hash {
-            if retrieved_hash.trim() != expected_hash.trim() {
-                error!(
-                    "Binary hash {} mismatched expected hash of {} for protocol: {}",
-                    retrieved_hash, expected_hash, self.gadget_name
-                );
-                return Ok(PathBuf::from(binary_download_path));
-            }
+        if let Some(signing_key) = &self.signing_key {
+            self.verify_signature(client, relevant_binary, &expected_digest, signing_key)
+                .await?;
         }
 
-        Err(color_eyre::Report::msg(
-            "The hash of the downloaded binary did not match",
-        ))
+        self.install_artifact(
+            relevant_binary,
+            artifact,
+            &expected_digest,
+            &binary_download_path,
+        )
+        .await?;
+
+        Ok(binary_download_path)
     }
 
     fn blueprint_id(&self) -> u64 {
@@ -76,4 +375,261 @@ impl BinarySourceFetcher for GithubBinaryFetcher {
     fn name(&self) -> String {
         self.gadget_name.clone()
     }
+
+    fn expected_digest(&self) -> color_eyre::Result<Option<BinaryDigest>> {
+        // The on-chain digest is that of the archive, not of the binary extracted from it, whose
+        // digest is recorded as it is extracted instead
+        let extracted_digest = self
+            .extracted_digest
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        if extracted_digest.is_some() {
+            return Ok(extracted_digest);
+        }
+
+        Ok(get_gadget_binary(&self.fetcher.binaries.0)
+            .map(|binary| BinaryDigest::sha256(binary.sha256)))
+    }
+
+    fn source_description(&self) -> String {
+        let metadata = github_fetcher_to_native_github_metadata(&self.fetcher, self.blueprint_id);
+        format!(
+            "github:{}/{}@{}",
+            metadata.owner, metadata.repo, metadata.tag
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdk::digest::HashAlgorithm;
+    use crate::sources::archive::tests::tar_gz;
+    use crate::sources::download::tests::serve_requests;
+    use std::time::Duration;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::bounded_collections::bounded_vec::BoundedVec;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::field::BoundedString;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::{
+        Architecture, OperatingSystem,
+    };
+
+    fn bounded_string(value: &str) -> BoundedString {
+        BoundedString(BoundedVec(value.as_bytes().to_vec()))
+    }
+
+    fn test_fetcher(
+        download_url_template: Option<&str>,
+        use_github_api: bool,
+        data_dir: &Path,
+    ) -> (GadgetBinary, GithubBinaryFetcher) {
+        let binary = GadgetBinary {
+            arch: Architecture::Amd64,
+            os: OperatingSystem::Linux,
+            name: bounded_string("incredible-squaring"),
+            sha256: [0u8; 32],
+        };
+        let fetcher = GithubBinaryFetcher {
+            fetcher: GithubFetcher {
+                owner: bounded_string("webb-tools"),
+                repo: bounded_string("gadget"),
+                tag: bounded_string("0.1.0"),
+                binaries: BoundedVec(vec![binary.clone()]),
+            },
+            blueprint_id: 7,
+            gadget_name: "incredible-squaring".to_string(),
+            download_url_template: download_url_template.map(str::to_string),
+            use_github_api,
+            github_token: None,
+            api_url: GITHUB_API_URL.to_string(),
+            signing_key: None,
+            data_dir: DataDir::new(data_dir),
+            max_in_memory_size: 16 * 1024 * 1024,
+            retry: DownloadRetry {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+            cancel: CancellationToken::new(),
+            extracted_digest: Mutex::default(),
+        };
+        (binary, fetcher)
+    }
+
+    const RELEASES: &str = r#"[
+        {
+            "tag_name": "v0.2.0-rc1",
+            "draft": false,
+            "prerelease": true,
+            "assets": [
+                {
+                    "name": "incredible-squaring-linux-amd64",
+                    "url": "https://api.github.com/repos/webb-tools/gadget/releases/assets/2"
+                }
+            ]
+        },
+        {
+            "tag_name": "v0.1.0",
+            "draft": false,
+            "prerelease": false,
+            "assets": [
+                {
+                    "name": "incredible-squaring-linux-amd64",
+                    "url": "https://api.github.com/repos/webb-tools/gadget/releases/assets/1"
+                }
+            ]
+        }
+    ]"#;
+
+    #[test]
+    fn test_find_prerelease_asset() {
+        let releases: Vec<GithubRelease> = serde_json::from_str(RELEASES).unwrap();
+        let asset =
+            find_release_asset(&releases, "0.2.0-rc1", "incredible-squaring-linux-amd64").unwrap();
+        assert_eq!(
+            asset.url,
+            "https://api.github.com/repos/webb-tools/gadget/releases/assets/2"
+        );
+        assert!(find_release_asset(&releases, "0.2.0-rc1", "missing-asset").is_none());
+        assert!(
+            find_release_asset(&releases, "0.3.0", "incredible-squaring-linux-amd64").is_none()
+        );
+    }
+
+    fn release_json(tag: &str, draft: bool, asset_id: u32) -> String {
+        format!(
+            r#"{{
+                "tag_name": "{tag}",
+                "draft": {draft},
+                "assets": [
+                    {{
+                        "name": "incredible-squaring-linux-amd64",
+                        "url": "https://api.github.com/repos/webb-tools/gadget/releases/assets/{asset_id}"
+                    }}
+                ]
+            }}"#
+        )
+    }
+
+    async fn find(fetcher: &GithubBinaryFetcher) -> color_eyre::Result<Option<GithubReleaseAsset>> {
+        fetcher
+            .find_asset(
+                &reqwest::Client::new(),
+                "webb-tools",
+                "gadget",
+                "0.1.0",
+                "incredible-squaring-linux-amd64",
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_release_is_found_by_tag_or_among_drafts() {
+        let (_, mut fetcher) = test_fetcher(None, true, Path::new("unused"));
+
+        // A published release is looked up by its tag, falling back to the `v` prefixed one
+        let (url, server) = serve_requests(vec![
+            (404, b"{}".to_vec()),
+            (200, release_json("v0.1.0", false, 1).into_bytes()),
+        ]);
+        fetcher.api_url = url;
+        let asset = find(&fetcher).await.unwrap().unwrap();
+        assert!(asset.url.ends_with("/assets/1"));
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "GET /repos/webb-tools/gadget/releases/tags/0.1.0 HTTP/1.1",
+                "GET /repos/webb-tools/gadget/releases/tags/v0.1.0 HTTP/1.1",
+            ]
+        );
+
+        // A draft release is found by listing every release, beyond the first page
+        let first_page = (0..RELEASES_PER_PAGE)
+            .map(|i| release_json(&format!("v0.0.{i}"), false, 100))
+            .collect::<Vec<_>>()
+            .join(",");
+        let (url, server) = serve_requests(vec![
+            (404, b"{}".to_vec()),
+            (404, b"{}".to_vec()),
+            (200, format!("[{first_page}]").into_bytes()),
+            (
+                200,
+                format!("[{}]", release_json("v0.1.0", true, 2)).into_bytes(),
+            ),
+        ]);
+        fetcher.api_url = url;
+        let asset = find(&fetcher).await.unwrap().unwrap();
+        assert!(asset.url.ends_with("/assets/2"));
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests[2..],
+            [
+                "GET /repos/webb-tools/gadget/releases?per_page=100&page=1 HTTP/1.1",
+                "GET /repos/webb-tools/gadget/releases?per_page=100&page=2 HTTP/1.1",
+            ]
+        );
+
+        // The listing stops at the last page
+        let (url, server) = serve_requests(vec![
+            (404, b"{}".to_vec()),
+            (404, b"{}".to_vec()),
+            (200, b"[]".to_vec()),
+        ]);
+        fetcher.api_url = url;
+        assert!(find(&fetcher).await.unwrap().is_none());
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_archive_assets_are_extracted_and_pinned() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let binary_bytes = b"#!/bin/sh\necho gadget\n";
+        let archive = tar_gz(&[("release/incredible-squaring", binary_bytes)]);
+        let archive_digest = BinaryDigest::compute(HashAlgorithm::Sha256, &archive);
+        let dest = dir.join("protocol-7-0.1.0");
+
+        // An archive asset resolved through the GitHub API is named like the template
+        let (binary, fetcher) = test_fetcher(
+            Some("https://mirror.example.com/{tag}/{name}-{os}-{arch}.tar.gz?token=secret"),
+            true,
+            dir,
+        );
+        assert_eq!(
+            fetcher.asset_name(&binary),
+            "incredible-squaring-linux-amd64.tar.gz"
+        );
+        let artifact = BinaryCache::new(fetcher.data_dir.binary_cache())
+            .insert(&archive_digest, &archive)
+            .await
+            .unwrap();
+        fetcher
+            .install_artifact(&binary, artifact, &archive_digest, &dest)
+            .await
+            .unwrap();
+
+        // The binary is extracted, and pinned to the digest it was extracted with
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), binary_bytes);
+        assert_eq!(
+            fetcher.expected_digest().unwrap(),
+            Some(BinaryDigest::compute(HashAlgorithm::Sha256, binary_bytes))
+        );
+
+        // A raw asset is linked from the cache as is
+        let (binary, fetcher) = test_fetcher(None, true, dir);
+        assert_eq!(
+            fetcher.asset_name(&binary),
+            "incredible-squaring-linux-amd64"
+        );
+        let raw_digest = BinaryDigest::compute(HashAlgorithm::Sha256, binary_bytes);
+        let artifact = BinaryCache::new(fetcher.data_dir.binary_cache())
+            .insert(&raw_digest, binary_bytes)
+            .await
+            .unwrap();
+        fetcher
+            .install_artifact(&binary, artifact, &raw_digest, &dest)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&dest).await.unwrap(), binary_bytes);
+        assert!(fetcher.extracted_digest.lock().unwrap().is_none());
+    }
 }
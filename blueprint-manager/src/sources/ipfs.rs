@@ -0,0 +1,188 @@
+use crate::config::DataDir;
+use crate::sdk::digest::BinaryDigest;
+use crate::sdk::shutdown::CancellationToken;
+use crate::sdk::utils::{is_windows, msg_to_error};
+use crate::sources::download::{fetch_verified_binary, DownloadRetry};
+use crate::sources::BinarySourceFetcher;
+use async_trait::async_trait;
+use cid::Cid;
+use gadget_sdk::info;
+use std::path::PathBuf;
+
+/// The public gateway binaries are fetched from, unless another one is configured
+pub const DEFAULT_IPFS_GATEWAY: &str = "https://ipfs.io";
+
+/// The multicodec of a raw block, whose bytes are the content itself
+const RAW_CODEC: u64 = 0x55;
+
+/// Fetches a binary from IPFS, through an HTTP gateway.
+///
+/// The binary is verified against the digest embedded in its CID, so the gateway does not need to
+/// be trusted. This requires the CID of a single raw block, e.g. as produced by
+/// `ipfs block put --cid-codec raw --allow-big-block`, since the CID of a UnixFS file is the digest
+/// of its DAG rather than of the binary.
+pub struct IpfsBinaryFetcher {
+    /// The binary CID, as stored on-chain
+    pub cid: Vec<u8>,
+    pub blueprint_id: u64,
+    pub gadget_name: String,
+    /// The base URL of the gateway the binary is fetched from, e.g. [`DEFAULT_IPFS_GATEWAY`]
+    pub gateway: String,
+    /// Where the binary is cached and linked to
+    pub data_dir: DataDir,
+    /// The largest binary, in bytes, buffered in memory rather than streamed to disk
+    pub max_in_memory_size: u64,
+    /// How failed downloads are retried
+    pub retry: DownloadRetry,
+    /// Aborts an in-flight download when the manager shuts down
+    pub cancel: CancellationToken,
+}
+
+impl IpfsBinaryFetcher {
+    /// The URL the binary is served at by the gateway
+    fn download_url(&self, cid: &Cid) -> String {
+        format!("{}/ipfs/{cid}", self.gateway.trim_end_matches('/'))
+    }
+}
+
+/// Parses the binary `cid`, returning it along with the digest of the binary it refers to
+pub fn parse_binary_cid(cid: &[u8]) -> color_eyre::Result<(Cid, BinaryDigest)> {
+    let cid = Cid::try_from(cid).map_err(|err| msg_to_error(format!("Invalid CID: {err}")))?;
+    if cid.codec() != RAW_CODEC {
+        return Err(msg_to_error(format!(
+            "The binary at {cid} cannot be verified: only the CIDs of raw blocks are supported"
+        )));
+    }
+
+    let digest = BinaryDigest::from_multihash(&cid.hash().to_bytes())?;
+    Ok((cid, digest))
+}
+
+#[async_trait]
+impl BinarySourceFetcher for IpfsBinaryFetcher {
+    async fn get_binary(&self) -> color_eyre::Result<PathBuf> {
+        let (cid, expected_digest) = parse_binary_cid(&self.cid)?;
+        let binaries_dir = self.data_dir.binaries();
+        tokio::fs::create_dir_all(&binaries_dir).await?;
+        let mut binary_download_path =
+            binaries_dir.join(format!("protocol-{}-{cid}", self.blueprint_id));
+
+        if is_windows() {
+            let _ = binary_download_path.set_extension("exe");
+        }
+
+        let url = self.download_url(&cid);
+        let client = reqwest::Client::new();
+        fetch_verified_binary(
+            &self.gadget_name,
+            || {
+                info!("Downloading {url} into the binary cache");
+                let request = client.get(&url);
+                async { Ok(request) }
+            },
+            &expected_digest,
+            &binary_download_path,
+            &self.data_dir,
+            self.max_in_memory_size,
+            &self.retry,
+            &self.cancel,
+        )
+        .await?;
+
+        Ok(binary_download_path)
+    }
+
+    fn blueprint_id(&self) -> u64 {
+        self.blueprint_id
+    }
+
+    fn name(&self) -> String {
+        self.gadget_name.clone()
+    }
+
+    fn expected_digest(&self) -> color_eyre::Result<Option<BinaryDigest>> {
+        parse_binary_cid(&self.cid).map(|(_, digest)| Some(digest))
+    }
+
+    fn source_description(&self) -> String {
+        match Cid::try_from(&self.cid[..]) {
+            Ok(cid) => format!("ipfs:{cid}"),
+            Err(_) => format!("ipfs:0x{}", hex::encode(&self.cid)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdk::digest::HashAlgorithm;
+    use crate::sources::download::tests::serve_once;
+    use cid::multihash::Multihash;
+    use std::time::Duration;
+
+    fn raw_cid(binary: &[u8]) -> Cid {
+        let digest = BinaryDigest::compute(HashAlgorithm::Sha256, binary);
+        let hash = Multihash::wrap(HashAlgorithm::Sha256.code(), digest.digest()).unwrap();
+        Cid::new_v1(RAW_CODEC, hash)
+    }
+
+    fn fetcher(cid: &Cid, gateway: String, data_dir: &std::path::Path) -> IpfsBinaryFetcher {
+        IpfsBinaryFetcher {
+            cid: cid.to_bytes(),
+            blueprint_id: 7,
+            gadget_name: "incredible-squaring".to_string(),
+            gateway,
+            data_dir: DataDir::new(data_dir),
+            max_in_memory_size: 16 * 1024 * 1024,
+            retry: DownloadRetry {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    #[test]
+    fn test_only_raw_block_cids_are_accepted() {
+        let binary = b"gadget binary";
+        let cid = raw_cid(binary);
+        let (parsed, digest) = parse_binary_cid(&cid.to_bytes()).unwrap();
+        assert_eq!(parsed, cid);
+        assert_eq!(digest, BinaryDigest::compute(HashAlgorithm::Sha256, binary));
+
+        // The CID of a UnixFS file is the digest of its DAG, not of the binary
+        let dag_pb = Cid::new_v1(0x70, *cid.hash());
+        let err = parse_binary_cid(&dag_pb.to_bytes()).unwrap_err();
+        assert!(err.to_string().contains("cannot be verified"));
+
+        assert!(parse_binary_cid(b"not a cid").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_binary_is_fetched_from_the_gateway_and_verified() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let binary = b"#!/bin/sh\necho gadget\n".to_vec();
+        let cid = raw_cid(&binary);
+
+        let (url, server) = serve_once(binary.clone());
+        let gateway = url.trim_end_matches("/gadget").to_string();
+        let path = fetcher(&cid, format!("{gateway}/"), data_dir.path())
+            .get_binary()
+            .await
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), binary);
+        assert!(path.ends_with(format!("protocol-7-{cid}")));
+
+        // A gateway serving other bytes than those the CID refers to is rejected
+        let tampered_cid = raw_cid(b"another binary");
+        let (url, server) = serve_once(binary);
+        let gateway = url.trim_end_matches("/gadget").to_string();
+        let err = fetcher(&tampered_cid, gateway, data_dir.path())
+            .get_binary()
+            .await
+            .unwrap_err();
+        server.join().unwrap();
+        assert!(err.to_string().contains("did not match"));
+    }
+}
@@ -1,15 +1,24 @@
 use crate::config::BlueprintManagerConfig;
 use crate::executor::event_handler::VerifiedBlueprint;
-use crate::gadget::ActiveGadgets;
+use crate::gadget::{ActiveGadget, ActiveGadgetMetadata, ActiveGadgets};
+use crate::sdk::digest::{hash_file, BinaryDigest};
+use crate::sdk::sandbox::Sandbox;
 use crate::sdk::utils::{
     chmod_x_file, generate_process_arguments, generate_running_process_status_handle, is_windows,
+    msg_to_error,
 };
 use async_trait::async_trait;
 use gadget_io::GadgetConfig;
 use gadget_sdk::{error, info, warn};
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 
+pub mod archive;
+pub mod cache;
+pub mod download;
 pub mod github;
+pub mod ipfs;
 pub mod testing;
 
 #[async_trait]
@@ -18,13 +27,24 @@ pub trait BinarySourceFetcher: Send + Sync {
     async fn get_binary(&self) -> color_eyre::Result<PathBuf>;
     fn blueprint_id(&self) -> u64;
     fn name(&self) -> String;
+    /// A human-readable description of where the binary comes from
+    fn source_description(&self) -> String;
+    /// The digest the binary returned by [`Self::get_binary`] must have, if known upfront. The
+    /// binary is checked against it right before it is spawned
+    fn expected_digest(&self) -> color_eyre::Result<Option<BinaryDigest>> {
+        Ok(None)
+    }
 }
 
+/// Starts the services of `blueprint` that are not running yet, or whose configuration changed.
+///
+/// `active_gadgets` is shared with the other blueprints being started, so it is only locked
+/// briefly, and never while fetching or spawning a binary.
 pub async fn handle<'a>(
     blueprint: &VerifiedBlueprint<'a>,
     gadget_config: &GadgetConfig,
     blueprint_manager_opts: &BlueprintManagerConfig,
-    active_gadgets: &mut ActiveGadgets,
+    active_gadgets: &Mutex<ActiveGadgets>,
 ) -> color_eyre::Result<()> {
     let blueprint_source = &blueprint.fetcher;
     let blueprint = &blueprint.blueprint;
@@ -32,85 +52,397 @@ pub async fn handle<'a>(
     let blueprint_id = blueprint_source.blueprint_id();
     let service_str = blueprint_source.name();
 
-    if !active_gadgets.contains_key(&blueprint_id) {
-        let mut binary_download_path = blueprint_source.get_binary().await?;
+    let source = blueprint_source.source_description();
+    let mut binary_download_path: Option<PathBuf> = None;
+
+    for service_id in &dedup_services(&service_str, &blueprint.services) {
+        let sub_service_str = format!("{service_str}-{service_id}");
+        let arguments = generate_process_arguments(
+            gadget_config,
+            blueprint_manager_opts,
+            blueprint_id,
+            *service_id,
+            blueprint.protocol,
+        )?;
 
-        // Ensure the binary is executable
-        if is_windows() {
-            if binary_download_path.extension().is_none() {
-                binary_download_path.set_extension("exe");
+        {
+            let mut active_gadgets = active_gadgets
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if let Some(active) = active_gadgets
+                .get_mut(&blueprint_id)
+                .and_then(|gadgets| gadgets.get_mut(service_id))
+            {
+                if !active.metadata.is_outdated(&source, &arguments) {
+                    continue;
+                }
+
+                info!(
+                    "Configuration for {sub_service_str} changed (source: {} -> {source}), restarting",
+                    active.metadata.source
+                );
+                if !active.abort() {
+                    warn!("Failed to send abort signal to outdated service {sub_service_str}");
+                }
             }
-        } else if let Err(err) = chmod_x_file(&binary_download_path).await {
-            warn!("Failed to chmod +x the binary: {err}");
         }
 
-        for service_id in &blueprint.services {
-            let sub_service_str = format!("{service_str}-{service_id}");
-            let arguments = generate_process_arguments(
-                gadget_config,
-                blueprint_manager_opts,
-                blueprint_id,
-                *service_id,
-                blueprint.protocol,
-            )?;
-
-            // Add required env vars for all child processes/gadgets
-            let mut env_vars = vec![
-                ("RPC_URL".to_string(), gadget_config.url.to_string()),
-                (
-                    "KEYSTORE_URI".to_string(),
-                    blueprint_manager_opts.keystore_uri.clone(),
-                ),
-                ("DATA_DIR".to_string(), gadget_config.keystore_uri.clone()),
-                ("BLUEPRINT_ID".to_string(), format!("{}", blueprint_id)),
-                ("SERVICE_ID".to_string(), format!("{}", service_id)),
-            ];
-
-            // Ensure our child process inherits the current processes' environment vars
-            env_vars.extend(std::env::vars());
-
-            if blueprint.registration_mode {
-                env_vars.push(("REGISTRATION_MODE_ON".to_string(), "true".to_string()));
-            }
+        let binary_download_path = match &binary_download_path {
+            Some(path) => path.clone(),
+            None => {
+                let mut path = blueprint_source.get_binary().await?;
 
-            info!("Starting protocol: {sub_service_str} with args: {arguments:?}");
-
-            // Now that the file is loaded, spawn the process
-            let process_handle = tokio::process::Command::new(&binary_download_path)
-                .kill_on_drop(true)
-                .stdout(std::process::Stdio::inherit()) // Inherit the stdout of this process
-                .stderr(std::process::Stdio::inherit()) // Inherit the stderr of this process
-                .stdin(std::process::Stdio::null())
-                .current_dir(&std::env::current_dir()?)
-                .envs(env_vars)
-                .args(arguments)
-                .spawn()?;
-
-            if blueprint.registration_mode {
-                // We must wait for the process to exit successfully
-                let status = process_handle.wait_with_output().await?;
-                if !status.status.success() {
-                    error!(
-                        "Protocol (registration mode) {sub_service_str} failed to execute: {status:?}"
-                    );
-                } else {
-                    info!(
-                        "***Protocol (registration mode) {sub_service_str} executed successfully***"
-                    );
+                // The binary may have been corrupted or tampered with since it was cached
+                if let Some(expected_digest) = blueprint_source.expected_digest()? {
+                    verify_binary(&path, &expected_digest).await?;
                 }
-            } else {
-                // A normal running gadget binary. Store the process handle and let the event loop handle the rest
 
-                let (status_handle, abort) =
-                    generate_running_process_status_handle(process_handle, &sub_service_str);
+                // Ensure the binary is executable
+                if is_windows() {
+                    if path.extension().is_none() {
+                        path.set_extension("exe");
+                    }
+                } else if let Err(err) = chmod_x_file(&path).await {
+                    warn!("Failed to chmod +x the binary: {err}");
+                }
 
-                active_gadgets
-                    .entry(blueprint_id)
-                    .or_default()
-                    .insert(*service_id, (status_handle, Some(abort)));
+                binary_download_path.insert(path).clone()
             }
+        };
+
+        // Each service keeps its own state under the data directory
+        let data_dir = blueprint_manager_opts.data_dir()?;
+        let service_dir = data_dir.service(&sub_service_str);
+        tokio::fs::create_dir_all(&service_dir).await?;
+
+        // Add required env vars for all child processes/gadgets. The keystores are resolved against
+        // the data directory, since the gadget does not run from the manager's directory. As
+        // before, `DATA_DIR` holds the keystore, while the service directory is `SERVICE_DATA_DIR`
+        let mut env_vars = vec![
+            ("RPC_URL".to_string(), gadget_config.url.to_string()),
+            (
+                "KEYSTORE_URI".to_string(),
+                data_dir.keystore_uri(&blueprint_manager_opts.keystore_uri),
+            ),
+            ("DATA_DIR".to_string(), gadget_config.keystore_uri.clone()),
+            (
+                "SERVICE_DATA_DIR".to_string(),
+                service_dir.display().to_string(),
+            ),
+            ("BLUEPRINT_ID".to_string(), format!("{}", blueprint_id)),
+            ("SERVICE_ID".to_string(), format!("{}", service_id)),
+        ];
+
+        // Ensure our child process inherits the current processes' environment vars
+        env_vars.extend(std::env::vars());
+
+        // Service-specific vars take precedence over the inherited environment
+        env_vars.extend(blueprint_manager_opts.service_env_vars(&sub_service_str));
+
+        if blueprint.registration_mode {
+            env_vars.push(("REGISTRATION_MODE_ON".to_string(), "true".to_string()));
         }
+
+        info!("Starting protocol: {sub_service_str} with args: {arguments:?}");
+
+        // The stderr of a running gadget is piped so that its tail can be reported if it crashes,
+        // while still being forwarded to the logs of this process
+        let stderr = if blueprint.registration_mode {
+            std::process::Stdio::inherit()
+        } else {
+            std::process::Stdio::piped()
+        };
+
+        // Now that the file is loaded, spawn the process
+        let process_handle = Sandbox::from_config(blueprint_manager_opts)?
+            .command(&binary_download_path)
+            .kill_on_drop(true)
+            .stdout(std::process::Stdio::inherit()) // Inherit the stdout of this process
+            .stderr(stderr)
+            .stdin(std::process::Stdio::null())
+            .current_dir(&service_dir)
+            .envs(env_vars)
+            .args(&arguments)
+            .spawn()?;
+
+        if blueprint.registration_mode {
+            // We must wait for the process to exit successfully
+            let status = process_handle.wait_with_output().await?;
+            if !status.status.success() {
+                error!(
+                    "Protocol (registration mode) {sub_service_str} failed to execute: {status:?}"
+                );
+            } else {
+                info!("***Protocol (registration mode) {sub_service_str} executed successfully***");
+            }
+        } else {
+            // A normal running gadget binary. Store the process handle and let the event loop handle the rest
+
+            let (status_handle, exit_report, abort) =
+                generate_running_process_status_handle(process_handle, &sub_service_str);
+
+            let metadata = ActiveGadgetMetadata {
+                blueprint_id,
+                service_id: *service_id,
+                blueprint_name: blueprint.name.clone(),
+                source: source.clone(),
+                binary_path: binary_download_path.clone(),
+                arguments,
+            };
+
+            active_gadgets
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(blueprint_id)
+                .or_default()
+                .insert(
+                    *service_id,
+                    ActiveGadget {
+                        status: status_handle,
+                        exit_report,
+                        abort_handle: Some(abort),
+                        metadata,
+                    },
+                );
+        }
+    }
+
+    Ok(())
+}
+
+/// Hashes the binary at `path`, failing unless it matches `expected_digest`
+async fn verify_binary(path: &Path, expected_digest: &BinaryDigest) -> color_eyre::Result<()> {
+    let digest = hash_file(path, expected_digest).await?;
+    if digest != *expected_digest {
+        error!(
+            "Binary hash {digest} mismatched expected hash of {expected_digest} at {}",
+            path.display()
+        );
+        return Err(msg_to_error(format!(
+            "The binary at {} does not match its expected hash, refusing to run it",
+            path.display()
+        )));
     }
 
     Ok(())
 }
+
+/// Removes duplicate service ids, keeping the first occurrence of each, so that a service
+/// listed twice on-chain is only fetched and spawned once
+fn dedup_services(service_str: &str, services: &[u64]) -> Vec<u64> {
+    let mut seen = HashSet::with_capacity(services.len());
+    let deduped = services
+        .iter()
+        .copied()
+        .filter(|service_id| seen.insert(*service_id))
+        .collect::<Vec<_>>();
+
+    if deduped.len() != services.len() {
+        warn!(
+            "Collapsed {} duplicate service entries for {service_str}: {services:?} -> {deduped:?}",
+            services.len() - deduped.len()
+        );
+    }
+
+    deduped
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::gadget::native::FilteredBlueprint;
+    use crate::sdk::digest::HashAlgorithm;
+    use gadget_sdk::config::Protocol;
+    use structopt::StructOpt;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::bounded_collections::bounded_vec::BoundedVec;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::{
+        Gadget, NativeGadget,
+    };
+
+    /// A gadget binary running a shell script, in the temporary data directory of a blueprint
+    /// manager, which is removed once dropped
+    #[cfg(unix)]
+    pub(crate) struct ScriptGadget {
+        pub(crate) data_dir: tempfile::TempDir,
+        pub(crate) binary: PathBuf,
+        pub(crate) opts: BlueprintManagerConfig,
+        pub(crate) gadget_config: GadgetConfig,
+    }
+
+    #[cfg(unix)]
+    impl ScriptGadget {
+        /// A manager with a temporary data directory, whose gadget binary is yet to be written
+        pub(crate) fn new() -> Self {
+            let data_dir = tempfile::tempdir().unwrap();
+            let binary = data_dir.path().join("gadget");
+            let data_dir_arg = data_dir.path().display().to_string();
+            let opts = BlueprintManagerConfig::from_iter([
+                "blueprint-manager",
+                "--keystore-uri",
+                "./keystore",
+                "--data-dir",
+                &data_dir_arg,
+            ]);
+            let gadget_config = GadgetConfig::from_iter(["gadget", "--keystore-uri", "./keystore"]);
+            Self {
+                data_dir,
+                binary,
+                opts,
+                gadget_config,
+            }
+        }
+
+        /// Writes the gadget binary, running the shell `script`, and returns its contents
+        pub(crate) fn write(&self, script: &str) -> String {
+            let contents = format!("#!/bin/sh\n{script}");
+            std::fs::write(&self.binary, &contents).unwrap();
+            contents
+        }
+    }
+
+    /// Returns an already cached binary, which must hash to `expected`
+    struct CachedFetcher {
+        binary: PathBuf,
+        expected: BinaryDigest,
+    }
+
+    #[async_trait]
+    impl BinarySourceFetcher for CachedFetcher {
+        async fn get_binary(&self) -> color_eyre::Result<PathBuf> {
+            Ok(self.binary.clone())
+        }
+
+        fn blueprint_id(&self) -> u64 {
+            1
+        }
+
+        fn name(&self) -> String {
+            "cached".into()
+        }
+
+        fn source_description(&self) -> String {
+            "test:cached".into()
+        }
+
+        fn expected_digest(&self) -> color_eyre::Result<Option<BinaryDigest>> {
+            Ok(Some(self.expected.clone()))
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_tampered_binary_is_not_spawned() {
+        let gadget = ScriptGadget::new();
+        let marker = gadget.data_dir.path().join("spawned");
+        let script = gadget.write(&format!("touch {}\n", marker.display()));
+        std::fs::write(&gadget.binary, format!("{script}# tampered\n")).unwrap();
+
+        let blueprint = VerifiedBlueprint {
+            blueprint: FilteredBlueprint {
+                blueprint_id: 1,
+                services: vec![0],
+                gadget: Gadget::Native(NativeGadget {
+                    sources: BoundedVec(Vec::new()),
+                }),
+                name: "cached".into(),
+                registration_mode: false,
+                protocol: Protocol::Tangle,
+            },
+            fetcher: Box::new(CachedFetcher {
+                binary: gadget.binary.clone(),
+                expected: BinaryDigest::compute(HashAlgorithm::Sha256, script.as_bytes()),
+            }),
+        };
+
+        let active_gadgets = Mutex::new(ActiveGadgets::new());
+        let err = handle(
+            &blueprint,
+            &gadget.gadget_config,
+            &gadget.opts,
+            &active_gadgets,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("does not match its expected hash"));
+        assert!(active_gadgets.lock().unwrap().is_empty());
+
+        // Give a spawned binary the time to run
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_relative_keystore_is_resolved_for_the_gadget() {
+        let mut gadget = ScriptGadget::new();
+        let data_dir = gadget.data_dir.path();
+        let report = data_dir.join("keystore-uri");
+        let script = gadget.write(&format!(
+            "printf '%s\\n' \"$KEYSTORE_URI\" \"$DATA_DIR\" \"$SERVICE_DATA_DIR\" \"$PWD\" > {}\n",
+            report.display()
+        ));
+        gadget
+            .opts
+            .resolve_keystore_uri(&mut gadget.gadget_config)
+            .unwrap();
+        let blueprint = VerifiedBlueprint {
+            blueprint: FilteredBlueprint {
+                blueprint_id: 1,
+                services: vec![0],
+                gadget: Gadget::Native(NativeGadget {
+                    sources: BoundedVec(Vec::new()),
+                }),
+                name: "cached".into(),
+                registration_mode: false,
+                protocol: Protocol::Tangle,
+            },
+            fetcher: Box::new(CachedFetcher {
+                binary: gadget.binary.clone(),
+                expected: BinaryDigest::compute(HashAlgorithm::Sha256, script.as_bytes()),
+            }),
+        };
+
+        let active_gadgets = Mutex::new(ActiveGadgets::new());
+        handle(
+            &blueprint,
+            &gadget.gadget_config,
+            &gadget.opts,
+            &active_gadgets,
+        )
+        .await
+        .unwrap();
+        for _ in 0..50 {
+            if std::fs::read_to_string(&report).is_ok_and(|report| report.lines().count() == 4) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        // The gadget runs from its service directory, yet sees the keystore of the manager, under
+        // both `KEYSTORE_URI` and `DATA_DIR` as before
+        let report = std::fs::read_to_string(&report).unwrap();
+        let [keystore_uri, keystore_dir, service_dir, cwd] = report.lines().collect::<Vec<_>>()[..]
+        else {
+            panic!("Unexpected report: {report}");
+        };
+        assert_eq!(PathBuf::from(keystore_uri), data_dir.join("keystore"));
+        assert_eq!(PathBuf::from(keystore_dir), data_dir.join("keystore"));
+        assert_eq!(
+            PathBuf::from(service_dir),
+            data_dir.join("services/cached-0")
+        );
+        assert_eq!(
+            std::fs::canonicalize(cwd).unwrap(),
+            std::fs::canonicalize(service_dir).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_duplicate_services_are_spawned_once() {
+        assert_eq!(
+            dedup_services("incredible-squaring", &[3, 1, 3, 2, 1]),
+            vec![3, 1, 2]
+        );
+        assert_eq!(dedup_services("incredible-squaring", &[1, 2]), vec![1, 2]);
+    }
+}
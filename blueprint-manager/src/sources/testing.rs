@@ -82,6 +82,19 @@ impl BinarySourceFetcher for TestSourceFetcher {
     fn name(&self) -> String {
         self.gadget_name.clone()
     }
+
+    fn source_description(&self) -> String {
+        let TestFetcher {
+            cargo_package,
+            base_path,
+            ..
+        } = &self.fetcher;
+        format!(
+            "testing:{}#{}",
+            String::from_utf8_lossy(&base_path.0 .0),
+            String::from_utf8_lossy(&cargo_package.0 .0)
+        )
+    }
 }
 async fn get_git_repo_root_path() -> color_eyre::Result<PathBuf> {
     // Run a process the determine thw root directory for this repo
@@ -85,10 +85,35 @@ pub async fn run_test_blueprint_manager<T: Send + Clone + 'static>(
     let blueprint_manager_config = BlueprintManagerConfig {
         gadget_config: None,
         keystore_uri: keystore_uri_str.clone(),
+        key_import_file: None,
         verbose: input.verbose,
         pretty: input.pretty,
         instance_id: Some(NAME_IDS[input.instance_id as usize].to_string()),
         test_mode: true,
+        download_url_template: None,
+        github_api: false,
+        github_token: None,
+        self_test: false,
+        service_env: vec![],
+        failure_window_secs: 60,
+        restart_burst: 5,
+        restart_interval_secs: 60,
+        restart_cooldown_secs: 300,
+        restart_policy: blueprint_manager::gadget::RestartPolicy::Always,
+        restart_delay_secs: 1,
+        max_restart_delay_secs: 300,
+        sandbox_helper: None,
+        seccomp_profile: None,
+        data_dir: None,
+        keep_binaries_on_stop: true,
+        max_in_memory_binary_size: 16 * 1024 * 1024,
+        download_retries: 3,
+        download_retry_delay_ms: 500,
+        max_concurrent_starts: 4,
+        binary_signing_keys: vec![],
+        ipfs_gateway: blueprint_manager::sources::ipfs::DEFAULT_IPFS_GATEWAY.to_string(),
+        list_binary_cache: false,
+        clear_binary_cache: vec![],
     };
 
     let gadget_config = GadgetConfig {
@@ -17,6 +17,7 @@ use gadget_sdk::{
         evm::{Config, EventWatcher},
     },
     keystore::Backend,
+    network::replay::{DEFAULT_MESSAGE_TTL, DEFAULT_REPLAY_CACHE_CAPACITY},
     network::setup::{start_p2p_network, NetworkConfig},
     run::GadgetRunner,
     info
@@ -367,6 +368,10 @@ impl GadgetRunner for EigenlayerGadgetRunner<parking_lot::RawRwLock> {
             bind_ip: self.env.bind_addr,
             bind_port: self.env.bind_port,
             topics: vec!["__TESTING_INCREDIBLE_SQUARING".to_string()],
+            session_cipher: None,
+            replay_cache_capacity: DEFAULT_REPLAY_CACHE_CAPACITY,
+            message_ttl: DEFAULT_MESSAGE_TTL,
+            protocol_versions: Default::default(),
         };
 
         let _network: GossipHandle =
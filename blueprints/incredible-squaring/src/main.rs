@@ -86,6 +86,9 @@ impl GadgetRunner for TangleGadgetRunner {
             context: MyContext,
             env: self.env.clone(),
             signer,
+            handled_jobs: Default::default(),
+            job_notifier: Default::default(),
+            result_cache: Default::default(),
         };
 
         let program = TangleEventsWatcher {
@@ -68,6 +68,9 @@ impl GadgetRunner for TangleGadgetRunner {
         let tangle_avs = blueprint::TangleAvsEventHandler {
             service_id: env.service_id.unwrap(),
             signer,
+            handled_jobs: Default::default(),
+            job_notifier: Default::default(),
+            result_cache: Default::default(),
         };
 
         info!("Starting the event watcher ...");
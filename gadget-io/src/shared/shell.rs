@@ -18,6 +18,16 @@ pub enum SupportedChains {
     Mainnet,
 }
 
+impl SupportedChains {
+    /// The SS58 address prefix used by this chain when displaying account IDs
+    pub fn ss58_prefix(&self) -> u16 {
+        match self {
+            SupportedChains::LocalTestnet | SupportedChains::Testnet => 42,
+            SupportedChains::LocalMainnet | SupportedChains::Mainnet => 5845,
+        }
+    }
+}
+
 impl FromStr for SupportedChains {
     type Err = String;
 
@@ -56,6 +56,12 @@ pub fn say_hello(who: Option<String>) -> Result<String, Error> {
     }
 }
 
+/// Greet someone by name, borrowing the decoded string param.
+#[job(id = 4, params(name), result(_))]
+pub fn greet(name: &str) -> Result<String, Error> {
+    Ok(format!("Hello, {name}!"))
+}
+
 // ==================
 //       Hooks
 // ==================
@@ -124,6 +130,20 @@ mod tests {
         eprintln!("{}", super::REGISTRATION_HOOK);
     }
 
+    #[test]
+    fn generated_event_handlers_decode_params() {
+        use gadget_sdk::clients::tangle::runtime::TangleConfig;
+        use gadget_sdk::events_watcher::substrate::EventHandler;
+
+        // Each of these jobs takes params, which the generated handlers decode from the job calls
+        fn assert_event_handler<H: EventHandler<TangleConfig>>() {}
+        assert_event_handler::<super::KeygenEventHandler>();
+        assert_event_handler::<super::SignEventHandler>();
+        assert_event_handler::<super::RefreshEventHandler>();
+        assert_event_handler::<super::SayHelloEventHandler>();
+        assert_event_handler::<super::GreetEventHandler>();
+    }
+
     // #[test]
     // fn example_benchmark() {
     //     super::keygen_2_of_3_benchmark();
@@ -1,7 +1,22 @@
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::{Ident, LitInt};
 
+/// How the generated Tangle event handler handles a job call, besides invoking the job.
+#[derive(Default)]
+pub(crate) struct TangleHandlerOptions<'a> {
+    /// Whether the job has a result to submit on-chain
+    pub submit_result: bool,
+    /// The number of blocks to wait for before submitting the result
+    pub confirmations: Option<&'a LitInt>,
+    /// Whether the job takes a `JobCallContext`
+    pub inject_call_context: bool,
+    /// Whether the results of a block are submitted in a single batch
+    pub batch: bool,
+    /// Whether to record the latency between observing a job call and submitting its result
+    pub record_latency: bool,
+}
+
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn generate_tangle_event_handler(
     fn_name_string: &str,
@@ -12,21 +27,184 @@ pub(crate) fn generate_tangle_event_handler(
     additional_params: &[TokenStream],
     fn_call: &TokenStream,
     event_listener_call: &TokenStream,
+    options: &TangleHandlerOptions<'_>,
 ) -> TokenStream {
+    let TangleHandlerOptions {
+        submit_result,
+        confirmations,
+        inject_call_context,
+        batch,
+        record_latency,
+    } = *options;
+    // Batching and latency only make sense if there are results to submit
+    let batch = batch && submit_result;
+    let record_latency = record_latency && submit_result;
+
+    // The JSON job definition generated along with the handler, see `job_impl`
+    let job_def_name = format_ident!("{}_JOB_DEF", fn_name_string.to_ascii_uppercase());
+
+    let observe_latency = record_latency.then(|| {
+        quote! {
+            gadget_sdk::prometheus::JOB_SUBMISSION_LATENCY
+                .with_label_values(&[&(#job_id).to_string()])
+                .observe(job_observed_at.elapsed().as_secs_f64());
+        }
+    });
+    let observed_at = record_latency.then(|| {
+        quote! {
+            let job_observed_at = std::time::Instant::now();
+        }
+    });
+
+    let wait_for_confirmations = confirmations.map(|confirmations| {
+        quote! {
+            gadget_sdk::events_watcher::substrate::wait_for_confirmations(
+                &client,
+                block_number,
+                #confirmations,
+            )
+            .await?;
+        }
+    });
+
+    let call_context = inject_call_context.then(|| {
+        quote! {
+            let job_call_context = gadget_sdk::events_watcher::tangle::JobCallContext {
+                service_id: self.service_id,
+                call_id: call.call_id,
+                block_number,
+                block_hash: events.block_hash(),
+            };
+        }
+    });
+
+    // A result is cached until it is submitted, so that a call handled again after a failed
+    // submission does not run the job a second time
+    let (input_hash, run_job) = if submit_result {
+        (
+            quote! {
+                let input_hash =
+                    gadget_sdk::events_watcher::tangle::JobResultCache::input_hash(&call.args);
+            },
+            quote! {
+                let result = match self.result_cache.get(self.service_id, call.call_id, &input_hash) {
+                    Some(result) => {
+                        ::gadget_sdk::info!(
+                            "Reusing the cached result of job call: sid={}, call_id={}",
+                            self.service_id,
+                            call.call_id
+                        );
+                        result
+                    }
+                    None => {
+                        #fn_call
+                        let mut result = Vec::new();
+                        #(#result_tokens)*
+                        self.result_cache.insert(self.service_id, call.call_id, input_hash, &result);
+                        result
+                    }
+                };
+            },
+        )
+    } else {
+        (quote! {}, fn_call.clone())
+    };
+
+    let submission = if batch {
+        // Queue the result, it is submitted along with the other calls of this block
+        quote! {
+            batch.push((
+                call.call_id,
+                RuntimeCall::Services(ServicesCall::submit_result {
+                    service_id: self.service_id,
+                    call_id: call.call_id,
+                    result,
+                }),
+            ));
+        }
+    } else if submit_result {
+        quote! {
+            #wait_for_confirmations
+            let response =
+                TangleApi::tx()
+                    .services()
+                    .submit_result(self.service_id, call.call_id, result);
+            gadget_sdk::tx::tangle::send(&client, &self.signer, &response).await?;
+            #observe_latency
+        }
+    } else {
+        // The job has no result, so there is nothing to submit on-chain
+        quote! {
+            let _ = job_result;
+        }
+    };
+
+    let (batch_init, mark_handled, batch_submission) = if batch {
+        (
+            quote! {
+                use gadget_sdk::tangle_subxt::tangle_testnet_runtime::api::runtime_types::{
+                    pallet_services::module::Call as ServicesCall,
+                    tangle_testnet_runtime::RuntimeCall,
+                };
+                let mut batch = Vec::new();
+            },
+            quote! {},
+            quote! {
+                if !batch.is_empty() {
+                    #wait_for_confirmations
+                    let (call_ids, calls): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+                    ::gadget_sdk::info!(
+                        "Submitting {} job results in a batch: #{block_number}",
+                        call_ids.len()
+                    );
+                    // `batch_all` is atomic, so the calls are either all submitted or none is
+                    let response = TangleApi::tx().utility().batch_all(calls);
+                    gadget_sdk::tx::tangle::send(&client, &self.signer, &response).await?;
+                    for call_id in call_ids {
+                        #observe_latency
+                        self.handled_jobs.insert(self.service_id, call_id).await?;
+                        self.result_cache.remove(self.service_id, call_id);
+                    }
+                }
+            },
+        )
+    } else {
+        (
+            quote! {},
+            quote! {
+                self.handled_jobs.insert(self.service_id, call_id).await?;
+                self.result_cache.remove(self.service_id, call_id);
+            },
+            quote! {},
+        )
+    };
+
     quote! {
         /// Event handler for the function
         #[doc = "[`"]
         #[doc = #fn_name_string]
         #[doc = "`]"]
-        pub struct #struct_name {
+        ///
+        /// Generic over the signer `S` submitting the job results, which defaults to an sr25519
+        /// pair signer.
+        pub struct #struct_name<S = gadget_sdk::keystore::TanglePairSigner<gadget_sdk::keystore::sp_core_subxt::sr25519::Pair>> {
             pub service_id: u64,
-            pub signer: gadget_sdk::keystore::TanglePairSigner<gadget_sdk::keystore::sp_core_subxt::sr25519::Pair>,
+            pub signer: S,
+            /// The job calls that were already handled, which are skipped if seen again
+            pub handled_jobs: gadget_sdk::events_watcher::tangle::HandledJobs,
+            /// Notified as the job calls are observed, started, completed or failed
+            pub job_notifier: gadget_sdk::events_watcher::tangle::JobNotifier,
+            /// Keeps the results of job calls until they are submitted, disabled by default
+            pub result_cache: gadget_sdk::events_watcher::tangle::JobResultCache,
             #(#additional_params)*
         }
 
         #[automatically_derived]
         #[async_trait::async_trait]
-        impl gadget_sdk::events_watcher::substrate::EventHandler<gadget_sdk::clients::tangle::runtime::TangleConfig> for #struct_name {
+        impl<S> gadget_sdk::events_watcher::substrate::EventHandler<gadget_sdk::clients::tangle::runtime::TangleConfig> for #struct_name<S>
+        where
+            S: gadget_sdk::tangle_subxt::subxt::tx::Signer<gadget_sdk::clients::tangle::runtime::TangleConfig> + Send + Sync + 'static,
+        {
             async fn can_handle_events(
                 &self,
                 events: gadget_sdk::tangle_subxt::subxt::events::Events<gadget_sdk::clients::tangle::runtime::TangleConfig>,
@@ -52,6 +230,21 @@ pub(crate) fn generate_tangle_event_handler(
                 Ok(has_event)
             }
 
+            /// Checks that the job parameters match the job's on-chain signature, before any job
+            /// call is decoded against them
+            async fn init(
+                &self,
+                client: &gadget_sdk::tangle_subxt::subxt::OnlineClient<gadget_sdk::clients::tangle::runtime::TangleConfig>,
+            ) -> Result<(), gadget_sdk::events_watcher::Error> {
+                gadget_sdk::events_watcher::tangle::validate_job_signature(
+                    client,
+                    self.service_id,
+                    #job_id,
+                    #job_def_name,
+                )
+                .await
+            }
+
             async fn handle_events(
                 &self,
                 client: gadget_sdk::tangle_subxt::subxt::OnlineClient<gadget_sdk::clients::tangle::runtime::TangleConfig>,
@@ -60,6 +253,7 @@ pub(crate) fn generate_tangle_event_handler(
                     u64
                 ),
             ) -> Result<(), gadget_sdk::events_watcher::Error> {
+                use gadget_sdk::events_watcher::tangle::JobLifecycleStatus;
                 use gadget_sdk::tangle_subxt::{
                     subxt,
                     tangle_testnet_runtime::api::{
@@ -73,6 +267,7 @@ pub(crate) fn generate_tangle_event_handler(
                 };
 
                 ::gadget_sdk::info!("Handling actionable events ...");
+                #observed_at
 
                 let job_events: Vec<_> = events
                     .find::<JobCalled>()
@@ -81,24 +276,280 @@ pub(crate) fn generate_tangle_event_handler(
                         event.service_id == self.service_id && event.job == #job_id
                     })
                     .collect();
+                #batch_init
                 for call in job_events {
+                    if self.handled_jobs.contains(self.service_id, call.call_id).await? {
+                        ::gadget_sdk::info!(
+                            "Skipping already handled job call: sid={}, call_id={}",
+                            self.service_id,
+                            call.call_id
+                        );
+                        continue;
+                    }
+
                     ::gadget_sdk::info!("Handling JobCalled Events: #{block_number}");
 
+                    let call_id = call.call_id;
+                    self.job_notifier.notify(self.service_id, #job_id, call_id, JobLifecycleStatus::Observed);
+                    // The params are decoded within the loop, since a call whose arguments do not
+                    // match them is skipped with `continue`
+                    #input_hash
                     let mut args_iter = call.args.into_iter();
                     #(#params_tokens)*
-                    #fn_call
-
-                    let mut result = Vec::new();
-                    #(#result_tokens)*
-
-                    let response =
-                        TangleApi::tx()
-                            .services()
-                            .submit_result(self.service_id, call.call_id, result);
-                    gadget_sdk::tx::tangle::send(&client, &self.signer, &response).await?;
+                    // Run the job in its own scope, so that its failures are reported as well, and
+                    // its logs carry the ids of the call
+                    let outcome = ::gadget_sdk::logging::with_job_span(self.service_id, #job_id, call_id, async {
+                        #call_context
+                        self.job_notifier.notify(self.service_id, #job_id, call.call_id, JobLifecycleStatus::Started);
+                        #run_job
+                        #submission
+                        Ok::<(), gadget_sdk::events_watcher::Error>(())
+                    })
+                    .await;
+                    if let Err(err) = outcome {
+                        self.job_notifier.notify(
+                            self.service_id,
+                            #job_id,
+                            call_id,
+                            JobLifecycleStatus::Failed(err.to_string()),
+                        );
+                        return Err(err);
+                    }
+                    self.job_notifier.notify(self.service_id, #job_id, call_id, JobLifecycleStatus::Completed);
+                    #mark_handled
                 }
+                #batch_submission
                 Ok(())
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::{format_ident, ToTokens};
+
+    fn expand(submit_result: bool) -> String {
+        expand_with(&TangleHandlerOptions {
+            submit_result,
+            ..Default::default()
+        })
+    }
+
+    fn expand_with(options: &TangleHandlerOptions<'_>) -> String {
+        let job_id: LitInt = syn::parse_quote!(0);
+        let params = [crate::tangle::field_type_to_param_token(
+            &format_ident!("param0"),
+            &gadget_blueprint_proc_macro_core::FieldType::Uint64,
+        )];
+        generate_tangle_event_handler(
+            "side_effect",
+            &format_ident!("SideEffectEventHandler"),
+            &job_id,
+            &params,
+            &[quote! { result.push(Field::Uint64(job_result)); }],
+            &[],
+            &quote! { let job_result = side_effect(param0)?; },
+            &quote! {},
+            options,
+        )
+        .to_string()
+    }
+
+    #[test]
+    fn test_void_job_does_not_submit_a_result() {
+        assert!(expand(true).contains("submit_result"));
+        assert!(!expand(false).contains("submit_result"));
+        assert!(!expand(false).contains("gadget_sdk :: tx :: tangle :: send"));
+    }
+
+    #[test]
+    fn test_confirmations_are_awaited_before_submitting() {
+        let confirmations: LitInt = syn::parse_quote!(3);
+        let expanded = expand_with(&TangleHandlerOptions {
+            submit_result: true,
+            confirmations: Some(&confirmations),
+            ..Default::default()
+        });
+        let wait = expanded.find("wait_for_confirmations").unwrap();
+        let submit = expanded.find("submit_result").unwrap();
+        assert!(wait < submit);
+
+        assert!(!expand(true).contains("wait_for_confirmations"));
+    }
+
+    #[test]
+    fn test_call_context_is_only_built_when_requested() {
+        let expanded = expand_with(&TangleHandlerOptions {
+            submit_result: true,
+            inject_call_context: true,
+            ..Default::default()
+        });
+        let context = expanded.find("let job_call_context").unwrap();
+        let call = expanded.find("side_effect (param0)").unwrap();
+        assert!(context < call);
+        assert!(expanded.contains("call_id : call . call_id"));
+
+        assert!(!expand(true).contains("job_call_context"));
+    }
+
+    #[test]
+    fn test_batched_results_are_submitted_once_per_block() {
+        let expanded = expand_with(&TangleHandlerOptions {
+            submit_result: true,
+            batch: true,
+            ..Default::default()
+        });
+        let queued = expanded.find("batch . push").unwrap();
+        let submit = expanded.find("utility () . batch_all").unwrap();
+        let handled = expanded.find("handled_jobs . insert").unwrap();
+        assert!(queued < submit && submit < handled);
+        assert_eq!(
+            expanded
+                .matches("gadget_sdk :: tx :: tangle :: send")
+                .count(),
+            1
+        );
+
+        // Per-call submission stays the default
+        let expanded = expand(true);
+        assert!(!expanded.contains("batch_all"));
+        assert!(expanded.contains("submit_result (self . service_id"));
+    }
+
+    #[test]
+    fn test_handler_is_generic_over_the_signer() {
+        let file: syn::File = syn::parse_str(&expand(true)).unwrap();
+        let (item_struct, item_impl) = match &file.items[..] {
+            [syn::Item::Struct(item_struct), syn::Item::Impl(item_impl)] => {
+                (item_struct, item_impl)
+            }
+            _ => panic!("Expected the handler struct and its EventHandler impl"),
+        };
+
+        // Existing handlers keep using the sr25519 pair signer
+        let Some(syn::GenericParam::Type(signer)) = item_struct.generics.params.first() else {
+            panic!("Expected a signer type parameter");
+        };
+        assert_eq!(signer.ident, "S");
+        assert!(signer.default.to_token_stream().to_string().ends_with(
+            "TanglePairSigner < gadget_sdk :: keystore :: sp_core_subxt :: sr25519 :: Pair >"
+        ));
+
+        // While any other signer, e.g. an ECDSA one, can be used instead
+        assert_eq!(
+            item_impl.self_ty.to_token_stream().to_string(),
+            "SideEffectEventHandler < S >"
+        );
+        let bounds = item_impl
+            .generics
+            .where_clause
+            .to_token_stream()
+            .to_string();
+        assert!(bounds.contains("S : gadget_sdk :: tangle_subxt :: subxt :: tx :: Signer"));
+    }
+
+    #[test]
+    fn test_latency_is_recorded_after_submitting() {
+        let expanded = expand_with(&TangleHandlerOptions {
+            submit_result: true,
+            record_latency: true,
+            ..Default::default()
+        });
+        let observed = expanded.find("let job_observed_at").unwrap();
+        let submit = expanded.find("gadget_sdk :: tx :: tangle :: send").unwrap();
+        let recorded = expanded.find("JOB_SUBMISSION_LATENCY").unwrap();
+        assert!(observed < submit && submit < recorded);
+
+        assert!(!expand(true).contains("JOB_SUBMISSION_LATENCY"));
+    }
+
+    #[test]
+    fn test_job_lifecycle_is_notified() {
+        let expanded = expand(true);
+        let observed = expanded.find("JobLifecycleStatus :: Observed").unwrap();
+        let started = expanded.find("JobLifecycleStatus :: Started").unwrap();
+        let call = expanded.find("side_effect (param0)").unwrap();
+        let submit = expanded.find("gadget_sdk :: tx :: tangle :: send").unwrap();
+        let failed = expanded.find("JobLifecycleStatus :: Failed").unwrap();
+        let completed = expanded.find("JobLifecycleStatus :: Completed").unwrap();
+        let handled = expanded.find("handled_jobs . insert").unwrap();
+        assert!(observed < started && started < call && call < submit);
+        assert!(submit < failed && failed < completed && completed < handled);
+    }
+
+    #[test]
+    fn test_job_signature_is_validated_on_init() {
+        let expanded = expand(true);
+        let init = expanded.find("async fn init").unwrap();
+        let validate = expanded
+            .find("gadget_sdk :: events_watcher :: tangle :: validate_job_signature (client , self . service_id , 0 , SIDE_EFFECT_JOB_DEF ,")
+            .unwrap();
+        assert!(init < validate);
+    }
+
+    #[test]
+    fn test_cached_result_is_reused_instead_of_running_the_job() {
+        let expanded = expand(true);
+        let hashed = expanded
+            .find("JobResultCache :: input_hash (& call . args)")
+            .unwrap();
+        let args = expanded.find("call . args . into_iter ()").unwrap();
+        let cached = expanded.find("self . result_cache . get").unwrap();
+        let call = expanded.find("side_effect (param0)").unwrap();
+        let cache = expanded.find("self . result_cache . insert").unwrap();
+        let submit = expanded.find("gadget_sdk :: tx :: tangle :: send").unwrap();
+        let removed = expanded.find("self . result_cache . remove").unwrap();
+        assert!(hashed < args && args < cached && cached < call);
+        assert!(call < cache && cache < submit && submit < removed);
+
+        // Jobs without a result have nothing to cache
+        assert!(!expand(false).contains("result_cache . get"));
+    }
+
+    #[test]
+    fn test_job_runs_within_its_span() {
+        let expanded = expand(true);
+        let decoded = expanded
+            .find("let Some (Field :: Uint64 (param0)) = args_iter . next () else { continue ; } ;")
+            .unwrap();
+        let span = expanded
+            .find("gadget_sdk :: logging :: with_job_span (self . service_id , 0 , call_id")
+            .unwrap();
+        let call = expanded.find("side_effect (param0)").unwrap();
+        let submit = expanded.find("gadget_sdk :: tx :: tangle :: send").unwrap();
+        // The params are decoded outside of the span, where `continue` skips to the next call
+        assert!(decoded < span && span < call && call < submit);
+    }
+
+    #[test]
+    fn test_params_are_not_decoded_within_an_async_block() {
+        let file: syn::File = syn::parse_str(&expand(true)).unwrap();
+        let mut visitor = ContinueInAsync::default();
+        syn::visit::visit_file(&mut visitor, &file);
+        assert_eq!(visitor.continues_in_async, 0);
+    }
+
+    /// Counts the `continue`s within `async` blocks, which do not compile
+    #[derive(Default)]
+    struct ContinueInAsync {
+        async_depth: usize,
+        continues_in_async: usize,
+    }
+
+    impl<'ast> syn::visit::Visit<'ast> for ContinueInAsync {
+        fn visit_expr_async(&mut self, expr: &'ast syn::ExprAsync) {
+            self.async_depth += 1;
+            syn::visit::visit_expr_async(self, expr);
+            self.async_depth -= 1;
+        }
+
+        fn visit_expr_continue(&mut self, expr: &'ast syn::ExprContinue) {
+            if self.async_depth > 0 {
+                self.continues_in_async += 1;
+            }
+            syn::visit::visit_expr_continue(self, expr);
+        }
+    }
+}
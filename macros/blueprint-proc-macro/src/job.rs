@@ -1,5 +1,5 @@
 use crate::event_listener::eigenlayer::generate_eigenlayer_event_handler;
-use crate::event_listener::tangle::generate_tangle_event_handler;
+use crate::event_listener::tangle::{generate_tangle_event_handler, TangleHandlerOptions};
 use crate::shared::{pascal_case, type_to_field_type};
 use gadget_blueprint_proc_macro_core::{FieldType, JobDefinition, JobMetadata, JobResultVerifier};
 use indexmap::{IndexMap, IndexSet};
@@ -25,10 +25,22 @@ mod kw {
     syn::custom_keyword!(event_converter);
     syn::custom_keyword!(callback);
     syn::custom_keyword!(skip_codegen);
+    syn::custom_keyword!(no_result);
+    syn::custom_keyword!(confirmations);
+    syn::custom_keyword!(batch);
+    syn::custom_keyword!(metrics);
 }
 
 /// Job Macro implementation
 pub(crate) fn job_impl(args: &JobArgs, input: &ItemFn) -> syn::Result<TokenStream> {
+    // `#[job_params]` is only read by this macro, so it is stripped from the emitted function
+    let job_params = job_params_attr(input)?;
+    let mut input = input.clone();
+    input
+        .attrs
+        .retain(|attr| !attr.path().is_ident("job_params"));
+    let input = &input;
+
     // Extract function name and arguments
     let fn_name = &input.sig.ident;
     let fn_name_string = fn_name.to_string();
@@ -79,6 +91,42 @@ pub(crate) fn job_impl(args: &JobArgs, input: &ItemFn) -> syn::Result<TokenStrea
         }
     }
 
+    if args.event_handler.is_eigenlayer() {
+        if let Some(ty) = param_types.values().find(|ty| is_job_call_context(ty)) {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "`JobCallContext` is only available to jobs handling Tangle events",
+            ));
+        }
+    }
+
+    if let Some(ref params_ty) = job_params {
+        if args.event_handler.is_eigenlayer() {
+            return Err(syn::Error::new_spanned(
+                params_ty,
+                "`#[job_params]` is only available to jobs handling Tangle events",
+            ));
+        }
+        let [param] = &args.params[..] else {
+            return Err(syn::Error::new_spanned(
+                params_ty,
+                "jobs using `#[job_params]` must take their parameters as a single struct, e.g. `params(args)`",
+            ));
+        };
+        let matches_struct = param_types
+            .get(param)
+            .is_some_and(|ty| is_params_struct(ty, params_ty));
+        if !matches_struct {
+            return Err(syn::Error::new_spanned(
+                param,
+                format!(
+                    "expected the job parameter to be of type `{}`",
+                    params_ty.to_token_stream()
+                ),
+            ));
+        }
+    }
+
     let (event_handler_args, event_handler_arg_types) = get_event_handler_args(&param_types, args);
     // Generate Event Listener, if not being skipped
     let mut event_listener_call = None;
@@ -122,6 +170,12 @@ pub(crate) fn job_impl(args: &JobArgs, input: &ItemFn) -> syn::Result<TokenStrea
     let job_id = &args.id;
     let params_type = args.params_to_field_types(&param_types)?;
     let result_type = args.result_to_field_types(result)?;
+    // Jobs returning `()`, or marked with `no_result`, have nothing to submit on-chain
+    let result_type = if args.no_result || result_type.iter().all(|t| *t == FieldType::Void) {
+        Vec::new()
+    } else {
+        result_type
+    };
 
     // Generate Event Handler, if not being skipped
     let event_handler_gen = if args.skip_codegen {
@@ -134,6 +188,7 @@ pub(crate) fn job_impl(args: &JobArgs, input: &ItemFn) -> syn::Result<TokenStrea
             &param_types,
             &params_type,
             &result_type,
+            job_params.as_ref(),
             event_listener_call,
         )
     };
@@ -191,13 +246,62 @@ pub(crate) fn job_impl(args: &JobArgs, input: &ItemFn) -> syn::Result<TokenStrea
     Ok(gen.into())
 }
 
+/// Whether `ty` is the [`JobCallContext`](gadget_sdk::events_watcher::tangle::JobCallContext),
+/// which is injected by the event handler rather than stored in it.
+fn is_job_call_context(ty: &Type) -> bool {
+    let ty = match ty {
+        Type::Reference(r) => &*r.elem,
+        ty => ty,
+    };
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|seg| seg.ident == "JobCallContext"),
+        _ => false,
+    }
+}
+
+/// Reads the `#[job_params(MyStruct)]` attribute of a job, naming the struct that all the
+/// arguments of a job call are decoded into.
+fn job_params_attr(input: &ItemFn) -> syn::Result<Option<Type>> {
+    let mut attrs = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("job_params"));
+    let Some(attr) = attrs.next() else {
+        return Ok(None);
+    };
+    if let Some(duplicate) = attrs.next() {
+        return Err(syn::Error::new_spanned(
+            duplicate,
+            "`#[job_params]` can only be given once",
+        ));
+    }
+    attr.parse_args().map(Some)
+}
+
+/// Whether the job parameter of type `ty` is the `#[job_params]` struct `params_ty`
+fn is_params_struct(ty: &Type, params_ty: &Type) -> bool {
+    let ty = match ty {
+        Type::Reference(r) => &*r.elem,
+        ty => ty,
+    };
+    ty.to_token_stream().to_string() == params_ty.to_token_stream().to_string()
+}
+
 /// Get all the params names inside the param_types map
 /// and not in the params list to be added to the event handler.
 fn get_event_handler_args<'a>(
     param_types: &'a IndexMap<Ident, Type>,
     job_args: &'a JobArgs,
 ) -> (Vec<&'a Ident>, Vec<&'a Type>) {
-    let x = param_types.keys().collect::<IndexSet<_>>();
+    let x = param_types
+        .iter()
+        .filter(|(_, ty)| !is_job_call_context(ty))
+        .map(|(ident, _)| ident)
+        .collect::<IndexSet<_>>();
     let y = job_args.params.iter().collect::<IndexSet<_>>();
     let event_handler_args = x.difference(&y).copied().collect::<Vec<_>>();
     let event_handler_types = event_handler_args
@@ -215,6 +319,7 @@ pub fn generate_event_handler_for(
     param_types: &IndexMap<Ident, Type>,
     params: &[FieldType],
     result: &[FieldType],
+    job_params: Option<&Type>,
     event_listener_call: Option<proc_macro2::TokenStream>,
 ) -> proc_macro2::TokenStream {
     let fn_name = &f.sig.ident;
@@ -248,6 +353,13 @@ pub fn generate_event_handler_for(
         .iter()
         .enumerate()
         .map(|(pos_in_all_args, (ident, ty))| {
+            if is_job_call_context(ty) {
+                return match ty {
+                    Type::Reference(_) => quote! { &job_call_context, },
+                    _ => quote! { job_call_context, },
+                };
+            }
+
             // if the current param is not in the additional params, then it is a job param to be passed to the function
 
             let is_job_var = !additional_var_indexes.contains(&pos_in_all_args);
@@ -256,7 +368,11 @@ pub fn generate_event_handler_for(
                 // TODO: Make sure this index properly increments
                 let ident = format_ident!("param{job_var_idx}");
                 job_var_idx += 1;
-                return quote! { #ident, };
+                // Params are decoded into owned values, e.g. a `String` for a `&str` param
+                return match ty {
+                    Type::Reference(_) => quote! { &#ident, },
+                    _ => quote! { #ident, },
+                };
             }
 
             let (is_ref, is_ref_mut) = match ty {
@@ -274,22 +390,37 @@ pub fn generate_event_handler_for(
         })
         .collect::<Vec<_>>();
 
-    let params_tokens = params
-        .iter()
-        .enumerate()
-        .map(|(i, t)| {
-            let ident = format_ident!("param{i}");
-            let index = syn::Index::from(i);
-            match event_handler {
-                EventHandlerArgs::Eigenlayer { .. } => {
-                    quote! {
-                        let #ident = inputs.#index;
+    let params_tokens = if let Some(params_ty) = job_params {
+        // All the arguments are decoded at once, into the single job parameter
+        vec![quote! {
+            let param0 = match <#params_ty as gadget_sdk::events_watcher::tangle::JobParams>::decode_job_args(
+                args_iter.by_ref().collect(),
+            ) {
+                Ok(params) => params,
+                Err(e) => {
+                    ::gadget_sdk::warn!("Failed to decode the job parameters: {e}");
+                    continue;
+                }
+            };
+        }]
+    } else {
+        params
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let ident = format_ident!("param{i}");
+                let index = syn::Index::from(i);
+                match event_handler {
+                    EventHandlerArgs::Eigenlayer { .. } => {
+                        quote! {
+                            let #ident = inputs.#index;
+                        }
                     }
+                    EventHandlerArgs::Tangle => crate::tangle::field_type_to_param_token(&ident, t),
                 }
-                EventHandlerArgs::Tangle => crate::tangle::field_type_to_param_token(&ident, t),
-            }
-        })
-        .collect::<Vec<_>>();
+            })
+            .collect::<Vec<_>>()
+    };
 
     let asyncness = if f.sig.asyncness.is_some() {
         quote! {.await}
@@ -347,6 +478,8 @@ pub fn generate_event_handler_for(
     };
 
     let event_listener_call = event_listener_call.unwrap_or_default();
+    let submit_result = !result.is_empty();
+    let inject_call_context = param_types.values().any(is_job_call_context);
     if event_handler.is_eigenlayer() {
         generate_eigenlayer_event_handler(
             &fn_name_string,
@@ -367,6 +500,13 @@ pub fn generate_event_handler_for(
             &additional_params,
             &fn_call,
             &event_listener_call,
+            &TangleHandlerOptions {
+                submit_result,
+                confirmations: job_args.confirmations.as_ref(),
+                inject_call_context,
+                batch: job_args.batch,
+                record_latency: job_args.metrics,
+            },
         )
     }
 }
@@ -397,6 +537,22 @@ pub(crate) struct JobArgs {
     /// this is useful if the developer want to impl a custom event handler
     /// for this job.
     skip_codegen: bool,
+    /// Optional: Do not submit a result on-chain, for jobs that only perform side effects.
+    /// This is implied for jobs returning `Result<(), E>`.
+    /// `#[job(no_result)]`
+    no_result: bool,
+    /// Optional: The number of blocks to wait for on top of the block that triggered the job,
+    /// before submitting its result. Has no effect on blocks that are already finalized.
+    /// `#[job(confirmations = 3)]`
+    confirmations: Option<LitInt>,
+    /// Optional: Submit the results of all the calls to this job in a block as a single batch
+    /// extrinsic, instead of one extrinsic per call.
+    /// `#[job(batch)]`
+    batch: bool,
+    /// Optional: Record the time between observing a call to the job and submitting its result,
+    /// in the `job_submission_latency_seconds` Prometheus histogram.
+    /// `#[job(metrics)]`
+    metrics: bool,
 }
 
 impl Parse for JobArgs {
@@ -407,6 +563,10 @@ impl Parse for JobArgs {
         let mut verifier = Verifier::None;
         let mut event_handler = EventHandlerArgs::Tangle;
         let mut skip_codegen = false;
+        let mut no_result = false;
+        let mut confirmations = None;
+        let mut batch = false;
+        let mut metrics = false;
         let mut event_listener = EventListener { listener: None };
 
         while !input.is_empty() {
@@ -428,6 +588,21 @@ impl Parse for JobArgs {
             } else if lookahead.peek(kw::skip_codegen) {
                 let _ = input.parse::<kw::skip_codegen>()?;
                 skip_codegen = true;
+            } else if lookahead.peek(kw::no_result) {
+                let _ = input.parse::<kw::no_result>()?;
+                no_result = true;
+            } else if lookahead.peek(kw::confirmations) {
+                let _ = input.parse::<kw::confirmations>()?;
+                let _ = input.parse::<Token![=]>()?;
+                let value: LitInt = input.parse()?;
+                let _ = value.base10_parse::<u64>()?;
+                confirmations = Some(value);
+            } else if lookahead.peek(kw::batch) {
+                let _ = input.parse::<kw::batch>()?;
+                batch = true;
+            } else if lookahead.peek(kw::metrics) {
+                let _ = input.parse::<kw::metrics>()?;
+                metrics = true;
             } else if lookahead.peek(Token![,]) {
                 let _ = input.parse::<Token![,]>()?;
             } else if lookahead.peek(kw::event_listener) {
@@ -443,10 +618,14 @@ impl Parse for JobArgs {
             return Err(input.error("Missing `params` argument in attribute"));
         }
 
-        let result = result.ok_or_else(|| input.error("Missing 'result' argument in attribute"))?;
+        let result = match result {
+            Some(result) => result,
+            None if no_result => ResultsKind::Types(Vec::new()),
+            None => return Err(input.error("Missing 'result' argument in attribute")),
+        };
 
         if let ResultsKind::Types(ref r) = result {
-            if r.is_empty() {
+            if r.is_empty() && !no_result {
                 return Err(input.error("Expected at least one parameter for the `result` attribute, or `_` to infer the type"));
             }
         }
@@ -458,6 +637,10 @@ impl Parse for JobArgs {
             verifier,
             event_handler,
             skip_codegen,
+            no_result,
+            confirmations,
+            batch,
+            metrics,
             event_listener,
         })
     }
@@ -728,3 +911,134 @@ impl Parse for EventHandlerArgs {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param_types(f: &ItemFn) -> IndexMap<Ident, Type> {
+        f.sig
+            .inputs
+            .iter()
+            .filter_map(|arg| match arg {
+                syn::FnArg::Typed(arg) => match &*arg.pat {
+                    syn::Pat::Ident(pat) => Some((pat.ident.clone(), (*arg.ty).clone())),
+                    _ => None,
+                },
+                syn::FnArg::Receiver(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_job_call_context_is_injected() {
+        let args: JobArgs = syn::parse_str("id = 0, params(x), result(_)").unwrap();
+        let f: ItemFn = syn::parse_quote! {
+            async fn xsquare(
+                x: u64,
+                ctx: gadget_sdk::events_watcher::tangle::JobCallContext,
+                env: GadgetConfiguration,
+            ) -> Result<u64, Infallible> {
+                Ok(x.saturating_pow(2))
+            }
+        };
+        let param_types = param_types(&f);
+
+        let (event_handler_args, _) = get_event_handler_args(&param_types, &args);
+        assert_eq!(event_handler_args, vec!["env"]);
+
+        let expanded = generate_event_handler_for(
+            &f,
+            &args,
+            &param_types,
+            &[FieldType::Uint64],
+            &[FieldType::Uint64],
+            None,
+            None,
+        )
+        .to_string();
+        assert!(expanded.contains("xsquare (param0 , job_call_context , self . env . clone () ,)"));
+        assert!(!expanded.contains("pub ctx"));
+    }
+
+    #[test]
+    fn test_job_params_are_decoded_into_a_struct() {
+        let args: JobArgs = syn::parse_str("id = 0, params(transfer), result(_)").unwrap();
+        let f: ItemFn = syn::parse_quote! {
+            #[job_params(TransferParams)]
+            async fn transfer(
+                transfer: TransferParams,
+                env: GadgetConfiguration,
+            ) -> Result<u64, Infallible> {
+                Ok(transfer.amount)
+            }
+        };
+        let job_params = job_params_attr(&f).unwrap().unwrap();
+        assert_eq!(job_params.to_token_stream().to_string(), "TransferParams");
+
+        let param_types = param_types(&f);
+        assert!(is_params_struct(&param_types[0], &job_params));
+
+        let expanded = generate_event_handler_for(
+            &f,
+            &args,
+            &param_types,
+            &[FieldType::Struct("TransferParams".into(), Vec::new())],
+            &[FieldType::Uint64],
+            Some(&job_params),
+            None,
+        )
+        .to_string();
+        // Every argument (amount, recipient, memo, ...) goes into the struct, decoded at once
+        assert!(expanded.contains(
+            "let param0 = match < TransferParams as gadget_sdk :: events_watcher :: tangle :: JobParams > :: decode_job_args (args_iter . by_ref () . collect () ,)"
+        ));
+        assert!(!expanded.contains("Field :: Struct"));
+        assert!(expanded.contains("transfer (param0 , self . env . clone () ,)"));
+        assert!(!expanded.contains("pub transfer"));
+
+        // Without the attribute, there is nothing to decode the arguments into
+        let f: ItemFn = syn::parse_quote! {
+            fn transfer(transfer: TransferParams) -> Result<u64, Infallible> {
+                Ok(transfer.amount)
+            }
+        };
+        assert!(job_params_attr(&f).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_string_params_are_decoded() {
+        let args: JobArgs = syn::parse_str("id = 0, params(name, greeting), result(_)").unwrap();
+        let f: ItemFn = syn::parse_quote! {
+            fn greet(name: &str, greeting: String) -> Result<String, Infallible> {
+                Ok(format!("{greeting}, {name}!"))
+            }
+        };
+        let param_types = param_types(&f);
+        let params = args.params_to_field_types(&param_types).unwrap();
+        assert_eq!(params, vec![FieldType::String, FieldType::String]);
+
+        let expanded = generate_event_handler_for(
+            &f,
+            &args,
+            &param_types,
+            &params,
+            &[FieldType::String],
+            None,
+            None,
+        )
+        .to_string();
+        for param in ["param0", "param1"] {
+            assert!(expanded.contains(&format!(
+                "let Some (Field :: String (BoundedString (BoundedVec ({param}_inner)))) = args_iter . next () else { continue ; } ;"
+            )));
+            assert!(expanded.contains(&format!(
+                "let {param} = match gadget_sdk :: events_watcher :: tangle :: decode_string_arg ({param}_inner)"
+            )));
+        }
+        // Invalid UTF-8 fails the handler instead of calling the job
+        assert!(expanded.contains("Err (e) => { :: gadget_sdk :: warn ! (\"failed to convert bytes to a valid utf8 string: {e}\") ; return Err (e) ; }"));
+        // The decoded `String` is borrowed for the `&str` param
+        assert!(expanded.contains("greet (& param0 , param1 ,)"));
+    }
+}
@@ -57,21 +57,77 @@ mod tangle;
 ///   a + b
 /// }
 ///
-/// pub struct AddEventHandler {
+/// pub struct AddEventHandler<S = TanglePairSigner<sr25519::Pair>> {
 ///    pub service_id: u64,
-///    pub signer: subxt_signer::sr25519::Keypair,
+///    pub signer: S,
 ///    // ... other fields
 /// }
 /// ```
 /// Addon to the generated code, the `job` macro also generates an Event Handler struct that
 /// implements the `EventHandler` trait for you.
 ///
+/// The generated Event Handler records each job call it submitted a result for in its
+/// `handled_jobs` store, and skips calls it finds there. The default store is in-memory; use a
+/// persistent one such as `FileHandledJobStore` to avoid double submissions across restarts.
+///
+/// Each job call is also reported to the handler's `job_notifier` as it is observed, started,
+/// completed or failed. Nothing is reported by default; use a `JobNotifier` wrapping a
+/// `WebhookNotifier` to POST these events to an external system.
+///
+/// The results of job calls can be kept in the handler's `result_cache` until they are submitted,
+/// so that a call handled again after a failed submission, with identical arguments, reuses its
+/// result rather than running the job again. Use `JobResultCache::enabled()` to turn it on.
+///
+/// Each job call is run within a `job` tracing span carrying its `service_id`, `job_id` and
+/// `call_id`, so that the logs of concurrent job calls can be told apart.
+///
+/// When the event watcher starts, the generated handler compares the job parameters against the
+/// job's signature in the on-chain blueprint of its service, and a warning is logged on mismatch,
+/// before any job call fails to decode.
+///
+/// The `signer` submitting the job results can be any `subxt::tx::Signer` for the Tangle
+/// runtime, e.g. a `TanglePairSigner<ecdsa::Pair>` for blueprints using ECDSA keys.
+///
 /// # Parameters
 /// - `id`: The unique identifier for the job (must be in the range of 0..[`u8::MAX`])
 /// - `params`: The parameters of the job function, must be a tuple of identifiers in the function signature.
 /// - `result`: The result of the job function, must be a type that this job returns.
 ///    also, it can be omitted if the return type is simple to infer, like `u32` or `Vec<u8>` just use `_`.
 /// - `skip_codegen`: A flag to skip the code generation for the job, useful for manual event handling.
+/// - `no_result`: A flag to skip submitting a result on-chain, for jobs that only perform side effects.
+///    This is implied for jobs returning `Result<(), E>`.
+/// - `confirmations`: The number of blocks to wait for on top of the block that called the job before
+///    submitting its result, trading latency for safety against reorgs. This is a no-op for blocks
+///    that are already finalized, such as those delivered by the default finalized-only event watcher.
+/// - `batch`: A flag to submit the results of every call to the job in a block as a single
+///    `utility.batch_all` extrinsic, reducing fees and nonce churn. Results are submitted one
+///    extrinsic per call by default.
+/// - `metrics`: A flag to record the time between observing a call to the job and submitting its
+///    result, in the `job_submission_latency_seconds` Prometheus histogram labeled by job id.
+///
+/// A function parameter of type `gadget_sdk::events_watcher::tangle::JobCallContext` is not part of
+/// the job parameters nor of the generated handler. Instead, it receives the service id, call id,
+/// block number and block hash of the job call being handled.
+///
+/// Jobs with many parameters can take them as a single struct instead, by naming it in a
+/// `#[job_params(MyStruct)]` attribute placed below `#[job]`. All the arguments of a job call are
+/// then decoded into the struct, in the order its fields are declared, through its
+/// `gadget_sdk::events_watcher::tangle::JobParams` implementation. Any struct deriving SCALE
+/// `Decode` implements it.
+///
+/// ```rust,ignore
+/// #[derive(Decode)]
+/// pub struct TransferParams {
+///     amount: u64,
+///     memo: String,
+/// }
+///
+/// #[job(id = 0, params(transfer), result(_))]
+/// #[job_params(TransferParams)]
+/// pub fn transfer(transfer: TransferParams) -> Result<u64, Infallible> {
+///     Ok(transfer.amount)
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn job(args: TokenStream, input: TokenStream) -> TokenStream {
     let args = parse_macro_input!(args as job::JobArgs);
@@ -29,7 +29,8 @@ pub fn ident_to_field_type(ident: &Ident) -> syn::Result<FieldType> {
         "i128" => Ok(FieldType::Int128),
         "f64" => Ok(FieldType::Float64),
         "bool" => Ok(FieldType::Bool),
-        "String" => Ok(FieldType::String),
+        // `&str` params are decoded into a `String` and passed by reference
+        "String" | "str" => Ok(FieldType::String),
         "Bytes" => Ok(FieldType::Bytes),
         "AccountId" => Ok(FieldType::AccountId),
         _ => Err(syn::Error::new_spanned(ident, "unsupported type")),
@@ -41,6 +42,7 @@ pub fn type_to_field_type(ty: &Type) -> syn::Result<FieldType> {
         Type::Array(_) => Err(syn::Error::new_spanned(ty, "TODO: support arrays")),
         Type::Path(inner) => path_to_field_type(&inner.path),
         Type::Reference(type_reference) => type_to_field_type(&type_reference.elem),
+        Type::Tuple(tuple) if tuple.elems.is_empty() => Ok(FieldType::Void),
         _ => Err(syn::Error::new_spanned(ty, "unsupported type")),
     }
 }
@@ -151,4 +153,10 @@ mod tests {
             assert_eq!(pascal_case(i), *e);
         }
     }
+
+    #[test]
+    fn unit_type_is_void() {
+        let ty: Type = syn::parse_quote!(Result<(), String>);
+        assert_eq!(type_to_field_type(&ty).unwrap(), FieldType::Void);
+    }
 }
@@ -50,12 +50,11 @@ pub fn field_type_to_param_token(ident: &Ident, t: &FieldType) -> proc_macro2::T
             quote! {
                 let Some(Field::String(BoundedString(BoundedVec(#inner_ident)))) = args_iter.next() else { continue; };
                 // Convert the BoundedVec to a String
-                let #ident = match String::from_utf8(#inner_ident) {
+                let #ident = match gadget_sdk::events_watcher::tangle::decode_string_arg(#inner_ident) {
                     Ok(s) => s,
                     Err(e) => {
                         ::gadget_sdk::warn!("failed to convert bytes to a valid utf8 string: {e}");
-                        use gadget_sdk::events_watcher::Error;
-                        return Err(Error::Handler(Box::new(e)));
+                        return Err(e);
                     }
                 };
             }
@@ -16,6 +16,38 @@ pub type TangleClient = subxt::OnlineClient<TangleConfig>;
 type TangleBlock = Block<TangleConfig, TangleClient>;
 type TangleBlockStream = subxt::backend::StreamOfResults<TangleBlock>;
 
+/// How long to wait for the connection to a node before giving up, by default.
+pub const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Connect to the node at `url`, failing if the connection is not established within `timeout`.
+///
+/// Without a timeout, connecting to an unreachable node can hang indefinitely.
+///
+/// # Errors
+///
+/// * [`Error::ConnectionTimeout`] if the connection did not complete within `timeout`.
+/// * `url` is not a valid URL, or the connection failed.
+pub async fn connect<U: AsRef<str>>(url: U, timeout: Duration) -> Result<TangleClient, Error> {
+    let url = url.as_ref();
+    match tokio::time::timeout(timeout, TangleClient::from_url(url)).await {
+        Ok(client) => Ok(client?),
+        Err(_) => Err(Error::ConnectionTimeout {
+            endpoint: url.to_string(),
+            timeout,
+        }),
+    }
+}
+
+/// Format an account ID as an SS58 address using the given network `prefix`.
+///
+/// The [`Display`](core::fmt::Display) implementation of [`AccountId32`] always uses the
+/// generic substrate prefix (42), which doesn't match what block explorers show for other networks.
+pub fn account_id_to_ss58(account_id: &AccountId32, prefix: u16) -> String {
+    use sp_core::crypto::{Ss58AddressFormat, Ss58Codec};
+    sp_core::crypto::AccountId32::new(account_id.0)
+        .to_ss58check_with_version(Ss58AddressFormat::custom(prefix))
+}
+
 #[derive(Clone, Debug)]
 pub struct TangleEvent {
     /// Finalized block number.
@@ -37,13 +69,30 @@ pub struct TangleRuntimeClient {
 impl TangleRuntimeClient {
     /// Create a new Tangle runtime client from an RPC url.
     ///
+    /// The connection times out after [`DEFAULT_CONNECTION_TIMEOUT`].
+    ///
     /// # Errors
     ///
     /// * `url` is not a valid URL.
     /// * `url` is not a secure (https:// or wss://) URL.
     /// * `url` cannot be resolved.
+    /// * The connection timed out.
     pub async fn from_url<U: AsRef<str>>(url: U, account_id: AccountId32) -> Result<Self, Error> {
-        let client = TangleClient::from_url(url).await?;
+        Self::from_url_with_timeout(url, account_id, DEFAULT_CONNECTION_TIMEOUT).await
+    }
+
+    /// Create a new Tangle runtime client from an RPC url, giving up on the connection after
+    /// `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// See [`TangleRuntimeClient::from_url`].
+    pub async fn from_url_with_timeout<U: AsRef<str>>(
+        url: U,
+        account_id: AccountId32,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let client = connect(url, timeout).await?;
         Ok(Self::new(client, account_id))
     }
 
@@ -146,3 +195,36 @@ impl Client<TangleEvent> for TangleRuntimeClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connecting_to_an_unresponsive_node_times_out() {
+        // The connection is accepted by the OS, but the node never answers the handshake, as
+        // with an unroutable address
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("ws://{}", listener.local_addr().unwrap());
+
+        let timeout = Duration::from_millis(200);
+        let err = TangleRuntimeClient::from_url_with_timeout(
+            &endpoint,
+            AccountId32::from([0; 32]),
+            timeout,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains(&endpoint));
+        match err {
+            Error::ConnectionTimeout {
+                endpoint: timed_out,
+                timeout: waited,
+            } => {
+                assert_eq!(timed_out, endpoint);
+                assert_eq!(waited, timeout);
+            }
+            err => panic!("Expected a connection timeout, got {err}"),
+        }
+    }
+}
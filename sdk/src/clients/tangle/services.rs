@@ -1,11 +1,20 @@
+use alloc::collections::BTreeMap;
+use crate::clients::tangle::runtime::account_id_to_ss58;
 use crate::error::Error;
+use core::future::Future;
+use core::time::Duration;
+use serde::Serialize;
 use sp_core::Encode;
 use subxt::utils::AccountId32;
 use tangle_subxt::subxt::backend::BlockRef;
 use tangle_subxt::subxt::utils::H256;
 use tangle_subxt::subxt::{Config, OnlineClient};
 use tangle_subxt::tangle_testnet_runtime::api;
+use tangle_subxt::tangle_testnet_runtime::api::runtime_types::bounded_collections::bounded_vec::BoundedVec;
 use tangle_subxt::tangle_testnet_runtime::api::runtime_types::sp_runtime::DispatchError;
+use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::field::{
+    BoundedString, Field,
+};
 use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services;
 use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::ServiceBlueprint;
 
@@ -30,6 +39,222 @@ impl<C: Config> ServicesClient<C> {
 /// A list of services provided by an operator, along with their blueprint
 pub type RpcServicesWithBlueprint = services::RpcServicesWithBlueprint<AccountId32, u64>;
 
+/// A JSON-friendly view of an [`RpcServicesWithBlueprint`]
+///
+/// Bounded strings are decoded as UTF-8 (lossily), and account IDs are rendered
+/// both as SS58 addresses of the chain and as `0x`-prefixed hex.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServicesWithBlueprintJson {
+    /// The ID of the blueprint
+    pub blueprint_id: u64,
+    /// The name of the blueprint, taken from its metadata
+    pub blueprint_name: String,
+    /// The number of jobs defined by the blueprint
+    pub job_count: usize,
+    /// The service instances running this blueprint
+    pub services: Vec<ServiceJson>,
+}
+
+/// A JSON-friendly view of a single service instance
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceJson {
+    /// The ID of the service instance
+    pub id: u64,
+    /// The owner of the service instance
+    pub owner: AccountJson,
+    /// The operators running the service instance
+    pub operators: Vec<AccountJson>,
+    /// The time-to-live of the service instance, in blocks
+    pub ttl: u64,
+}
+
+/// An account ID, encoded as both an SS58 address and hex
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountJson {
+    /// The SS58 address, using the prefix of the chain
+    pub ss58: String,
+    /// The raw 32-byte public key, `0x`-prefixed
+    pub hex: String,
+}
+
+impl AccountJson {
+    /// Render `account` with the SS58 `ss58_prefix` of the chain it belongs to
+    pub fn new(account: &AccountId32, ss58_prefix: u16) -> Self {
+        Self {
+            ss58: account_id_to_ss58(account, ss58_prefix),
+            hex: format!("0x{}", hex::encode(account.0)),
+        }
+    }
+}
+
+impl ServicesWithBlueprintJson {
+    /// Render `value`, with its accounts using the SS58 `ss58_prefix` of the chain
+    pub fn new(value: &RpcServicesWithBlueprint, ss58_prefix: u16) -> Self {
+        let account = |account| AccountJson::new(account, ss58_prefix);
+        let services = value
+            .services
+            .iter()
+            .map(|service| ServiceJson {
+                id: service.id,
+                owner: account(&service.owner),
+                operators: service.operators.0.iter().map(account).collect(),
+                ttl: service.ttl,
+            })
+            .collect();
+
+        Self {
+            blueprint_id: value.blueprint_id,
+            blueprint_name: String::from_utf8_lossy(&value.blueprint.metadata.name.0 .0)
+                .into_owned(),
+            job_count: value.blueprint.jobs.0.len(),
+            services,
+        }
+    }
+}
+
+/// Serialize the results of [`ServicesClient::query_operator_blueprints`] as pretty-printed JSON,
+/// with the SS58 `ss58_prefix` of the chain they were queried from
+///
+/// # Errors
+///
+/// Returns an error if the results could not be serialized
+pub fn services_with_blueprints_to_json(
+    results: &[RpcServicesWithBlueprint],
+    ss58_prefix: u16,
+) -> Result<String, Error> {
+    let json: Vec<ServicesWithBlueprintJson> = results
+        .iter()
+        .map(|result| ServicesWithBlueprintJson::new(result, ss58_prefix))
+        .collect();
+    serde_json::to_string_pretty(&json).map_err(|e| Error::Other(e.to_string()))
+}
+
+/// A node's view of the jobs assigned to an operator at a given block
+///
+/// Job arguments and results are SCALE-encoded and rendered as `0x`-prefixed hex, so the
+/// snapshot can be attached to bug reports and decoded later against the blueprint.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStateSnapshot {
+    /// The block the snapshot was taken at, `0x`-prefixed
+    pub block_hash: String,
+    /// The operator the jobs are assigned to
+    pub operator: AccountJson,
+    /// The call ID the next submitted job will be assigned
+    pub next_job_call_id: u64,
+    /// The jobs of every service instance the operator is running
+    pub services: Vec<ServiceJobsSnapshot>,
+}
+
+/// The jobs called on a single service instance
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceJobsSnapshot {
+    /// The ID of the blueprint
+    pub blueprint_id: u64,
+    /// The ID of the service instance
+    pub service_id: u64,
+    /// The jobs that were called and are still stored on-chain, ordered by call ID
+    pub jobs: Vec<JobCallSnapshot>,
+}
+
+/// A single job call and its result, if one was submitted
+#[derive(Debug, Clone, Serialize)]
+pub struct JobCallSnapshot {
+    /// The ID of the job call
+    pub call_id: u64,
+    /// The index of the job in the blueprint
+    pub job: u8,
+    /// The SCALE-encoded job arguments, `0x`-prefixed
+    pub args: String,
+    /// The SCALE-encoded job result, `0x`-prefixed, if one was submitted
+    pub result: Option<String>,
+}
+
+/// A single field of a job result, as returned by [`ServicesClient::query_job_result`]
+pub type JobResultField = Field<AccountId32>;
+
+/// Typed accessors for the fields of a job result, so callers don't have to pattern-match on
+/// the nested bounded collections themselves
+pub trait JobResultFieldExt {
+    /// The raw bytes, if this is a `Bytes` field (e.g. a public key or a signature)
+    fn as_bytes(&self) -> Option<&[u8]>;
+
+    /// The string, if this is a valid UTF-8 `String` field
+    fn as_str(&self) -> Option<&str>;
+
+    /// The account ID, if this is an `AccountId` field
+    fn as_account_id(&self) -> Option<&AccountId32>;
+}
+
+impl JobResultFieldExt for JobResultField {
+    fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Field::Bytes(BoundedVec(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Field::String(BoundedString(BoundedVec(bytes))) => core::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    fn as_account_id(&self) -> Option<&AccountId32> {
+        match self {
+            Field::AccountId(account) => Some(account),
+            _ => None,
+        }
+    }
+}
+
+/// Sorts the operators of a service into the canonical participant ordering, by public key.
+///
+/// Every operator derives the same ordering regardless of the order the operators were listed
+/// in, the same way the participants of a protocol session are sorted before deriving its
+/// [`SessionCipher`](crate::network::encryption::SessionCipher).
+pub fn canonical_participants(operators: &[AccountId32]) -> Vec<AccountId32> {
+    let mut participants = operators.to_vec();
+    participants.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    participants.dedup();
+    participants
+}
+
+/// The index of `account` in the canonical ordering of `operators`, or `None` if it is not one
+/// of them
+pub fn participant_index(operators: &[AccountId32], account: &AccountId32) -> Option<usize> {
+    canonical_participants(operators)
+        .binary_search_by(|participant| participant.0.cmp(&account.0))
+        .ok()
+}
+
+/// The maximum number of blocks [`ServicesClient::query_jobs_in_range`] scans in one call.
+pub const MAX_JOB_QUERY_RANGE: u64 = 1000;
+
+/// A job event found by [`ServicesClient::query_jobs_in_range`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JobEventRecord {
+    /// The number of the block the event was emitted in
+    pub block_number: u64,
+    /// The ID of the service instance
+    pub service_id: u64,
+    /// The ID of the job call
+    pub call_id: u64,
+    /// The index of the job in the blueprint
+    pub job: u8,
+    /// Whether the job was called, or its result submitted
+    pub kind: JobEventKind,
+}
+
+/// The kind of a [`JobEventRecord`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum JobEventKind {
+    /// The job was submitted by a user (`JobCalled`)
+    Called,
+    /// An operator submitted the result of the job (`JobResultSubmitted`)
+    ResultSubmitted,
+}
+
 impl<C: Config> ServicesClient<C>
 where
     BlockRef<<C as Config>::Hash>: From<BlockRef<H256>>,
@@ -58,6 +283,33 @@ where
         Ok(ret)
     }
 
+    /// Get the index of `account` among the operators of service `service_id`, in the canonical
+    /// participant ordering (see [`canonical_participants`])
+    ///
+    /// Returns `None` if the service doesn't exist, or if `account` is not one of its operators.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service could not be fetched
+    pub async fn participant_index(
+        &self,
+        at: [u8; 32],
+        service_id: u64,
+        account: &AccountId32,
+    ) -> Result<Option<usize>, Error> {
+        let call = api::storage().services().instances(service_id);
+        let at = BlockRef::from_hash(H256::from_slice(&at));
+        let service = self
+            .rpc_client
+            .storage()
+            .at(at)
+            .fetch(&call)
+            .await
+            .map_err(|e| Error::Client(e.to_string()))?;
+
+        Ok(service.and_then(|service| participant_index(&service.operators.0, account)))
+    }
+
     /// Get the services provided by the operator at `address`
     ///
     /// # Errors
@@ -84,6 +336,301 @@ where
         Ok(ret)
     }
 
+    /// Get the result submitted for the job call `call_id` of service `service_id`, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the result could not be fetched
+    pub async fn query_job_result(
+        &self,
+        at: [u8; 32],
+        service_id: u64,
+        call_id: u64,
+    ) -> Result<Option<Vec<JobResultField>>, Error> {
+        let call = api::storage().services().job_results(service_id, call_id);
+        let at = BlockRef::from_hash(H256::from_slice(&at));
+        let ret = self
+            .rpc_client
+            .storage()
+            .at(at)
+            .fetch(&call)
+            .await
+            .map_err(|e| Error::Client(e.to_string()))?
+            .map(|r| r.result);
+
+        Ok(ret)
+    }
+
+    /// Wait until the result of the job call `call_id` of service `service_id` is finalized
+    /// on-chain, returning it
+    ///
+    /// Returns immediately if the result is already on-chain, and otherwise checks every newly
+    /// finalized block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no result was finalized within `timeout`, or if the finalized blocks
+    /// or the result could not be fetched
+    pub async fn wait_for_job_result(
+        &self,
+        service_id: u64,
+        call_id: u64,
+        timeout: Duration,
+    ) -> Result<Vec<JobResultField>, Error> {
+        let call = api::storage().services().job_results(service_id, call_id);
+        let wait = async {
+            // Subscribe first, so that no block is missed between the two lookups
+            let mut blocks = self
+                .rpc_client
+                .blocks()
+                .subscribe_finalized()
+                .await
+                .map_err(|e| Error::Client(e.to_string()))?;
+
+            let latest = self
+                .rpc_client
+                .storage()
+                .at_latest()
+                .await
+                .map_err(|e| Error::Client(e.to_string()))?;
+            if let Some(result) = latest
+                .fetch(&call)
+                .await
+                .map_err(|e| Error::Client(e.to_string()))?
+            {
+                return Ok(result.result);
+            }
+
+            while let Some(block) = blocks.next().await {
+                let block = block.map_err(|e| Error::Client(e.to_string()))?;
+                let result = self
+                    .rpc_client
+                    .storage()
+                    .at(block.reference())
+                    .fetch(&call)
+                    .await
+                    .map_err(|e| Error::Client(e.to_string()))?;
+                if let Some(result) = result {
+                    return Ok(result.result);
+                }
+            }
+
+            Err(Error::Client(
+                "The finalized block subscription ended".to_string(),
+            ))
+        };
+
+        tokio::time::timeout(timeout, wait).await.map_err(|_| {
+            Error::Other(format!(
+                "Timed out after {timeout:?} waiting for the result of job call {call_id} of service {service_id}"
+            ))
+        })?
+    }
+
+    /// Take a snapshot of all jobs assigned to the operator at `address`, along with their
+    /// results and the next job call ID, at the given block. The operator is rendered with the
+    /// SS58 `ss58_prefix` of the chain
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the services, jobs or results could not be fetched
+    pub async fn job_state_snapshot(
+        &self,
+        at_block: [u8; 32],
+        address: AccountId32,
+        ss58_prefix: u16,
+    ) -> Result<JobStateSnapshot, Error> {
+        let blueprints = self
+            .query_operator_blueprints(at_block, address.clone())
+            .await?;
+        let storage = self
+            .rpc_client
+            .storage()
+            .at(BlockRef::from_hash(H256::from_slice(&at_block)));
+
+        let next_job_call_id = storage
+            .fetch_or_default(&api::storage().services().next_job_call_id())
+            .await
+            .map_err(|e| Error::Client(e.to_string()))?;
+
+        let mut services = Vec::new();
+        for blueprint in &blueprints {
+            for service in &blueprint.services {
+                let mut jobs = Vec::new();
+                let mut calls = storage
+                    .iter(api::storage().services().job_calls_iter1(service.id))
+                    .await
+                    .map_err(|e| Error::Client(e.to_string()))?;
+                while let Some(call) = calls.next().await {
+                    let call = call.map_err(|e| Error::Client(e.to_string()))?;
+                    let call_id = call_id_from_storage_key(&call.key_bytes)?;
+                    let result = storage
+                        .fetch(&api::storage().services().job_results(service.id, call_id))
+                        .await
+                        .map_err(|e| Error::Client(e.to_string()))?;
+
+                    jobs.push(JobCallSnapshot {
+                        call_id,
+                        job: call.value.job,
+                        args: format!("0x{}", hex::encode(call.value.args.encode())),
+                        result: result
+                            .map(|result| format!("0x{}", hex::encode(result.result.encode()))),
+                    });
+                }
+                jobs.sort_by_key(|job| job.call_id);
+
+                services.push(ServiceJobsSnapshot {
+                    blueprint_id: blueprint.blueprint_id,
+                    service_id: service.id,
+                    jobs,
+                });
+            }
+        }
+
+        Ok(JobStateSnapshot {
+            block_hash: format!("0x{}", hex::encode(at_block)),
+            operator: AccountJson::new(&address, ss58_prefix),
+            next_job_call_id,
+            services,
+        })
+    }
+
+    /// Get the job calls and result submissions in the blocks `from..=to`, in block order
+    ///
+    /// Only blocks whose hash is still stored by the `System` pallet, i.e. the most recent
+    /// `BlockHashCount` blocks, can be scanned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the range is empty, exceeds [`MAX_JOB_QUERY_RANGE`] blocks or is not
+    /// finalized yet, or if the events of any block in it could not be fetched
+    pub async fn query_jobs_in_range(
+        &self,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<JobEventRecord>, Error> {
+        let latest = self
+            .rpc_client
+            .blocks()
+            .at_latest()
+            .await
+            .map_err(|e| Error::Client(e.to_string()))?;
+        let latest_number: u64 = latest.number().into();
+        if to > latest_number {
+            return Err(Error::Other(format!(
+                "Block #{to} is not finalized yet, the latest finalized block is #{latest_number}"
+            )));
+        }
+
+        let storage = self.rpc_client.storage().at(latest.reference());
+        scan_job_events(from, to, |block_number| {
+            let storage = storage.clone();
+            async move {
+                let block_hash = storage
+                    .fetch_or_default(&api::storage().system().block_hash(block_number))
+                    .await
+                    .map_err(|e| Error::Client(e.to_string()))?;
+                if block_hash == H256::zero() {
+                    return Err(Error::Other(format!(
+                        "The hash of block #{block_number} is no longer stored on-chain"
+                    )));
+                }
+                self.job_events_at(block_number, block_hash).await
+            }
+        })
+        .await
+    }
+
+    /// Get the job calls `validator` was assigned in the blocks `from..=to`, in block order
+    ///
+    /// A validator is assigned every job called on a service instance it is an operator of. The
+    /// same range restrictions as [`Self::query_jobs_in_range`] apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the range is invalid, or if the events or service instances in it could
+    /// not be fetched
+    pub async fn query_validator_job_history(
+        &self,
+        validator: &AccountId32,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<JobEventRecord>, Error> {
+        let records = self.query_jobs_in_range(from, to).await?;
+        let storage = self
+            .rpc_client
+            .storage()
+            .at_latest()
+            .await
+            .map_err(|e| Error::Client(e.to_string()))?;
+
+        assigned_job_calls(records, |service_id, block_number| {
+            let storage = storage.clone();
+            async move {
+                let block_hash = storage
+                    .fetch_or_default(&api::storage().system().block_hash(block_number))
+                    .await
+                    .map_err(|e| Error::Client(e.to_string()))?;
+                let service = self
+                    .rpc_client
+                    .storage()
+                    .at(BlockRef::from_hash(block_hash))
+                    .fetch(&api::storage().services().instances(service_id))
+                    .await
+                    .map_err(|e| Error::Client(e.to_string()))?;
+                Ok(service.is_some_and(|service| service.operators.0.contains(validator)))
+            }
+        })
+        .await
+    }
+
+    /// Get the job events emitted in the block `block_number` with the given hash
+    async fn job_events_at(
+        &self,
+        block_number: u64,
+        block_hash: H256,
+    ) -> Result<Vec<JobEventRecord>, Error> {
+        let events = self
+            .rpc_client
+            .events()
+            .at(BlockRef::from_hash(block_hash))
+            .await
+            .map_err(|e| Error::Client(e.to_string()))?;
+
+        let mut records = Vec::new();
+        for event in events.iter() {
+            let event = event.map_err(|e| Error::Client(e.to_string()))?;
+            let record = if let Some(called) = event
+                .as_event::<api::services::events::JobCalled>()
+                .map_err(|e| Error::Client(e.to_string()))?
+            {
+                JobEventRecord {
+                    block_number,
+                    service_id: called.service_id,
+                    call_id: called.call_id,
+                    job: called.job,
+                    kind: JobEventKind::Called,
+                }
+            } else if let Some(submitted) = event
+                .as_event::<api::services::events::JobResultSubmitted>()
+                .map_err(|e| Error::Client(e.to_string()))?
+            {
+                JobEventRecord {
+                    block_number,
+                    service_id: submitted.service_id,
+                    call_id: submitted.call_id,
+                    job: submitted.job,
+                    kind: JobEventKind::ResultSubmitted,
+                }
+            } else {
+                continue;
+            };
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+
     pub fn dispatch_error_to_sdk_error(&self, err: DispatchError, at: &[u8; 32]) -> Error {
         let metadata = self.rpc_client.metadata();
         let at_hex = hex::encode(at);
@@ -97,3 +644,272 @@ where
         }
     }
 }
+
+/// Collects the job events of every block in `from..=to`, fetched with `job_events_at`.
+async fn scan_job_events<F, Fut>(
+    from: u64,
+    to: u64,
+    mut job_events_at: F,
+) -> Result<Vec<JobEventRecord>, Error>
+where
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = Result<Vec<JobEventRecord>, Error>>,
+{
+    if from > to {
+        return Err(Error::Other(format!(
+            "Invalid block range: #{from} is after #{to}"
+        )));
+    }
+    if to - from >= MAX_JOB_QUERY_RANGE {
+        return Err(Error::Other(format!(
+            "Block range #{from}..=#{to} exceeds the maximum of {MAX_JOB_QUERY_RANGE} blocks"
+        )));
+    }
+
+    let mut records = Vec::new();
+    for block_number in from..=to {
+        records.extend(job_events_at(block_number).await?);
+    }
+    Ok(records)
+}
+
+/// Keeps the job calls of `records` made to services the validator is an operator of, as
+/// reported by `is_operator_of(service_id, block_number)`.
+///
+/// The operators of a service instance are fixed when it is initiated, so each service is only
+/// looked up once, at the block it was first called in.
+async fn assigned_job_calls<F, Fut>(
+    records: Vec<JobEventRecord>,
+    mut is_operator_of: F,
+) -> Result<Vec<JobEventRecord>, Error>
+where
+    F: FnMut(u64, u64) -> Fut,
+    Fut: Future<Output = Result<bool, Error>>,
+{
+    let mut assignments = BTreeMap::new();
+    let mut assigned = Vec::new();
+    for record in records {
+        if record.kind != JobEventKind::Called {
+            continue;
+        }
+        let is_assigned = match assignments.get(&record.service_id) {
+            Some(is_assigned) => *is_assigned,
+            None => {
+                let is_assigned = is_operator_of(record.service_id, record.block_number).await?;
+                let _ = assignments.insert(record.service_id, is_assigned);
+                is_assigned
+            }
+        };
+        if is_assigned {
+            assigned.push(record);
+        }
+    }
+    Ok(assigned)
+}
+
+/// Extracts the call ID from the storage key of a `JobCalls` entry.
+///
+/// The call ID is the last key of the map, and both its `Identity` and `Blake2_128Concat` hashers
+/// leave the SCALE-encoded `u64` at the end of the storage key.
+fn call_id_from_storage_key(key: &[u8]) -> Result<u64, Error> {
+    key.len()
+        .checked_sub(8)
+        .and_then(|start| key[start..].try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(|| {
+            Error::Other(format!(
+                "Invalid job call storage key: 0x{}",
+                hex::encode(key)
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accounts_use_the_ss58_prefix_of_the_chain() {
+        let account = AccountId32([7u8; 32]);
+
+        let generic = AccountJson::new(&account, 42);
+        assert_eq!(generic.ss58, account.to_string());
+        assert_eq!(generic.hex, format!("0x{}", "07".repeat(32)));
+
+        let tangle = AccountJson::new(&account, 5845);
+        assert_ne!(tangle.ss58, generic.ss58);
+        assert_eq!(tangle.ss58, account_id_to_ss58(&account, 5845));
+        assert_eq!(tangle.hex, generic.hex);
+    }
+
+    #[test]
+    fn test_call_id_is_read_from_the_end_of_the_storage_key() {
+        let mut key = vec![0xAA; 32];
+        key.extend_from_slice(&7u64.to_le_bytes());
+        key.extend_from_slice(&[0xBB; 16]);
+        key.extend_from_slice(&42u64.to_le_bytes());
+
+        assert_eq!(call_id_from_storage_key(&key).unwrap(), 42);
+        assert!(call_id_from_storage_key(&[0u8; 4]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_are_collected_over_the_range() {
+        // Job 0 is called on service 1 in blocks 10 and 12, and its results submitted in 11 and 13
+        let synthetic_chain = |block_number: u64| {
+            let kind = if block_number % 2 == 0 {
+                JobEventKind::Called
+            } else {
+                JobEventKind::ResultSubmitted
+            };
+            let records = if (10..=13).contains(&block_number) {
+                vec![JobEventRecord {
+                    block_number,
+                    service_id: 1,
+                    call_id: (block_number - 10) / 2,
+                    job: 0,
+                    kind,
+                }]
+            } else {
+                Vec::new()
+            };
+            async move { Ok::<_, Error>(records) }
+        };
+
+        let records = scan_job_events(5, 20, synthetic_chain).await.unwrap();
+        let summary = records
+            .iter()
+            .map(|record| (record.block_number, record.call_id, record.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (10, 0, JobEventKind::Called),
+                (11, 0, JobEventKind::ResultSubmitted),
+                (12, 1, JobEventKind::Called),
+                (13, 1, JobEventKind::ResultSubmitted),
+            ]
+        );
+
+        // Only the requested blocks are scanned
+        let records = scan_job_events(11, 12, synthetic_chain).await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].block_number, 11);
+    }
+
+    #[tokio::test]
+    async fn test_validator_job_history_only_has_assigned_calls() {
+        // Job calls to services 1 and 2 in every block, with their results submitted in the next
+        let synthetic_chain = |block_number: u64| {
+            let records = [1, 2]
+                .into_iter()
+                .flat_map(|service_id| {
+                    [
+                        JobEventRecord {
+                            block_number,
+                            service_id,
+                            call_id: block_number,
+                            job: 0,
+                            kind: JobEventKind::Called,
+                        },
+                        JobEventRecord {
+                            block_number,
+                            service_id,
+                            call_id: block_number - 1,
+                            job: 0,
+                            kind: JobEventKind::ResultSubmitted,
+                        },
+                    ]
+                })
+                .collect::<Vec<_>>();
+            async move { Ok::<_, Error>(records) }
+        };
+        let records = scan_job_events(10, 12, synthetic_chain).await.unwrap();
+
+        // The validator only operates service 2
+        let mut lookups = Vec::new();
+        let history = assigned_job_calls(records, |service_id, block_number| {
+            lookups.push((service_id, block_number));
+            async move { Ok(service_id == 2) }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            history
+                .iter()
+                .map(|record| (record.block_number, record.service_id, record.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                (10, 2, JobEventKind::Called),
+                (11, 2, JobEventKind::Called),
+                (12, 2, JobEventKind::Called),
+            ]
+        );
+        // Each service is only looked up once
+        assert_eq!(lookups, vec![(1, 10), (2, 10)]);
+    }
+
+    #[tokio::test]
+    async fn test_job_query_range_is_capped() {
+        let no_events = |_| async { Ok::<_, Error>(Vec::new()) };
+        assert!(scan_job_events(0, MAX_JOB_QUERY_RANGE - 1, no_events)
+            .await
+            .is_ok());
+        assert!(scan_job_events(0, MAX_JOB_QUERY_RANGE, no_events)
+            .await
+            .is_err());
+        assert!(scan_job_events(2, 1, no_events).await.is_err());
+    }
+
+    #[test]
+    fn test_job_result_fields_are_decoded_by_type() {
+        let key = vec![0x02; 33];
+        let account = AccountId32([7u8; 32]);
+        let result: Vec<JobResultField> = vec![
+            Field::Bytes(BoundedVec(key.clone())),
+            Field::String(BoundedString(BoundedVec(b"ecdsa".to_vec()))),
+            Field::AccountId(account.clone()),
+            Field::Uint64(42),
+        ];
+
+        assert_eq!(result[0].as_bytes(), Some(&key[..]));
+        assert_eq!(result[0].as_str(), None);
+        assert_eq!(result[1].as_str(), Some("ecdsa"));
+        assert_eq!(result[1].as_bytes(), None);
+        assert_eq!(result[2].as_account_id(), Some(&account));
+        assert!(result[3].as_bytes().is_none());
+        assert!(result[3].as_account_id().is_none());
+
+        let invalid_utf8 = Field::String(BoundedString(BoundedVec(vec![0xFF, 0xFE])));
+        assert_eq!(invalid_utf8.as_str(), None);
+    }
+
+    #[test]
+    fn test_participant_index_follows_the_canonical_ordering() {
+        let alice = AccountId32([3u8; 32]);
+        let bob = AccountId32([1u8; 32]);
+        let charlie = AccountId32([2u8; 32]);
+        let operators = vec![alice.clone(), bob.clone(), charlie.clone()];
+
+        // Sorted by public key, regardless of the on-chain order
+        assert_eq!(
+            canonical_participants(&operators),
+            vec![bob.clone(), charlie.clone(), alice.clone()]
+        );
+        let mut reversed = operators.clone();
+        reversed.reverse();
+        assert_eq!(
+            canonical_participants(&reversed),
+            canonical_participants(&operators)
+        );
+
+        assert_eq!(participant_index(&operators, &bob), Some(0));
+        assert_eq!(participant_index(&operators, &charlie), Some(1));
+        assert_eq!(participant_index(&reversed, &alice), Some(2));
+
+        // Not an operator of the service
+        assert_eq!(participant_index(&operators, &AccountId32([9u8; 32])), None);
+        assert_eq!(participant_index(&[], &alice), None);
+    }
+}
@@ -426,9 +426,25 @@ impl<RwLock: lock_api::RawRwLock> GadgetConfiguration<RwLock> {
 
     /// Returns a new [`subxt::OnlineClient`] for the Tangle.
     ///
+    /// The connection times out after
+    /// [`DEFAULT_CONNECTION_TIMEOUT`](crate::clients::tangle::runtime::DEFAULT_CONNECTION_TIMEOUT).
+    ///
     /// # Errors
     /// This function will return an error if we are unable to connect to the Tangle RPC endpoint.
-    #[cfg(any(feature = "std", feature = "wasm"))]
+    #[cfg(feature = "std")]
+    pub async fn client(&self) -> Result<crate::clients::tangle::runtime::TangleClient, Error> {
+        crate::clients::tangle::runtime::connect(
+            &self.rpc_endpoint,
+            crate::clients::tangle::runtime::DEFAULT_CONNECTION_TIMEOUT,
+        )
+        .await
+    }
+
+    /// Returns a new [`subxt::OnlineClient`] for the Tangle.
+    ///
+    /// # Errors
+    /// This function will return an error if we are unable to connect to the Tangle RPC endpoint.
+    #[cfg(all(feature = "wasm", not(feature = "std")))]
     pub async fn client(&self) -> Result<crate::clients::tangle::runtime::TangleClient, Error> {
         let client =
             subxt::OnlineClient::<crate::clients::tangle::runtime::TangleConfig>::from_url(
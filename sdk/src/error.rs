@@ -26,10 +26,32 @@ pub enum Error {
     #[error("Peer not found: {id:?}")]
     PeerNotFound { id: ecdsa::Public },
 
+    #[error("Account {account} does not exist on chain")]
+    AccountNotFound { account: String },
+
+    #[error("Insufficient free balance for {account}: {free} available, {required} required")]
+    InsufficientBalance {
+        account: String,
+        free: u128,
+        required: u128,
+    },
+
+    #[error(
+        "The runtime was upgraded from spec version {from} to {to}, reconnect with types generated for the new runtime"
+    )]
+    RuntimeUpgraded { from: u32, to: u32 },
+
     #[cfg(feature = "std")]
     #[error("Join error: {0}")]
     Join(#[from] tokio::task::JoinError),
 
+    #[cfg(feature = "std")]
+    #[error("Timed out after {timeout:?} connecting to {endpoint}")]
+    ConnectionTimeout {
+        endpoint: String,
+        timeout: core::time::Duration,
+    },
+
     // TODO: Add feature flag for substrate/tangle
     #[error("Subxt error: {0}")]
     #[cfg(any(feature = "std", feature = "wasm"))]
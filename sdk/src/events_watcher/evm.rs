@@ -120,6 +120,14 @@ pub trait EventWatcher<T: Config<N = Ethereum>>: Send + Sync + 'static {
     fn contract(&mut self) -> Self::Contract;
     fn handlers(&self) -> &Vec<EventHandlerFor<Self, T>>;
 
+    /// The maximum number of blocks behind the latest block to backfill events from.
+    ///
+    /// Events in older blocks, e.g. emitted while the node was offline for days, are skipped
+    /// rather than handled in a long catch-up. Unbounded by default.
+    fn max_lookback_blocks(&self) -> Option<u64> {
+        None
+    }
+
     /// The Storage backend that will be used to store the required state for this event watcher
     /// Returns a task that should be running in the background
     /// that will watch events
@@ -127,6 +135,7 @@ pub trait EventWatcher<T: Config<N = Ethereum>>: Send + Sync + 'static {
     async fn run(&mut self) -> Result<(), Error> {
         let contract = self.contract();
         let handlers = self.handlers();
+        let max_lookback_blocks = self.max_lookback_blocks();
 
         let local_db = LocalDatabase::open("./db");
         let backoff = UnboundedConstantBuilder::new(Duration::from_secs(1));
@@ -161,9 +170,10 @@ pub trait EventWatcher<T: Config<N = Ethereum>>: Send + Sync + 'static {
                 .unwrap_or_default();
 
             loop {
-                let block = local_db
+                let last_block = local_db
                     .get(&format!("LAST_BLOCK_NUMBER_{}", contract.address()))
                     .unwrap_or(deployed_at);
+                let block = skip_stale_blocks(last_block, target_block_number, max_lookback_blocks);
                 let dest_block = core::cmp::min(block + step, target_block_number);
 
                 let events_filter = contract.event::<Self::Event>(
@@ -244,3 +254,39 @@ pub trait EventWatcher<T: Config<N = Ethereum>>: Send + Sync + 'static {
         Ok(())
     }
 }
+
+/// The block to resume watching after, skipping the blocks after `last_block` that are more than
+/// `max_lookback_blocks` behind `target_block`.
+fn skip_stale_blocks(last_block: u64, target_block: u64, max_lookback_blocks: Option<u64>) -> u64 {
+    let Some(oldest) = max_lookback_blocks.map(|max| target_block.saturating_sub(max)) else {
+        return last_block;
+    };
+    if last_block >= oldest {
+        return last_block;
+    }
+
+    warn!(
+        "Skipping events in blocks #{}..=#{oldest}, older than the maximum lookback of {} blocks",
+        last_block + 1,
+        target_block - oldest
+    );
+    oldest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_older_than_the_lookback_are_skipped() {
+        // Offline for 10_000 blocks, only the last 100 are backfilled
+        assert_eq!(skip_stale_blocks(0, 10_000, Some(100)), 9_900);
+        // Within the window, nothing is skipped
+        assert_eq!(skip_stale_blocks(9_950, 10_000, Some(100)), 9_950);
+        assert_eq!(skip_stale_blocks(9_900, 10_000, Some(100)), 9_900);
+        // A young chain is backfilled from the start
+        assert_eq!(skip_stale_blocks(0, 50, Some(100)), 0);
+        // Unbounded by default
+        assert_eq!(skip_stale_blocks(0, 10_000, None), 0);
+    }
+}
@@ -14,3 +14,6 @@ pub mod evm;
 mod retry;
 pub mod substrate;
 pub mod tangle;
+/// Delivery of job lifecycle events to a webhook
+#[cfg(feature = "std")]
+pub mod webhook;
@@ -11,7 +11,7 @@ use crate::events_watcher::error::Error;
 use crate::{error, info, warn};
 use backon::{ConstantBuilder, ExponentialBuilder, Retryable};
 use core::time::Duration;
-use futures::TryFutureExt;
+use futures::{StreamExt, TryFutureExt};
 use subxt::OnlineClient;
 
 /// A type alias to extract the event handler type from the event watcher.
@@ -43,6 +43,14 @@ where
         &self,
         events: subxt::events::Events<RuntimeConfig>,
     ) -> Result<bool, Error>;
+
+    /// Called once by the [`SubstrateEventWatcher`] before it handles any event, e.g. to check the
+    /// handler against the on-chain state.
+    ///
+    /// An error is logged, but does not prevent the handler from running.
+    async fn init(&self, _client: &OnlineClient<RuntimeConfig>) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 /// An Auxiliary trait to handle events with retry logic.
@@ -85,6 +93,49 @@ where
 {
 }
 
+/// Waits until `confirmations` blocks have been built on top of block `block_number`.
+///
+/// This guards against acting on events from a block that is later reorged out, at the cost of
+/// latency. If the block is already finalized, it can no longer be reorged and this returns
+/// immediately. In particular, this is a no-op for handlers run by a [`SubstrateEventWatcher`],
+/// which only watches finalized blocks.
+///
+/// # Errors
+///
+/// Returns an error if the block subscription fails or ends early.
+pub async fn wait_for_confirmations<RuntimeConfig>(
+    client: &OnlineClient<RuntimeConfig>,
+    block_number: u64,
+    confirmations: u64,
+) -> Result<(), Error>
+where
+    RuntimeConfig: subxt::Config + Send + Sync + 'static,
+{
+    if confirmations == 0 {
+        return Ok(());
+    }
+
+    let finalized: u64 = client.blocks().at_latest().await?.number().into();
+    if finalized >= block_number {
+        return Ok(());
+    }
+
+    let target = block_number.saturating_add(confirmations);
+    info!("Waiting for {confirmations} confirmations of block #{block_number}");
+    let mut blocks = client.blocks().subscribe_best().await?;
+    while let Some(block) = blocks.next().await {
+        let number: u64 = block?.number().into();
+        if number >= target {
+            return Ok(());
+        }
+    }
+
+    Err(subxt::Error::Other(format!(
+        "Block subscription ended before block #{block_number} was confirmed"
+    ))
+    .into())
+}
+
 /// Represents a Substrate event watcher.
 #[async_trait::async_trait]
 pub trait SubstrateEventWatcher<RuntimeConfig>: Send + Sync + 'static
@@ -110,6 +161,11 @@ where
         const MAX_RETRY_COUNT: usize = 5;
         let client = self.client().clone();
         let handlers = self.handlers();
+        for handler in handlers {
+            if let Err(err) = handler.init(&client).await {
+                warn!("Event handler failed to initialize: {err}");
+            }
+        }
 
         let backoff = ExponentialBuilder::default().with_max_times(usize::MAX);
         let task = || async {
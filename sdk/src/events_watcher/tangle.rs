@@ -3,8 +3,23 @@
 #![allow(clippy::module_name_repetitions)]
 
 use crate::clients::tangle::runtime::{TangleClient, TangleConfig};
+use crate::events_watcher::error::Error;
 use crate::events_watcher::substrate::{EventHandler, EventHandlerFor};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use std::sync::{Mutex, PoisonError};
+use subxt::ext::codec::{Compact, Decode, DecodeAll, Encode};
+use subxt::utils::AccountId32;
 use subxt::OnlineClient;
+use tangle_subxt::tangle_testnet_runtime::api;
+use tangle_subxt::tangle_testnet_runtime::api::runtime_types::bounded_collections::bounded_vec::BoundedVec;
+use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::field::{
+    Field, FieldType,
+};
+use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::JobDefinition;
 
 /// An event watcher for the Tangle network.
 pub struct TangleEventsWatcher {
@@ -26,3 +41,580 @@ impl super::substrate::SubstrateEventWatcher<TangleConfig> for TangleEventsWatch
         &self.handlers
     }
 }
+
+/// The job call being handled, injected by the `#[job]` macro into job functions that take a
+/// parameter of this type.
+///
+/// The call id is unique per service, so it can be used to derive per-call values, such as
+/// deterministic randomness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobCallContext {
+    /// The service the job was called on
+    pub service_id: u64,
+    /// The id of the job call
+    pub call_id: u64,
+    /// The number of the block containing the `JobCalled` event
+    pub block_number: u64,
+    /// The hash of the block containing the `JobCalled` event
+    pub block_hash: subxt::utils::H256,
+}
+
+/// The parameters of a job, decoded from all the arguments of a job call at once.
+///
+/// Used by the handlers of jobs marked with `#[job_params(MyParams)]`. It is implemented for any
+/// type deriving SCALE `Decode`, whose fields are decoded in order from the arguments, so they
+/// must be declared in the same order as the job's on-chain parameters.
+pub trait JobParams: Sized {
+    /// Decodes the parameters from the arguments of a job call
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the arguments do not match the fields of the parameters
+    fn decode_job_args(args: Vec<Field<AccountId32>>) -> Result<Self, Error>;
+}
+
+impl<T: Decode> JobParams for T {
+    fn decode_job_args(args: Vec<Field<AccountId32>>) -> Result<Self, Error> {
+        let mut encoded = Vec::new();
+        for arg in &args {
+            encode_arg_value(arg, &mut encoded)?;
+        }
+
+        T::decode_all(&mut &encoded[..]).map_err(|err| Error::Handler(Box::new(err)))
+    }
+}
+
+/// Appends the SCALE encoding of the value held by `arg` to `out`, without its `Field` variant.
+///
+/// The encoding of a struct is the concatenation of its fields, so the values of the arguments
+/// decode as the fields of the parameters.
+fn encode_arg_value(arg: &Field<AccountId32>, out: &mut Vec<u8>) -> Result<(), Error> {
+    match arg {
+        Field::List(BoundedVec(items)) => {
+            let len = u32::try_from(items.len()).map_err(|err| Error::Handler(Box::new(err)))?;
+            Compact(len).encode_to(out);
+            for item in items {
+                encode_arg_value(item, out)?;
+            }
+        }
+        Field::None | Field::Struct(..) => {
+            return Err(Error::Handler(
+                "Optional and struct arguments cannot be decoded as job parameters".into(),
+            ));
+        }
+        // The other variants wrap a single value, encoded right after the variant index
+        arg => out.extend_from_slice(&arg.encode()[1..]),
+    }
+    Ok(())
+}
+
+/// Decodes the bytes of a `Field::String` job argument into a [`String`].
+///
+/// # Errors
+///
+/// Returns an error if the bytes are not valid UTF-8
+pub fn decode_string_arg(bytes: Vec<u8>) -> Result<String, Error> {
+    String::from_utf8(bytes).map_err(|err| Error::Handler(Box::new(err)))
+}
+
+/// Checks that the job defined by `job_def`, the JSON job definition generated by the `#[job]`
+/// macro, takes the parameters declared by its on-chain definition `onchain`.
+///
+/// # Errors
+///
+/// Returns an error describing the mismatch, or if `job_def` is malformed
+pub fn check_job_signature(job_def: &str, onchain: &JobDefinition) -> Result<(), Error> {
+    let job_def: serde_json::Value =
+        serde_json::from_str(job_def).map_err(|err| Error::Handler(Box::new(err)))?;
+    let expected: Vec<FieldType> = serde_json::from_value(job_def["params"].clone())
+        .map_err(|err| Error::Handler(Box::new(err)))?;
+
+    if expected.encode() != onchain.params.0.encode() {
+        return Err(Error::Handler(
+            format!(
+                "The job handler decodes parameters {expected:?}, but the on-chain job declares {:?}",
+                onchain.params.0
+            )
+            .into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the job `job_id` of the blueprint that service `service_id` runs against `job_def`, see
+/// [`check_job_signature`].
+///
+/// # Errors
+///
+/// Returns an error if the service or its blueprint could not be fetched, if the blueprint has no
+/// such job, or if the job parameters do not match
+pub async fn validate_job_signature(
+    client: &OnlineClient<TangleConfig>,
+    service_id: u64,
+    job_id: u8,
+    job_def: &str,
+) -> Result<(), Error> {
+    let not_found = |what: String| Error::Handler(format!("{what} not found on-chain").into());
+
+    let storage = client.storage().at_latest().await?;
+    let service = storage
+        .fetch(&api::storage().services().instances(service_id))
+        .await?
+        .ok_or_else(|| not_found(format!("Service {service_id}")))?;
+    let (_, blueprint) = storage
+        .fetch(&api::storage().services().blueprints(service.blueprint))
+        .await?
+        .ok_or_else(|| not_found(format!("Blueprint {}", service.blueprint)))?;
+    let onchain = blueprint
+        .jobs
+        .0
+        .get(usize::from(job_id))
+        .ok_or_else(|| not_found(format!("Job {job_id} of blueprint {}", service.blueprint)))?;
+
+    check_job_signature(job_def, onchain)
+}
+
+/// A point in the lifecycle of a job call
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "snake_case")]
+pub enum JobLifecycleStatus {
+    /// A `JobCalled` event for the job was found, and the call was not handled yet
+    Observed,
+    /// The arguments of the call were decoded, and the job is being run
+    Started,
+    /// The job ran, and its result was submitted, or queued for a batch submission
+    Completed,
+    /// The job or the submission of its result failed, with the given error
+    Failed(String),
+}
+
+/// A change in the lifecycle of a job call, reported to the [`JobNotifier`] of the generated
+/// handlers.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct JobLifecycleEvent {
+    pub service_id: u64,
+    pub job_id: u8,
+    pub call_id: u64,
+    #[serde(flatten)]
+    pub status: JobLifecycleStatus,
+}
+
+/// Receives the [`JobLifecycleEvent`]s of the job calls handled by the generated handlers.
+///
+/// [`notify`](Self::notify) is called inline while handling a job call, so implementations must
+/// not block, and should instead queue the event for delivery.
+pub trait JobLifecycleSink: Send + Sync + 'static {
+    /// Reports `event`
+    fn notify(&self, event: JobLifecycleEvent);
+}
+
+/// A shared handle to an optional [`JobLifecycleSink`], which discards the events by default.
+#[derive(Clone, Default)]
+pub struct JobNotifier(Option<Arc<dyn JobLifecycleSink>>);
+
+impl JobNotifier {
+    /// Report the lifecycle of job calls to `sink`
+    pub fn new<S: JobLifecycleSink>(sink: S) -> Self {
+        Self(Some(Arc::new(sink)))
+    }
+
+    /// Reports that the job call `call_id` of service `service_id` reached `status`
+    pub fn notify(&self, service_id: u64, job_id: u8, call_id: u64, status: JobLifecycleStatus) {
+        if let Some(sink) = &self.0 {
+            sink.notify(JobLifecycleEvent {
+                service_id,
+                job_id,
+                call_id,
+                status,
+            });
+        }
+    }
+}
+
+impl core::fmt::Debug for JobNotifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("JobNotifier")
+            .field(&self.0.is_some())
+            .finish()
+    }
+}
+
+/// The result of a job call, as submitted on-chain
+pub type JobResult = Vec<Field<AccountId32>>;
+
+/// The cached result of a job call, along with the hash of the arguments it was computed from
+type CachedResult = ([u8; 32], JobResult);
+
+/// Caches the results of job calls until they are submitted, so that a job call handled again
+/// after a failed submission reuses its result rather than running the job a second time.
+///
+/// A cached result is only reused if the arguments of the call are identical, see
+/// [`Self::input_hash`]. The cache is disabled by default, use [`Self::enabled`] to turn it on.
+#[derive(Clone, Default)]
+pub struct JobResultCache(Option<Arc<Mutex<BTreeMap<(u64, u64), CachedResult>>>>);
+
+impl JobResultCache {
+    /// A cache that keeps the results of job calls until they are submitted
+    #[must_use]
+    pub fn enabled() -> Self {
+        Self(Some(Arc::default()))
+    }
+
+    /// Whether results are cached at all
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// The hash identifying the arguments `args` of a job call
+    #[must_use]
+    pub fn input_hash(args: &[Field<AccountId32>]) -> [u8; 32] {
+        sp_core::blake2_256(&args.encode())
+    }
+
+    /// The result cached for the job call `call_id` of service `service_id`, if it was computed
+    /// from the arguments hashing to `input_hash`
+    #[must_use]
+    pub fn get(&self, service_id: u64, call_id: u64, input_hash: &[u8; 32]) -> Option<JobResult> {
+        let cache = self
+            .0
+            .as_ref()?
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        cache
+            .get(&(service_id, call_id))
+            .filter(|(cached_hash, _)| cached_hash == input_hash)
+            .map(|(_, result)| result.clone())
+    }
+
+    /// Caches `result`, computed from the arguments hashing to `input_hash`, for the job call
+    /// `call_id` of service `service_id`
+    pub fn insert(
+        &self,
+        service_id: u64,
+        call_id: u64,
+        input_hash: [u8; 32],
+        result: &[Field<AccountId32>],
+    ) {
+        if let Some(cache) = &self.0 {
+            let _ = cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert((service_id, call_id), (input_hash, result.to_vec()));
+        }
+    }
+
+    /// Drops the result of the job call `call_id` of service `service_id`, once it was submitted
+    pub fn remove(&self, service_id: u64, call_id: u64) {
+        if let Some(cache) = &self.0 {
+            let _ = cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&(service_id, call_id));
+        }
+    }
+}
+
+impl core::fmt::Debug for JobResultCache {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("JobResultCache")
+            .field(&self.is_enabled())
+            .finish()
+    }
+}
+
+/// Records which job calls have already been handled, so that a job reprocessed after a restart
+/// is not executed and its result submitted a second time.
+///
+/// The handlers generated by the `#[job]` macro check this store before invoking the job, and
+/// record the call once its result has been submitted.
+#[async_trait::async_trait]
+pub trait HandledJobStore: Send + Sync + 'static {
+    /// Whether the job call `call_id` of service `service_id` has already been handled
+    async fn contains(&self, service_id: u64, call_id: u64) -> Result<bool, Error>;
+
+    /// Marks the job call `call_id` of service `service_id` as handled
+    async fn insert(&self, service_id: u64, call_id: u64) -> Result<(), Error>;
+}
+
+/// A shared handle to a [`HandledJobStore`], defaulting to an [`InMemoryHandledJobStore`].
+#[derive(Clone)]
+pub struct HandledJobs(Arc<dyn HandledJobStore>);
+
+impl HandledJobs {
+    /// Use `store` to keep track of handled job calls
+    pub fn new<S: HandledJobStore>(store: S) -> Self {
+        Self(Arc::new(store))
+    }
+}
+
+impl Default for HandledJobs {
+    fn default() -> Self {
+        Self::new(InMemoryHandledJobStore::default())
+    }
+}
+
+impl core::fmt::Debug for HandledJobs {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("HandledJobs").finish_non_exhaustive()
+    }
+}
+
+impl core::ops::Deref for HandledJobs {
+    type Target = dyn HandledJobStore;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+/// A [`HandledJobStore`] that only lives as long as the process.
+///
+/// This protects against the same block being processed twice, but not against restarts.
+/// Use [`FileHandledJobStore`] for that.
+#[derive(Debug, Default)]
+pub struct InMemoryHandledJobStore {
+    handled: Mutex<BTreeSet<(u64, u64)>>,
+}
+
+#[async_trait::async_trait]
+impl HandledJobStore for InMemoryHandledJobStore {
+    async fn contains(&self, service_id: u64, call_id: u64) -> Result<bool, Error> {
+        Ok(self
+            .handled
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains(&(service_id, call_id)))
+    }
+
+    async fn insert(&self, service_id: u64, call_id: u64) -> Result<(), Error> {
+        let _ = self
+            .handled
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert((service_id, call_id));
+        Ok(())
+    }
+}
+
+/// A [`HandledJobStore`] persisted to a file, one `service_id,call_id` pair per line.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct FileHandledJobStore {
+    path: std::path::PathBuf,
+    handled: InMemoryHandledJobStore,
+}
+
+#[cfg(feature = "std")]
+impl FileHandledJobStore {
+    /// Opens the store at `path`, loading the job calls handled by previous runs
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but could not be read, or is malformed
+    pub fn open<P: Into<std::path::PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let mut handled = BTreeSet::new();
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                    let entry = line
+                        .split_once(',')
+                        .and_then(|(service_id, call_id)| {
+                            Some((
+                                service_id.trim().parse().ok()?,
+                                call_id.trim().parse().ok()?,
+                            ))
+                        })
+                        .ok_or_else(|| {
+                            handler_error(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                format!("Malformed entry `{line}` in {}", path.display()),
+                            ))
+                        })?;
+                    let _ = handled.insert(entry);
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(handler_error(err)),
+        }
+
+        Ok(Self {
+            path,
+            handled: InMemoryHandledJobStore {
+                handled: Mutex::new(handled),
+            },
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+#[async_trait::async_trait]
+impl HandledJobStore for FileHandledJobStore {
+    async fn contains(&self, service_id: u64, call_id: u64) -> Result<bool, Error> {
+        self.handled.contains(service_id, call_id).await
+    }
+
+    async fn insert(&self, service_id: u64, call_id: u64) -> Result<(), Error> {
+        use std::io::Write;
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(handler_error)?;
+        writeln!(file, "{service_id},{call_id}").map_err(handler_error)?;
+        file.sync_data().map_err(handler_error)?;
+
+        self.handled.insert(service_id, call_id).await
+    }
+}
+
+#[cfg(feature = "std")]
+fn handler_error(err: std::io::Error) -> Error {
+    Error::Handler(Box::new(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::field::BoundedString;
+    use tangle_subxt::tangle_testnet_runtime::api::runtime_types::tangle_primitives::services::{
+        JobMetadata, JobResultVerifier,
+    };
+
+    #[derive(Debug, PartialEq, Decode)]
+    #[codec(crate = subxt::ext::codec)]
+    struct TransferParams {
+        amount: u64,
+        memo: String,
+        recipients: Vec<u32>,
+        signature: Vec<u8>,
+    }
+
+    #[test]
+    fn test_job_args_are_decoded_as_params() {
+        let args = vec![
+            Field::Uint64(42),
+            Field::String(BoundedString(BoundedVec(b"rent".to_vec()))),
+            Field::List(BoundedVec(vec![Field::Uint32(1), Field::Uint32(2)])),
+            Field::Bytes(BoundedVec(vec![0xde, 0xad])),
+        ];
+        assert_eq!(
+            TransferParams::decode_job_args(args).unwrap(),
+            TransferParams {
+                amount: 42,
+                memo: "rent".into(),
+                recipients: vec![1, 2],
+                signature: vec![0xde, 0xad],
+            }
+        );
+
+        // Missing and extra arguments are both rejected
+        assert!(TransferParams::decode_job_args(vec![Field::Uint64(42)]).is_err());
+        assert!(<(u64,)>::decode_job_args(vec![Field::Uint64(1), Field::Uint64(2)]).is_err());
+        assert!(<(u64,)>::decode_job_args(vec![Field::None]).is_err());
+    }
+
+    #[test]
+    fn test_string_args_are_utf8_validated() {
+        assert_eq!(
+            decode_string_arg("héllo".as_bytes().to_vec()).unwrap(),
+            "héllo"
+        );
+        // A truncated multi-byte character
+        let err = decode_string_arg(vec![b'h', 0xc3]).unwrap_err();
+        assert!(matches!(err, Error::Handler(_)));
+    }
+
+    fn onchain_job(params: Vec<FieldType>) -> JobDefinition {
+        JobDefinition {
+            metadata: JobMetadata {
+                name: BoundedString(BoundedVec(b"transfer".to_vec())),
+                description: None,
+            },
+            params: BoundedVec(params),
+            result: BoundedVec(vec![FieldType::Uint64]),
+            verifier: JobResultVerifier::None,
+        }
+    }
+
+    #[test]
+    fn test_job_signature_is_checked_against_the_chain() {
+        let job_def = r#"{"metadata":{"name":"transfer","description":null},"params":["Uint64",{"List":"Uint32"}],"result":["Uint64"],"verifier":"None"}"#;
+
+        let matching = onchain_job(vec![
+            FieldType::Uint64,
+            FieldType::List(Box::new(FieldType::Uint32)),
+        ]);
+        assert!(check_job_signature(job_def, &matching).is_ok());
+
+        let mismatching = onchain_job(vec![FieldType::Uint64, FieldType::String]);
+        let err = check_job_signature(job_def, &mismatching).unwrap_err();
+        assert!(err.to_string().contains("String"));
+        assert!(check_job_signature(job_def, &onchain_job(vec![FieldType::Uint64])).is_err());
+    }
+
+    #[test]
+    fn test_retry_with_identical_inputs_reuses_the_result() {
+        let args = vec![Field::Uint64(5)];
+        let changed_args = vec![Field::Uint64(6)];
+        let result = vec![Field::Uint64(25)];
+
+        let cache = JobResultCache::enabled();
+        cache.insert(1, 7, JobResultCache::input_hash(&args), &result);
+        assert_eq!(
+            cache
+                .get(1, 7, &JobResultCache::input_hash(&args))
+                .unwrap()
+                .encode(),
+            result.encode()
+        );
+        // Changed inputs, or another call, are recomputed
+        assert!(cache
+            .get(1, 7, &JobResultCache::input_hash(&changed_args))
+            .is_none());
+        assert!(cache
+            .get(1, 8, &JobResultCache::input_hash(&args))
+            .is_none());
+
+        // Until the result is submitted
+        cache.remove(1, 7);
+        assert!(cache
+            .get(1, 7, &JobResultCache::input_hash(&args))
+            .is_none());
+
+        // Nothing is cached by default
+        let disabled = JobResultCache::default();
+        disabled.insert(1, 7, JobResultCache::input_hash(&args), &result);
+        assert!(disabled
+            .get(1, 7, &JobResultCache::input_hash(&args))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_tracks_handled_jobs() {
+        let handled = HandledJobs::default();
+        assert!(!handled.contains(1, 7).await.unwrap());
+
+        handled.insert(1, 7).await.unwrap();
+        assert!(handled.contains(1, 7).await.unwrap());
+        assert!(!handled.contains(1, 8).await.unwrap());
+        assert!(!handled.contains(2, 7).await.unwrap());
+    }
+
+    #[cfg(feature = "std")]
+    #[tokio::test]
+    async fn test_file_store_survives_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("handled-jobs.csv");
+
+        let store = FileHandledJobStore::open(&path).unwrap();
+        store.insert(1, 7).await.unwrap();
+        drop(store);
+
+        let restarted = FileHandledJobStore::open(&path).unwrap();
+        assert!(restarted.contains(1, 7).await.unwrap());
+        assert!(!restarted.contains(1, 8).await.unwrap());
+    }
+}
@@ -0,0 +1,234 @@
+//! Delivery of job lifecycle events to an HTTP webhook.
+//!
+//! A [`WebhookNotifier`] POSTs every [`JobLifecycleEvent`] it is notified of as JSON, e.g.
+//!
+//! ```json
+//! { "service_id": 1, "job_id": 0, "call_id": 7, "status": "completed" }
+//! ```
+//!
+//! Events are queued and delivered in order by a background task, so a slow or unreachable
+//! webhook never blocks the handling of job calls. Once the queue is full, new events are dropped.
+
+use crate::events_watcher::tangle::{JobLifecycleEvent, JobLifecycleSink};
+use crate::{error, warn};
+use alloc::string::String;
+use backon::{ExponentialBuilder, Retryable};
+use core::time::Duration;
+use tokio::sync::mpsc;
+use url::Url;
+
+/// The default number of events waiting to be delivered before new ones are dropped
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// The default number of times the delivery of an event is retried
+pub const DEFAULT_MAX_RETRIES: usize = 3;
+
+/// The delay before the first retry, roughly doubled after every attempt
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Where and how to deliver job lifecycle events
+#[derive(Clone)]
+pub struct WebhookConfig {
+    /// The URL the events are POSTed to
+    pub url: Url,
+    /// The value of the `Authorization` header sent along with the events, if any
+    pub auth_header: Option<String>,
+    /// The number of times the delivery of an event is retried before it is dropped
+    pub max_retries: usize,
+    /// The number of events waiting to be delivered before new ones are dropped
+    pub queue_capacity: usize,
+}
+
+impl core::fmt::Debug for WebhookConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // The header holds the credentials of the webhook, e.g. a bearer token
+        f.debug_struct("WebhookConfig")
+            .field("url", &self.url)
+            .field(
+                "auth_header",
+                &self.auth_header.as_ref().map(|_| "<redacted>"),
+            )
+            .field("max_retries", &self.max_retries)
+            .field("queue_capacity", &self.queue_capacity)
+            .finish()
+    }
+}
+
+impl WebhookConfig {
+    /// Deliver the events to `url`, without authentication
+    #[must_use]
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            auth_header: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+        }
+    }
+
+    /// Send `value` as the `Authorization` header, e.g. `Bearer <token>`
+    #[must_use]
+    pub fn with_auth_header<T: Into<String>>(mut self, value: T) -> Self {
+        self.auth_header = Some(value.into());
+        self
+    }
+
+    fn backoff(&self) -> ExponentialBuilder {
+        ExponentialBuilder::default()
+            .with_min_delay(INITIAL_RETRY_DELAY)
+            .with_max_times(self.max_retries)
+            .with_jitter()
+    }
+}
+
+/// A [`JobLifecycleSink`] delivering the events to a webhook.
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    queue: mpsc::Sender<JobLifecycleEvent>,
+}
+
+impl WebhookNotifier {
+    /// Starts delivering the events the notifier is notified of, as configured by `config`
+    ///
+    /// # Panics
+    ///
+    /// If called outside of a Tokio runtime
+    #[must_use]
+    pub fn spawn(config: WebhookConfig) -> Self {
+        let (queue, mut events) = mpsc::channel(config.queue_capacity.max(1));
+        drop(tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            while let Some(event) = events.recv().await {
+                deliver(&client, &config, &event).await;
+            }
+        }));
+
+        Self { queue }
+    }
+}
+
+impl JobLifecycleSink for WebhookNotifier {
+    fn notify(&self, event: JobLifecycleEvent) {
+        if let Err(err) = self.queue.try_send(event) {
+            warn!("Dropping job lifecycle event, the webhook is not keeping up: {err}");
+        }
+    }
+}
+
+/// POSTs `event` to the webhook, retrying with an exponential backoff on failure
+async fn deliver(client: &reqwest::Client, config: &WebhookConfig, event: &JobLifecycleEvent) {
+    let post = || async {
+        let mut request = client.post(config.url.clone()).json(event);
+        if let Some(auth_header) = &config.auth_header {
+            request = request.header(reqwest::header::AUTHORIZATION, auth_header);
+        }
+        request.send().await.and_then(|res| res.error_for_status())
+    };
+    let delivered = post
+        .retry(config.backoff())
+        .notify(|err, delay| {
+            warn!("Failed to deliver job lifecycle event, retrying in {delay:?}: {err}");
+        })
+        .await;
+    if let Err(err) = delivered {
+        error!(
+            "Failed to deliver job lifecycle event to {}: {err}",
+            config.url
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events_watcher::tangle::{JobLifecycleStatus, JobNotifier};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Accepts a single HTTP request, returning its headers and body
+    fn serve_one(listener: &TcpListener) -> (Vec<String>, Vec<u8>) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            let _ = reader.read_line(&mut line).unwrap();
+            let line = line.trim_end().to_string();
+            if line.is_empty() {
+                break;
+            }
+            headers.push(line);
+        }
+
+        let len = headers
+            .iter()
+            .find_map(|header| {
+                let (name, value) = header.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().unwrap())
+            })
+            .unwrap();
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+
+        reader
+            .into_inner()
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+            .unwrap();
+        (headers, body)
+    }
+
+    #[tokio::test]
+    async fn test_completed_job_is_posted() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/jobs", listener.local_addr().unwrap())).unwrap();
+        let server = std::thread::spawn(move || serve_one(&listener));
+
+        let notifier = JobNotifier::new(WebhookNotifier::spawn(
+            WebhookConfig::new(url).with_auth_header("Bearer secret"),
+        ));
+        notifier.notify(1, 0, 7, JobLifecycleStatus::Completed);
+
+        let (headers, body) = tokio::task::spawn_blocking(move || server.join().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(headers[0], "POST /jobs HTTP/1.1");
+        assert!(headers
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case("authorization: Bearer secret")));
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "service_id": 1, "job_id": 0, "call_id": 7, "status": "completed" })
+        );
+    }
+
+    #[test]
+    fn test_auth_header_is_redacted() {
+        let url = Url::parse("http://127.0.0.1/jobs").unwrap();
+        let config = WebhookConfig::new(url).with_auth_header("Bearer secret");
+        let debug = format!("{config:?}");
+        assert!(!debug.contains("secret"), "{debug}");
+        assert!(debug.contains("<redacted>"), "{debug}");
+    }
+
+    #[test]
+    fn test_failures_carry_their_error() {
+        let event = JobLifecycleEvent {
+            service_id: 1,
+            job_id: 0,
+            call_id: 7,
+            status: JobLifecycleStatus::Failed("out of gas".into()),
+        };
+        assert_eq!(
+            serde_json::to_value(event).unwrap(),
+            serde_json::json!({
+                "service_id": 1,
+                "job_id": 0,
+                "call_id": 7,
+                "status": "failed",
+                "error": "out of gas"
+            })
+        );
+    }
+}
@@ -1,7 +1,7 @@
 //! Keystore backend implementations.
 
 use crate::keystore::bn254::{Public, Secret, Signature};
-use crate::keystore::{Backend, Error, TanglePairSigner};
+use crate::keystore::{Backend, Error, TanglePairSigner, GENERIC_SS58_PREFIX};
 use alloc::vec::Vec;
 use core::fmt::Display;
 use subxt::ext::sp_core::Pair;
@@ -42,6 +42,7 @@ impl<RwLock: lock_api::RawRwLock> GenericKeyStore<RwLock> {
             pair: subxt::tx::PairSigner::new(
                 sp_core_subxt::sr25519::Pair::from_seed_slice(seed).map_err(err_to_std_io_err)?,
             ),
+            ss58_prefix: GENERIC_SS58_PREFIX,
         })
     }
 
@@ -57,6 +58,7 @@ impl<RwLock: lock_api::RawRwLock> GenericKeyStore<RwLock> {
             pair: subxt::tx::PairSigner::new(
                 sp_core_subxt::ecdsa::Pair::from_seed_slice(&seed).map_err(err_to_std_io_err)?,
             ),
+            ss58_prefix: GENERIC_SS58_PREFIX,
         })
     }
 
@@ -72,6 +74,7 @@ impl<RwLock: lock_api::RawRwLock> GenericKeyStore<RwLock> {
             pair: subxt::tx::PairSigner::new(
                 sp_core_subxt::ed25519::Pair::from_seed_slice(&seed).map_err(err_to_std_io_err)?,
             ),
+            ss58_prefix: GENERIC_SS58_PREFIX,
         })
     }
 }
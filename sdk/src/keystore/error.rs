@@ -30,6 +30,9 @@ pub enum Error {
     /// An error occurred during bls_bn254 module operation
     #[error("alloy_ecdsa: {0}")]
     Alloy(String),
+    /// An error occurred while importing keys
+    #[error("import: {0}")]
+    Import(String),
 }
 
 impl From<ed25519_zebra::Error> for Error {
@@ -0,0 +1,223 @@
+//! Bulk import of keys into a keystore [`Backend`].
+//!
+//! Keys are described as a JSON array of entries, each holding a key type and a
+//! hex-encoded 32-byte seed:
+//!
+//! ```json
+//! [
+//!     { "key_type": "sr25519", "seed": "0x..." },
+//!     { "key_type": "ecdsa", "seed": "0x..." }
+//! ]
+//! ```
+//!
+//! This is meant for automated provisioning, where keys are mounted as a file or passed
+//! through the environment instead of being inserted by hand.
+
+use crate::keystore::{Backend, Error, KeyType};
+use serde::Deserialize;
+use std::path::Path;
+
+/// A single key to import into a keystore
+#[derive(Clone, Deserialize)]
+pub struct KeyImport {
+    /// The type of the key
+    pub key_type: KeyType,
+    /// The hex-encoded 32-byte seed of the key, optionally `0x`-prefixed
+    pub seed: String,
+}
+
+impl core::fmt::Debug for KeyImport {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // The seed is the secret key itself, so it must never end up in the logs
+        f.debug_struct("KeyImport")
+            .field("key_type", &self.key_type)
+            .field("seed", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Import every key in `entries` into `backend`.
+///
+/// All entries are validated before any key is inserted, so a malformed entry
+/// leaves the keystore untouched.
+///
+/// # Errors
+///
+/// * An entry has a seed that is not 32 bytes of valid hex.
+/// * An entry has a key type that cannot be derived from a seed.
+/// * The backend fails to insert a key.
+pub fn import_keys<B: Backend>(backend: &B, entries: &[KeyImport]) -> Result<(), Error> {
+    let keys = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            // The errors never include the seed, not even the invalid character of a seed
+            let seed = hex::decode(entry.seed.trim_start_matches("0x")).map_err(|e| {
+                let reason = match e {
+                    hex::FromHexError::InvalidHexCharacter { index, .. } => {
+                        format!("invalid character at position {index}")
+                    }
+                    e => e.to_string(),
+                };
+                Error::Import(format!("entry {index}: seed is not valid hex: {reason}"))
+            })?;
+            if seed.len() != 32 {
+                return Err(Error::Import(format!(
+                    "entry {index}: expected a 32-byte seed, got {} bytes",
+                    seed.len()
+                )));
+            }
+            match entry.key_type {
+                KeyType::Sr25519 => Ok(ValidatedKey::Sr25519(seed)),
+                KeyType::Ecdsa => Ok(ValidatedKey::Ecdsa(seed)),
+                KeyType::Ed25519 => Ok(ValidatedKey::Ed25519(seed)),
+                key_type @ (KeyType::Bls381 | KeyType::BlsBn254) => Err(Error::Import(format!(
+                    "entry {index}: importing {key_type} keys is not supported"
+                ))),
+            }
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    for key in keys {
+        match key {
+            ValidatedKey::Sr25519(seed) => {
+                let _ = backend.sr25519_generate_new(Some(&seed))?;
+            }
+            ValidatedKey::Ecdsa(seed) => {
+                let _ = backend.ecdsa_generate_new(Some(&seed))?;
+            }
+            ValidatedKey::Ed25519(seed) => {
+                let _ = backend.ed25519_generate_new(Some(&seed))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A [`KeyImport`] whose seed was decoded, and whose key type can be derived from a seed
+enum ValidatedKey {
+    Sr25519(Vec<u8>),
+    Ecdsa(Vec<u8>),
+    Ed25519(Vec<u8>),
+}
+
+/// Import the keys listed in the JSON file at `path` into `backend`.
+///
+/// # Errors
+///
+/// * The file cannot be read or is not a valid JSON list of [`KeyImport`]s.
+/// * See [`import_keys`].
+pub fn import_keys_from_file<B: Backend, P: AsRef<Path>>(
+    backend: &B,
+    path: P,
+) -> Result<(), Error> {
+    let contents = std::fs::read_to_string(path)?;
+    import_keys_from_json(backend, &contents)
+}
+
+/// Import the keys listed in the JSON value of the environment variable `var` into `backend`.
+///
+/// # Errors
+///
+/// * The variable is not set or is not a valid JSON list of [`KeyImport`]s.
+/// * See [`import_keys`].
+pub fn import_keys_from_env<B: Backend>(backend: &B, var: &str) -> Result<(), Error> {
+    // The error of a value that is not valid unicode would hold the value, seeds included
+    let contents = std::env::var(var).map_err(|e| {
+        let reason = match e {
+            std::env::VarError::NotPresent => "it is not set",
+            std::env::VarError::NotUnicode(_) => "it is not valid unicode",
+        };
+        Error::Import(format!("failed to read `{var}`: {reason}"))
+    })?;
+    import_keys_from_json(backend, &contents)
+}
+
+fn import_keys_from_json<B: Backend>(backend: &B, json: &str) -> Result<(), Error> {
+    // Only the position of a JSON error is reported, since its message may quote a seed
+    let entries: Vec<KeyImport> = serde_json::from_str(json).map_err(|e| {
+        Error::Import(format!(
+            "invalid key list: {:?} error at line {} column {}",
+            e.classify(),
+            e.line(),
+            e.column()
+        ))
+    })?;
+    import_keys(backend, &entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keystore::backend::mem::InMemoryKeystore;
+
+    #[test]
+    fn test_import_keys_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys.json");
+        let json = format!(
+            r#"[{{ "key_type": "sr25519", "seed": "0x{}" }}, {{ "key_type": "ecdsa", "seed": "{}" }}]"#,
+            hex::encode([1u8; 32]),
+            hex::encode([2u8; 32])
+        );
+        std::fs::write(&path, json).unwrap();
+
+        let keystore = InMemoryKeystore::<parking_lot::RawRwLock>::new();
+        import_keys_from_file(&keystore, &path).unwrap();
+
+        assert_eq!(keystore.iter_sr25519().count(), 1);
+        assert_eq!(keystore.iter_ecdsa().count(), 1);
+    }
+
+    #[test]
+    fn test_import_keys_rejects_short_seed() {
+        let keystore = InMemoryKeystore::<parking_lot::RawRwLock>::new();
+        let entries = [
+            KeyImport {
+                key_type: KeyType::Sr25519,
+                seed: hex::encode([1u8; 32]),
+            },
+            KeyImport {
+                key_type: KeyType::Ecdsa,
+                seed: "0x1234".to_string(),
+            },
+        ];
+
+        assert!(matches!(
+            import_keys(&keystore, &entries),
+            Err(Error::Import(_))
+        ));
+        assert_eq!(keystore.iter_sr25519().count(), 0);
+    }
+
+    #[test]
+    fn test_seeds_are_never_logged() {
+        let seed = hex::encode([7u8; 32]);
+        let entry = KeyImport {
+            key_type: KeyType::Sr25519,
+            seed: seed.clone(),
+        };
+        assert!(!format!("{entry:?}").contains(&seed));
+
+        let keystore = InMemoryKeystore::<parking_lot::RawRwLock>::new();
+        let invalid = [
+            format!(
+                r#"[{{ "key_type": "sr25519", "seed": "{}z" }}]"#,
+                &seed[1..]
+            ),
+            format!(r#"[{{ "key_type": "{seed}", "seed": "{seed}" }}]"#),
+            format!(r#"[{{ "key_type": "sr25519", "seed": ["{seed}"] }}]"#),
+        ];
+        for json in invalid {
+            let err = import_keys_from_json(&keystore, &json).unwrap_err();
+            assert!(!format!("{err} {err:?}").contains(&seed[1..]), "{err}");
+        }
+
+        let var = "GADGET_TEST_SEEDS_ARE_NEVER_LOGGED";
+        std::env::set_var(var, format!(r#"[{{ "key_type": "{seed}" }}]"#));
+        let err = import_keys_from_env(&keystore, var).unwrap_err();
+        std::env::remove_var(var);
+        assert!(!format!("{err} {err:?}").contains(&seed[1..]), "{err}");
+    }
+}
@@ -41,10 +41,14 @@ pub mod ed25519;
 /// Keystore errors module
 pub mod error;
 
+/// Bulk key import from files and environment variables
+#[cfg(feature = "std")]
+pub mod import;
+
 /// Schnorrkel Support
 pub mod sr25519;
 
-use crate::clients::tangle::runtime::TangleConfig;
+use crate::clients::tangle::runtime::{account_id_to_ss58, TangleConfig};
 use crate::keystore::sp_core_subxt::crypto::{DeriveError, SecretStringError};
 use crate::keystore::sp_core_subxt::DeriveJunction;
 #[cfg(any(feature = "std", feature = "wasm"))]
@@ -61,10 +65,15 @@ use subxt_core::utils::{AccountId32, MultiAddress, MultiSignature};
 #[cfg(any(feature = "std", feature = "wasm"))]
 use tangle_subxt::subxt;
 
+/// The SS58 prefix of generic Substrate addresses, used by signers unless configured otherwise
+pub const GENERIC_SS58_PREFIX: u16 = 42;
+
 #[cfg(any(feature = "std", feature = "wasm"))]
 #[derive(Clone, Debug)]
 pub struct TanglePairSigner<Pair> {
     pub(crate) pair: subxt::tx::PairSigner<TangleConfig, Pair>,
+    /// The SS58 prefix the account address is formatted with
+    pub(crate) ss58_prefix: u16,
 }
 
 #[cfg(any(feature = "std", feature = "wasm"))]
@@ -81,6 +90,7 @@ where
     pub fn new(pair: Pair) -> Self {
         TanglePairSigner {
             pair: PairSigner::new(pair),
+            ss58_prefix: GENERIC_SS58_PREFIX,
         }
     }
 
@@ -91,6 +101,36 @@ where
     pub fn signer(&self) -> &Pair {
         self.pair.signer()
     }
+
+    /// Format the account address with `ss58_prefix`, e.g. the prefix of the chain this signer
+    /// submits to, instead of [`GENERIC_SS58_PREFIX`]
+    #[must_use]
+    pub fn with_ss58_prefix(mut self, ss58_prefix: u16) -> Self {
+        self.ss58_prefix = ss58_prefix;
+        self
+    }
+
+    /// The SS58 prefix the account address is formatted with
+    pub fn ss58_prefix(&self) -> u16 {
+        self.ss58_prefix
+    }
+
+    /// The account address, SS58-encoded with the configured prefix
+    pub fn ss58_address(&self) -> String {
+        account_id_to_ss58(&self.pair.account_id(), self.ss58_prefix)
+    }
+}
+
+/// Formats the signer as its SS58 address
+#[cfg(any(feature = "std", feature = "wasm"))]
+impl<Pair: sp_core_subxt::Pair> core::fmt::Display for TanglePairSigner<Pair>
+where
+    <Pair as sp_core_subxt::Pair>::Signature: Into<MultiSignature>,
+    subxt::ext::sp_runtime::MultiSigner: From<<Pair as sp_core_subxt::Pair>::Public>,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.ss58_address())
+    }
 }
 
 #[cfg(any(feature = "std", feature = "wasm"))]
@@ -131,6 +171,7 @@ where
             (
                 TanglePairSigner {
                     pair: PairSigner::new(pair),
+                    ss58_prefix: self.ss58_prefix,
                 },
                 seed,
             )
@@ -140,6 +181,7 @@ where
     fn from_seed_slice(seed: &[u8]) -> Result<Self, SecretStringError> {
         Pair::from_seed_slice(seed).map(|pair| TanglePairSigner {
             pair: PairSigner::new(pair),
+            ss58_prefix: GENERIC_SS58_PREFIX,
         })
     }
 
@@ -327,9 +369,52 @@ pub trait Backend {
     fn iter_bls_bn254(&self) -> impl Iterator<Item = bn254::Public>;
 }
 
+/// The kinds of keys a [`Backend`] can hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyType {
+    /// An sr25519 key, used to sign Tangle extrinsics
+    Sr25519,
+    /// An ECDSA key, used for networking and EVM interaction
+    Ecdsa,
+    /// An ed25519 key
+    Ed25519,
+    /// A BLS381 key
+    Bls381,
+    /// A BLS BN254 key
+    BlsBn254,
+}
+
+impl core::fmt::Display for KeyType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            KeyType::Sr25519 => write!(f, "sr25519"),
+            KeyType::Ecdsa => write!(f, "ecdsa"),
+            KeyType::Ed25519 => write!(f, "ed25519"),
+            KeyType::Bls381 => write!(f, "bls381"),
+            KeyType::BlsBn254 => write!(f, "bls_bn254"),
+        }
+    }
+}
+
 /// A convenience trait to extend the [`Backend`] trait with additional methods
 /// that provide convenient access to keys
 pub trait BackendExt: Backend {
+    /// Returns every key type in `required` that has no key in this keystore
+    fn missing_key_types(&self, required: &[KeyType]) -> Vec<KeyType> {
+        required
+            .iter()
+            .copied()
+            .filter(|key_type| match key_type {
+                KeyType::Sr25519 => self.iter_sr25519().next().is_none(),
+                KeyType::Ecdsa => self.iter_ecdsa().next().is_none(),
+                KeyType::Ed25519 => self.iter_ed25519().next().is_none(),
+                KeyType::Bls381 => self.iter_bls381().next().is_none(),
+                KeyType::BlsBn254 => self.iter_bls_bn254().next().is_none(),
+            })
+            .collect()
+    }
+
     #[cfg(any(feature = "std", feature = "wasm"))]
     fn ecdsa_key(&self) -> Result<TanglePairSigner<sp_core_subxt::ecdsa::Pair>, Error> {
         let first_key = self
@@ -344,6 +429,7 @@ pub trait BackendExt: Backend {
         seed.copy_from_slice(&ecdsa_secret.to_bytes()[0..32]);
         Ok(TanglePairSigner {
             pair: subxt::tx::PairSigner::new(sp_core_subxt::ecdsa::Pair::from_seed(&seed)),
+            ss58_prefix: GENERIC_SS58_PREFIX,
         })
     }
 
@@ -358,7 +444,10 @@ pub trait BackendExt: Backend {
             .ok_or_else(|| Error::Sr25519("No SR25519 secret found".to_string()))?;
         let schnorrkel_kp = schnorrkel::Keypair::from(secret);
         let pair = subxt::tx::PairSigner::new(schnorrkel_kp.into());
-        Ok(TanglePairSigner { pair })
+        Ok(TanglePairSigner {
+            pair,
+            ss58_prefix: GENERIC_SS58_PREFIX,
+        })
     }
 
     fn ed25519_key(&self) -> Result<TanglePairSigner<sp_core_subxt::ed25519::Pair>, Error> {
@@ -374,6 +463,7 @@ pub trait BackendExt: Backend {
         seed.copy_from_slice(&ed25519_secret.as_ref()[0..32]);
         Ok(TanglePairSigner {
             pair: subxt::tx::PairSigner::new(sp_core_subxt::ed25519::Pair::from_seed(&seed)),
+            ss58_prefix: GENERIC_SS58_PREFIX,
         })
     }
 
@@ -413,8 +503,50 @@ impl<T: AsRef<Path>> KeystoreUriSanitizer for T {}
 
 #[cfg(test)]
 mod tests {
-    use crate::keystore::KeystoreUriSanitizer;
+    use crate::keystore::backend::mem::InMemoryKeystore;
+    use crate::keystore::sp_core_subxt::crypto::{Ss58AddressFormat, Ss58Codec};
+    use crate::keystore::{
+        Backend, BackendExt, KeyType, KeystoreUriSanitizer, GENERIC_SS58_PREFIX,
+    };
     use std::path::PathBuf;
+    use subxt::tx::Signer;
+    use tangle_subxt::subxt;
+
+    #[test]
+    fn test_missing_key_types() {
+        let keystore = InMemoryKeystore::<parking_lot::RawRwLock>::new();
+        let required = [KeyType::Sr25519, KeyType::Ecdsa];
+        assert_eq!(keystore.missing_key_types(&required), required.to_vec());
+
+        let _ = keystore.sr25519_generate_new(None).unwrap();
+        assert_eq!(keystore.missing_key_types(&required), vec![KeyType::Ecdsa]);
+
+        let _ = keystore.ecdsa_generate_new(None).unwrap();
+        assert!(keystore.missing_key_types(&required).is_empty());
+    }
+
+    #[test]
+    fn test_signer_address_uses_configured_ss58_prefix() {
+        let keystore = InMemoryKeystore::<parking_lot::RawRwLock>::new();
+        let _ = keystore.sr25519_generate_new(None).unwrap();
+        let signer = keystore.sr25519_key().unwrap();
+        assert_eq!(signer.ss58_prefix(), GENERIC_SS58_PREFIX);
+        let generic = signer.ss58_address();
+
+        // Tangle mainnet
+        let signer = signer.with_ss58_prefix(5845);
+        let address = signer.ss58_address();
+        assert_ne!(address, generic);
+        assert_eq!(signer.to_string(), address);
+
+        let (account, format) =
+            crate::keystore::sp_core_subxt::crypto::AccountId32::from_ss58check_with_version(
+                &address,
+            )
+            .unwrap();
+        assert_eq!(format, Ss58AddressFormat::custom(5845));
+        assert_eq!(<[u8; 32]>::from(account), signer.account_id().0);
+    }
 
     #[test]
     fn test_sanitize_file_paths() {
@@ -48,6 +48,23 @@ macro_rules! info {
     }
 }
 
+/// A span carrying the ids of a job call, see [`with_job_span`]
+#[must_use]
+pub fn job_span(service_id: u64, job_id: u8, call_id: u64) -> tracing::Span {
+    tracing::info_span!(target: "gadget", "job", service_id, job_id, call_id)
+}
+
+/// Runs `job` within a [`job_span`], so that every log line it emits carries the ids of the job
+/// call it handles, and the logs of concurrent jobs can be told apart
+pub async fn with_job_span<F: core::future::Future>(
+    service_id: u64,
+    job_id: u8,
+    call_id: u64,
+    job: F,
+) -> F::Output {
+    tracing::Instrument::instrument(job, job_span(service_id, job_id, call_id)).await
+}
+
 /// Sets up the logging for any crate
 pub fn setup_log() {
     use tracing_subscriber::util::SubscriberInitExt;
@@ -59,3 +76,51 @@ pub fn setup_log() {
         .finish()
         .try_init();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects everything logged through it
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_logs_within_a_job_carry_its_ids() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .without_time()
+            .with_writer(move || writer.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            futures::executor::block_on(async {
+                info!("before the job");
+                with_job_span(1, 0, 7, async { info!("computing the result") }).await;
+                info!("after the job");
+            });
+        });
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let lines = logs.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("job{service_id=1 job_id=0 call_id=7}"));
+        assert!(lines[1].contains("computing the result"));
+        assert!(!lines[0].contains("call_id"));
+        assert!(!lines[2].contains("call_id"));
+    }
+}
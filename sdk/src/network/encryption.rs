@@ -0,0 +1,146 @@
+//! Optional authenticated encryption of protocol payloads exchanged over the network.
+//!
+//! Transport-level encryption (noise) only protects a message between two directly connected
+//! peers, while gossip is relayed by every subscriber of a topic. A [`SessionCipher`] is a key
+//! shared only by the participants of a protocol session, so relaying peers outside the session
+//! can neither read nor tamper with the protocol messages.
+
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use sp_core::{ecdsa, keccak_256};
+
+use crate::error::Error;
+use rand::RngCore;
+
+/// The length, in bytes, of the nonce prepended to every encrypted payload.
+pub const NONCE_LEN: usize = 12;
+
+/// A symmetric `ChaCha20-Poly1305` key shared by the participants of a protocol session.
+#[derive(Clone)]
+pub struct SessionCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl core::fmt::Debug for SessionCipher {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SessionCipher").finish_non_exhaustive()
+    }
+}
+
+impl SessionCipher {
+    /// Creates a cipher from a raw 32-byte session key.
+    #[must_use]
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    /// Derives the session key from a secret established between the participants (e.g. during
+    /// keygen), bound to the participant set.
+    ///
+    /// The participants are sorted first, so every participant derives the same key regardless of
+    /// the order in which they learned about each other.
+    #[must_use]
+    pub fn derive(shared_secret: &[u8], participants: &[ecdsa::Public]) -> Self {
+        let mut participants = participants.to_vec();
+        participants.sort();
+
+        let mut preimage = Vec::with_capacity(shared_secret.len() + participants.len() * 33);
+        preimage.extend_from_slice(shared_secret);
+        for participant in &participants {
+            preimage.extend_from_slice(participant.as_ref());
+        }
+
+        Self::new(keccak_256(&preimage))
+    }
+
+    /// Encrypts `plaintext`, returning the random nonce followed by the ciphertext.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload could not be encrypted.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|e| Error::Network {
+                reason: format!("Failed to encrypt payload: {e}"),
+            })?;
+
+        let mut payload = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        payload.extend_from_slice(&nonce);
+        payload.extend_from_slice(&ciphertext);
+        Ok(payload)
+    }
+
+    /// Decrypts a payload produced by [`SessionCipher::encrypt`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload is malformed, was encrypted under a different key, or was
+    /// tampered with.
+    pub fn decrypt(&self, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        if payload.len() < NONCE_LEN {
+            return Err(Error::Network {
+                reason: "Encrypted payload is too short".into(),
+            });
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| Error::Network {
+                reason: "Failed to decrypt payload: invalid session key or corrupted payload"
+                    .into(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp_core::Pair;
+
+    fn participants() -> Vec<ecdsa::Public> {
+        (0..3u8)
+            .map(|i| ecdsa::Pair::from_seed(&[i + 1; 32]).public())
+            .collect()
+    }
+
+    #[test]
+    fn test_participants_derive_the_same_key() {
+        let participants = participants();
+        let mut reversed = participants.clone();
+        reversed.reverse();
+
+        let alice = SessionCipher::derive(b"keygen-secret", &participants);
+        let bob = SessionCipher::derive(b"keygen-secret", &reversed);
+
+        let payload = alice.encrypt(b"round 1 message").unwrap();
+        assert_ne!(&payload[NONCE_LEN..], b"round 1 message");
+        assert_eq!(bob.decrypt(&payload).unwrap(), b"round 1 message");
+    }
+
+    #[test]
+    fn test_eavesdropper_cannot_decrypt() {
+        let participants = participants();
+        let alice = SessionCipher::derive(b"keygen-secret", &participants);
+        let payload = alice.encrypt(b"round 1 message").unwrap();
+
+        // Knows the participant set, but not the secret established during keygen
+        let eavesdropper = SessionCipher::derive(b"guessed-secret", &participants);
+        assert!(eavesdropper.decrypt(&payload).is_err());
+
+        // Knows the secret, but is not part of the session
+        let outsider = SessionCipher::derive(b"keygen-secret", &participants[..2]);
+        assert!(outsider.decrypt(&payload).is_err());
+
+        let mut tampered = payload.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(alice.decrypt(&tampered).is_err());
+    }
+}
@@ -14,12 +14,16 @@ use libp2p::{
     gossipsub, mdns, request_response, swarm::NetworkBehaviour, swarm::SwarmEvent, PeerId,
 };
 use serde::{Deserialize, Serialize};
-use sp_core::ecdsa;
+use sp_core::{ecdsa, keccak_256, Pair};
+use sp_io::crypto::ecdsa_verify_prehashed;
 use std::collections::BTreeMap;
 use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
 
 use crate::error::Error;
+use crate::network::encryption::SessionCipher;
+use crate::network::replay::{now_millis, ReplayCache};
+use crate::network::versioning::{implied_versions, VersionedProtocol};
 use crate::{debug, error, trace, warn};
 
 use super::{Network, ParticipantInfo, ProtocolMessage};
@@ -43,10 +47,48 @@ pub struct MyBehaviour {
 
 pub type InboundMapping = (IdentTopic, UnboundedSender<Vec<u8>>, Arc<AtomicU32>);
 
+/// The versions each participant advertised in its handshake for each topic, see
+/// [`versioning`](crate::network::versioning)
+pub type PeerProtocolVersions = Arc<RwLock<BTreeMap<ecdsa::Public, BTreeMap<String, Vec<u32>>>>>;
+
+/// The [`GOSSIP_QUEUE_DEPTH`](crate::prometheus::GOSSIP_QUEUE_DEPTH) direction of messages
+/// received from the network, waiting for the protocol
+const INBOUND: &str = "inbound";
+/// The [`GOSSIP_QUEUE_DEPTH`](crate::prometheus::GOSSIP_QUEUE_DEPTH) direction of messages sent
+/// by the protocol, waiting for the network
+const OUTBOUND: &str = "outbound";
+
+fn queue_depth(topic: &str, direction: &str) -> prometheus::IntGauge {
+    crate::prometheus::GOSSIP_QUEUE_DEPTH.with_label_values(&[topic, direction])
+}
+
+/// Hands a message received from the network to the protocol listening on `topic`
+pub(crate) fn forward_to_worker(
+    inbound_mapping: &[InboundMapping],
+    topic: &str,
+    raw_payload: Vec<u8>,
+) {
+    let Some((_, tx, _)) = inbound_mapping.iter().find(|r| r.0.to_string() == topic) else {
+        error!("No registered worker for topic: {topic}!");
+        return;
+    };
+
+    let depth = queue_depth(topic, INBOUND);
+    depth.inc();
+    if let Err(e) = tx.send(raw_payload) {
+        depth.dec();
+        error!("Failed to send message to worker: {e}");
+    }
+}
+
 pub struct NetworkServiceWithoutSwarm<'a> {
     pub inbound_mapping: &'a [InboundMapping],
     pub ecdsa_peer_id_to_libp2p_id: Arc<RwLock<BTreeMap<ecdsa::Public, PeerId>>>,
     pub ecdsa_key: &'a ecdsa::Pair,
+    pub replay_cache: Mutex<ReplayCache>,
+    /// The versions this node supports for each topic, advertised in its handshakes
+    pub protocol_versions: &'a BTreeMap<String, Vec<u32>>,
+    pub peer_protocol_versions: PeerProtocolVersions,
     pub span: tracing::Span,
 }
 
@@ -60,6 +102,9 @@ impl<'a> NetworkServiceWithoutSwarm<'a> {
             inbound_mapping: self.inbound_mapping,
             ecdsa_peer_id_to_libp2p_id: &self.ecdsa_peer_id_to_libp2p_id,
             ecdsa_key: self.ecdsa_key,
+            replay_cache: &self.replay_cache,
+            protocol_versions: self.protocol_versions,
+            peer_protocol_versions: &self.peer_protocol_versions,
             span: &self.span,
         }
     }
@@ -70,6 +115,10 @@ pub struct NetworkService<'a> {
     pub inbound_mapping: &'a [InboundMapping],
     pub ecdsa_peer_id_to_libp2p_id: &'a Arc<RwLock<BTreeMap<ecdsa::Public, PeerId>>>,
     pub ecdsa_key: &'a ecdsa::Pair,
+    pub replay_cache: &'a Mutex<ReplayCache>,
+    /// The versions this node supports for each topic, advertised in its handshakes
+    pub protocol_versions: &'a BTreeMap<String, Vec<u32>>,
+    pub peer_protocol_versions: &'a PeerProtocolVersions,
     pub span: &'a tracing::Span,
 }
 
@@ -77,9 +126,11 @@ impl<'a> NetworkService<'a> {
     /// Handle local requests that are meant to be sent to the network.
     pub(crate) fn handle_intra_node_payload(&mut self, msg: IntraNodePayload) {
         let _enter = self.span.enter();
+        queue_depth(&msg.topic.to_string(), OUTBOUND).dec();
         match (msg.message_type, msg.payload) {
             (MessageType::Broadcast, GossipOrRequestResponse::Gossip(payload)) => {
-                let gossip_message = bincode::serialize(&payload).expect("Should serialize");
+                let signed = SignedGossipMessage::sign(payload, self.ecdsa_key);
+                let gossip_message = bincode::serialize(&signed).expect("Should serialize");
                 if let Err(e) = self
                     .swarm
                     .behaviour_mut()
@@ -254,9 +305,24 @@ pub struct GossipHandle {
     pub rx_from_inbound: Arc<Mutex<gadget_io::tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>>>,
     pub connected_peers: Arc<AtomicU32>,
     pub ecdsa_peer_id_to_libp2p_id: Arc<RwLock<BTreeMap<ecdsa::Public, PeerId>>>,
+    /// When set, protocol payloads are encrypted under this session key before leaving the node
+    pub session_cipher: Option<SessionCipher>,
+    /// The versions of this topic's protocol supported by this node
+    pub protocol: VersionedProtocol,
+    pub peer_protocol_versions: PeerProtocolVersions,
 }
 
 impl GossipHandle {
+    /// Encrypts all protocol messages sent and received through this handle with `cipher`.
+    ///
+    /// Every participant of the session must use the same cipher, see [`SessionCipher::derive`].
+    /// Inbound messages which can't be decrypted are dropped.
+    #[must_use]
+    pub fn with_session_cipher(mut self, cipher: SessionCipher) -> Self {
+        self.session_cipher = Some(cipher);
+        self
+    }
+
     #[must_use]
     pub fn connected_peers(&self) -> usize {
         self.connected_peers
@@ -268,6 +334,21 @@ impl GossipHandle {
         self.topic.clone()
     }
 
+    /// The version of this topic's protocol to speak with `peer`, e.g. the sender of a
+    /// [`ProtocolMessage`]: the highest version both this node and `peer` support.
+    ///
+    /// A peer that did not advertise its versions in its handshake supports the version in the
+    /// name of the topic, see [`implied_versions`]. Returns `None` if no handshake with `peer`
+    /// happened yet, or if the two have no version in common.
+    pub async fn negotiated_version(&self, peer: &ecdsa::Public) -> Option<u32> {
+        let peer_protocol_versions = self.peer_protocol_versions.read().await;
+        let topic = self.topic.to_string();
+        match peer_protocol_versions.get(peer)?.get(&topic) {
+            Some(versions) => self.protocol.negotiate(versions),
+            None => self.protocol.negotiate(&implied_versions(&topic)),
+        }
+    }
+
     /// Returns an ordered vector of public keys of the peers that are connected to the gossipsub topic.
     pub async fn peers(&self) -> Vec<ecdsa::Public> {
         self.ecdsa_peer_id_to_libp2p_id
@@ -277,6 +358,77 @@ impl GossipHandle {
             .copied()
             .collect()
     }
+
+    /// Reports how many members of `committee` are connected on this topic, compared to the
+    /// `threshold + 1` a threshold protocol, such as a keygen, needs to make progress.
+    ///
+    /// The readiness is also exported through the `committee_peers_connected` and
+    /// `committee_peers_required` Prometheus gauges.
+    pub async fn committee_readiness(
+        &self,
+        local: &ecdsa::Public,
+        committee: &[ecdsa::Public],
+        threshold: u16,
+    ) -> PeerReadiness {
+        let readiness = PeerReadiness::new(local, committee, &self.peers().await, threshold);
+        crate::prometheus::COMMITTEE_PEERS_CONNECTED
+            .set(i64::try_from(readiness.connected).unwrap_or(i64::MAX));
+        crate::prometheus::COMMITTEE_PEERS_REQUIRED
+            .set(i64::try_from(readiness.required).unwrap_or(i64::MAX));
+        readiness
+    }
+}
+
+/// How many committee members are connected on a gossip topic, versus how many are required
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerReadiness {
+    /// The committee members currently reachable, including the local node
+    pub connected: usize,
+    /// The committee members required, `threshold + 1`
+    pub required: usize,
+}
+
+impl PeerReadiness {
+    /// Counts the members of `committee` among the `connected` peers, and the `local` node
+    #[must_use]
+    pub fn new(
+        local: &ecdsa::Public,
+        committee: &[ecdsa::Public],
+        connected: &[ecdsa::Public],
+        threshold: u16,
+    ) -> Self {
+        let connected = committee
+            .iter()
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .filter(|member| *member == local || connected.contains(member))
+            .count();
+        Self {
+            connected,
+            required: usize::from(threshold) + 1,
+        }
+    }
+
+    /// Whether enough committee members are connected for the protocol to start
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        self.connected >= self.required
+    }
+}
+
+impl std::fmt::Display for PeerReadiness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = if self.is_ready() {
+            "ready"
+        } else {
+            "waiting for peers"
+        };
+        write!(
+            f,
+            "{status}: {}/{} committee members connected",
+            self.connected, self.required
+        )
+    }
 }
 
 pub struct IntraNodePayload {
@@ -307,12 +459,80 @@ pub struct GossipMessage {
     pub raw_payload: Vec<u8>,
 }
 
+/// A [`GossipMessage`] as it is sent over the wire, signed by the ECDSA key of its sender.
+///
+/// Gossip is relayed by other peers, so the libp2p source alone says nothing about which
+/// participant produced a message. The signature binds the message to the sender's ECDSA key,
+/// which in turn is bound to a libp2p peer through the handshake. The signed nonce and timestamp
+/// let receivers drop replayed messages, see [`ReplayCache`].
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignedGossipMessage {
+    pub message: GossipMessage,
+    /// Random per-message nonce, unique for each sender
+    pub nonce: u64,
+    /// When the message was signed, in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    pub sender: ecdsa::Public,
+    pub signature: ecdsa::Signature,
+}
+
+impl SignedGossipMessage {
+    /// Signs `message` with `key`, under a fresh nonce and the current time.
+    #[must_use]
+    pub fn sign(message: GossipMessage, key: &ecdsa::Pair) -> Self {
+        let nonce = rand::random();
+        let timestamp = now_millis();
+        let hash = Self::signing_hash(&message, nonce, timestamp);
+        Self {
+            signature: key.sign_prehashed(&hash),
+            sender: key.public(),
+            message,
+            nonce,
+            timestamp,
+        }
+    }
+
+    /// Checks that this message was signed by its claimed sender, and that the sender is a
+    /// participant we completed a handshake with, published from the libp2p peer `origin`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the message could not be authenticated.
+    pub fn authenticate(
+        &self,
+        origin: &PeerId,
+        ecdsa_peer_id_to_libp2p_id: &BTreeMap<ecdsa::Public, PeerId>,
+    ) -> Result<(), String> {
+        let hash = Self::signing_hash(&self.message, self.nonce, self.timestamp);
+        if !ecdsa_verify_prehashed(&self.signature, &hash, &self.sender) {
+            return Err(format!("Invalid signature for sender {}", self.sender));
+        }
+
+        match ecdsa_peer_id_to_libp2p_id.get(&self.sender) {
+            Some(peer_id) if peer_id == origin => Ok(()),
+            Some(peer_id) => Err(format!(
+                "Sender {} belongs to {peer_id}, but the message was published by {origin}",
+                self.sender
+            )),
+            None => Err(format!("Sender {} is not a known participant", self.sender)),
+        }
+    }
+
+    fn signing_hash(message: &GossipMessage, nonce: u64, timestamp: u64) -> [u8; 32] {
+        let signed = (message, nonce, timestamp);
+        keccak_256(&bincode::serialize(&signed).expect("Should serialize"))
+    }
+}
+
 #[non_exhaustive]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum MyBehaviourRequest {
     Handshake {
         ecdsa_public_key: ecdsa::Public,
         signature: ecdsa::Signature,
+        /// The versions supported for each topic, left out by nodes predating versioning
+        #[serde(default)]
+        protocol_versions: BTreeMap<String, Vec<u32>>,
     },
     Message {
         topic: String,
@@ -326,6 +546,9 @@ pub enum MyBehaviourResponse {
     Handshaked {
         ecdsa_public_key: ecdsa::Public,
         signature: ecdsa::Signature,
+        /// The versions supported for each topic, left out by nodes predating versioning
+        #[serde(default)]
+        protocol_versions: BTreeMap<String, Vec<u32>>,
     },
     MessageHandled,
 }
@@ -343,7 +566,19 @@ impl Network for GossipHandle {
             .try_lock()
             .expect("There should be only a single caller for `next_message`");
 
-        let message = lock.recv().await?;
+        let mut message = lock.recv().await?;
+        queue_depth(&self.topic.to_string(), INBOUND).dec();
+        if let Some(cipher) = &self.session_cipher {
+            match cipher.decrypt(&message) {
+                Ok(plaintext) => message = plaintext,
+                Err(e) => {
+                    warn!("Dropping message: {e}");
+                    drop(lock);
+                    return Network::next_message(self).await;
+                }
+            }
+        }
+
         match bincode::deserialize(&message) {
             Ok(message) => Some(message),
             Err(e) => {
@@ -377,14 +612,19 @@ impl Network for GossipHandle {
             MessageType::Broadcast
         };
 
+        let mut raw_payload = bincode::serialize(&message).expect("Should serialize");
+        if let Some(cipher) = &self.session_cipher {
+            raw_payload = cipher.encrypt(&raw_payload)?;
+        }
+
         let payload_inner = match message_type {
             MessageType::Broadcast => GossipOrRequestResponse::Gossip(GossipMessage {
                 topic: self.topic.to_string(),
-                raw_payload: bincode::serialize(&message).expect("Should serialize"),
+                raw_payload,
             }),
             MessageType::P2P(_) => GossipOrRequestResponse::Request(MyBehaviourRequest::Message {
                 topic: self.topic.to_string(),
-                raw_payload: bincode::serialize(&message).expect("Should serialize"),
+                raw_payload,
             }),
         };
 
@@ -394,10 +634,193 @@ impl Network for GossipHandle {
             message_type,
         };
 
-        self.tx_to_outbound
-            .send(payload)
-            .map_err(|e| Error::Network {
+        let depth = queue_depth(&self.topic.to_string(), OUTBOUND);
+        depth.inc();
+        self.tx_to_outbound.send(payload).map_err(|e| {
+            depth.dec();
+            Error::Network {
                 reason: format!("Failed to send intra-node payload: {e}"),
-            })
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_id(seed: u8) -> PeerId {
+        libp2p::identity::Keypair::ed25519_from_bytes([seed; 32])
+            .expect("Valid ed25519 seed")
+            .public()
+            .to_peer_id()
+    }
+
+    fn message() -> GossipMessage {
+        GossipMessage {
+            topic: "/tangle/test/1.0.0".to_string(),
+            raw_payload: b"round 1 message".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_authentic_message_is_accepted() {
+        let alice = ecdsa::Pair::from_seed(&[1u8; 32]);
+        let alice_peer = peer_id(1);
+        let known_peers = BTreeMap::from([(alice.public(), alice_peer)]);
+
+        let signed = SignedGossipMessage::sign(message(), &alice);
+        assert!(signed.authenticate(&alice_peer, &known_peers).is_ok());
+    }
+
+    #[test]
+    fn test_forged_sender_is_rejected() {
+        let alice = ecdsa::Pair::from_seed(&[1u8; 32]);
+        let mallory = ecdsa::Pair::from_seed(&[2u8; 32]);
+        let alice_peer = peer_id(1);
+        let mallory_peer = peer_id(2);
+        let known_peers = BTreeMap::from([
+            (alice.public(), alice_peer),
+            (mallory.public(), mallory_peer),
+        ]);
+
+        // Mallory signs with her own key, but claims the message came from Alice
+        let mut forged = SignedGossipMessage::sign(message(), &mallory);
+        forged.sender = alice.public();
+        assert!(forged.authenticate(&mallory_peer, &known_peers).is_err());
+
+        // Mallory republishes a genuine message from Alice as her own
+        let replayed = SignedGossipMessage::sign(message(), &alice);
+        assert!(replayed.authenticate(&mallory_peer, &known_peers).is_err());
+
+        // Mallory tampers with the payload of a genuine message
+        let mut tampered = SignedGossipMessage::sign(message(), &alice);
+        tampered.message.raw_payload = b"round 1 forgery".to_vec();
+        assert!(tampered.authenticate(&alice_peer, &known_peers).is_err());
+
+        // A non-participant signs a message with a key we never completed a handshake with
+        let outsider = ecdsa::Pair::from_seed(&[3u8; 32]);
+        let signed = SignedGossipMessage::sign(message(), &outsider);
+        assert!(signed.authenticate(&peer_id(3), &known_peers).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_version_is_negotiated_with_each_peer() {
+        let topic = IdentTopic::new("/tangle/keygen/1.0.0");
+        let (_, inbound_rx) = gadget_io::tokio::sync::mpsc::unbounded_channel();
+        let (tx_to_outbound, _) = gadget_io::tokio::sync::mpsc::unbounded_channel();
+        let old_node = ecdsa::Pair::from_seed(&[1u8; 32]).public();
+        let new_node = ecdsa::Pair::from_seed(&[2u8; 32]).public();
+        let unknown = ecdsa::Pair::from_seed(&[3u8; 32]).public();
+        let peer_protocol_versions = PeerProtocolVersions::default();
+        {
+            let mut peers = peer_protocol_versions.write().await;
+            // A node predating versioning advertises nothing in its handshake
+            peers.insert(old_node, BTreeMap::new());
+            peers.insert(new_node, BTreeMap::from([(topic.to_string(), vec![2, 1])]));
+        }
+        let handle = GossipHandle {
+            topic: topic.clone(),
+            tx_to_outbound,
+            rx_from_inbound: Arc::new(Mutex::new(inbound_rx)),
+            connected_peers: Arc::new(AtomicU32::new(0)),
+            ecdsa_peer_id_to_libp2p_id: Arc::default(),
+            session_cipher: None,
+            protocol: VersionedProtocol::new(&topic.to_string(), &[1, 2]),
+            peer_protocol_versions,
+        };
+
+        assert_eq!(handle.negotiated_version(&old_node).await, Some(1));
+        assert_eq!(handle.negotiated_version(&new_node).await, Some(2));
+        assert_eq!(handle.negotiated_version(&unknown).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_queue_depth_reflects_enqueued_messages() {
+        let topic = IdentTopic::new("/tangle/queue-depth-test/1.0.0");
+        let (inbound_tx, inbound_rx) = gadget_io::tokio::sync::mpsc::unbounded_channel();
+        let (tx_to_outbound, mut rx_to_outbound) =
+            gadget_io::tokio::sync::mpsc::unbounded_channel();
+        let inbound_mapping = [(topic.clone(), inbound_tx, Arc::new(AtomicU32::new(0)))];
+        let handle = GossipHandle {
+            topic: topic.clone(),
+            tx_to_outbound,
+            rx_from_inbound: Arc::new(Mutex::new(inbound_rx)),
+            connected_peers: Arc::new(AtomicU32::new(0)),
+            ecdsa_peer_id_to_libp2p_id: Arc::default(),
+            session_cipher: None,
+            protocol: VersionedProtocol::new(&topic.to_string(), &[1]),
+            peer_protocol_versions: Arc::default(),
+        };
+        let inbound = queue_depth(&topic.to_string(), INBOUND);
+        let outbound = queue_depth(&topic.to_string(), OUTBOUND);
+        let message = ProtocolMessage {
+            identifier_info: crate::network::IdentifierInfo {
+                block_id: None,
+                session_id: Some(1),
+                retry_id: None,
+                task_id: None,
+            },
+            sender: ParticipantInfo {
+                user_id: 0,
+                ecdsa_key: None,
+            },
+            recipient: None,
+            payload: b"round 1 message".to_vec(),
+        };
+
+        // Two messages arrive from the network before the protocol gets to them
+        for _ in 0..2 {
+            forward_to_worker(
+                &inbound_mapping,
+                &topic.to_string(),
+                bincode::serialize(&message).unwrap(),
+            );
+        }
+        assert_eq!(inbound.get(), 2);
+        assert!(handle.next_message().await.is_some());
+        assert_eq!(inbound.get(), 1);
+
+        // Messages for unknown topics are never enqueued
+        forward_to_worker(&inbound_mapping, "/tangle/unknown/1.0.0", Vec::new());
+        assert_eq!(queue_depth("/tangle/unknown/1.0.0", INBOUND).get(), 0);
+
+        // A broadcast waits for the network worker
+        handle.send_message(message).await.unwrap();
+        assert_eq!(outbound.get(), 1);
+        let _ = rx_to_outbound.recv().await.unwrap();
+    }
+
+    #[test]
+    fn test_committee_readiness_counts_connected_members() {
+        let [local, bob, charlie, outsider] =
+            [1u8, 2, 3, 4].map(|seed| ecdsa::Pair::from_seed(&[seed; 32]).public());
+        let committee = [local, bob, charlie];
+
+        // Only ourselves, so a 2-of-3 keygen is stalled
+        let readiness = PeerReadiness::new(&local, &committee, &[outsider], 1);
+        assert_eq!(
+            readiness,
+            PeerReadiness {
+                connected: 1,
+                required: 2
+            }
+        );
+        assert!(!readiness.is_ready());
+        assert_eq!(
+            readiness.to_string(),
+            "waiting for peers: 1/2 committee members connected"
+        );
+
+        let readiness = PeerReadiness::new(&local, &committee, &[bob, outsider], 1);
+        assert!(readiness.is_ready());
+        assert_eq!(
+            readiness.to_string(),
+            "ready: 2/2 committee members connected"
+        );
+
+        // A 3-of-3 keygen needs everyone
+        assert!(!PeerReadiness::new(&local, &committee, &[bob], 2).is_ready());
+        assert!(PeerReadiness::new(&local, &committee, &[bob, charlie], 2).is_ready());
     }
 }
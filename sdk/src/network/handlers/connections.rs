@@ -21,6 +21,7 @@ impl NetworkService<'_> {
             let handshake = MyBehaviourRequest::Handshake {
                 ecdsa_public_key: self.ecdsa_key.public(),
                 signature,
+                protocol_versions: self.protocol_versions.clone(),
             };
             self.swarm
                 .behaviour_mut()
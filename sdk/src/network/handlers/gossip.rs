@@ -1,8 +1,11 @@
 #![allow(unused_results)]
 
-use crate::network::gossip::{GossipMessage, NetworkService};
+use crate::network::gossip::{
+    forward_to_worker, GossipMessage, NetworkService, SignedGossipMessage,
+};
+use crate::network::replay::now_millis;
 
-use crate::{debug, error, trace};
+use crate::{debug, error, trace, warn};
 use libp2p::gossipsub::TopicHash;
 use libp2p::{gossipsub, PeerId};
 use std::sync::atomic::AtomicU32;
@@ -79,19 +82,27 @@ impl NetworkService<'_> {
             return;
         };
         debug!("Got message from peer: {origin}");
-        match bincode::deserialize::<GossipMessage>(&message.data) {
-            Ok(GossipMessage { topic, raw_payload }) => {
-                if let Some((_, tx, _)) = self
-                    .inbound_mapping
-                    .iter()
-                    .find(|r| r.0.to_string() == topic)
+        match bincode::deserialize::<SignedGossipMessage>(&message.data) {
+            Ok(signed) => {
+                if let Err(reason) =
+                    signed.authenticate(&origin, &*self.ecdsa_peer_id_to_libp2p_id.read().await)
                 {
-                    if let Err(e) = tx.send(raw_payload) {
-                        error!("Failed to send message to worker: {e}");
-                    }
-                } else {
-                    error!("No registered worker for topic: {topic}!");
+                    warn!("Dropping unauthenticated message from {origin}: {reason}");
+                    return;
+                }
+
+                if let Err(reason) = self.replay_cache.lock().await.check(
+                    signed.sender,
+                    signed.nonce,
+                    signed.timestamp,
+                    now_millis(),
+                ) {
+                    warn!("Dropping message from {origin}: {reason}");
+                    return;
                 }
+
+                let GossipMessage { topic, raw_payload } = signed.message;
+                forward_to_worker(self.inbound_mapping, &topic, raw_payload);
             }
             Err(e) => {
                 error!("Failed to deserialize message: {e}");
@@ -1,6 +1,8 @@
 #![allow(unused_results)]
 
-use crate::network::gossip::{MyBehaviourRequest, MyBehaviourResponse, NetworkService};
+use crate::network::gossip::{
+    forward_to_worker, MyBehaviourRequest, MyBehaviourResponse, NetworkService,
+};
 use crate::{debug, error, warn};
 
 use libp2p::gossipsub::IdentTopic;
@@ -79,6 +81,7 @@ impl NetworkService<'_> {
             Handshaked {
                 ecdsa_public_key,
                 signature,
+                protocol_versions,
             } => {
                 let msg = peer.to_bytes();
                 let hash = keccak_256(&msg);
@@ -90,6 +93,10 @@ impl NetworkService<'_> {
                         .write()
                         .await
                         .remove(&ecdsa_public_key);
+                    self.peer_protocol_versions
+                        .write()
+                        .await
+                        .remove(&ecdsa_public_key);
                     let _ = self.swarm.disconnect_peer_id(peer);
                     return;
                 }
@@ -97,6 +104,10 @@ impl NetworkService<'_> {
                     .write()
                     .await
                     .insert(ecdsa_public_key, peer);
+                self.peer_protocol_versions
+                    .write()
+                    .await
+                    .insert(ecdsa_public_key, protocol_versions);
             }
             MessageHandled => {}
         }
@@ -115,6 +126,7 @@ impl NetworkService<'_> {
             Handshake {
                 ecdsa_public_key,
                 signature,
+                protocol_versions,
             } => {
                 debug!("Received handshake from peer: {peer}");
                 // Verify the signature
@@ -130,7 +142,11 @@ impl NetworkService<'_> {
                     .write()
                     .await
                     .insert(ecdsa_public_key, peer);
-                // Send response with our public key
+                self.peer_protocol_versions
+                    .write()
+                    .await
+                    .insert(ecdsa_public_key, protocol_versions);
+                // Send response with our public key and versions
                 let my_peer_id = self.swarm.local_peer_id();
                 let msg = my_peer_id.to_bytes();
                 let hash = keccak_256(&msg);
@@ -140,22 +156,13 @@ impl NetworkService<'_> {
                     MyBehaviourResponse::Handshaked {
                         ecdsa_public_key: self.ecdsa_key.public(),
                         signature,
+                        protocol_versions: self.protocol_versions.clone(),
                     },
                 )
             }
             Message { topic, raw_payload } => {
                 let topic = IdentTopic::new(topic);
-                if let Some((_, tx, _)) = self
-                    .inbound_mapping
-                    .iter()
-                    .find(|r| r.0.to_string() == topic.to_string())
-                {
-                    if let Err(e) = tx.send(raw_payload) {
-                        error!("Failed to send message to worker: {e}");
-                    }
-                } else {
-                    error!("No registered worker for topic: {topic}!");
-                }
+                forward_to_worker(self.inbound_mapping, &topic.to_string(), raw_payload);
                 self.swarm
                     .behaviour_mut()
                     .p2p
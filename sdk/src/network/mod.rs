@@ -9,11 +9,14 @@ use crate::error::Error;
 use self::channels::UserID;
 
 pub mod channels;
+pub mod encryption;
 pub mod gossip;
 pub mod handlers;
 #[cfg(target_family = "wasm")]
 pub mod matchbox;
+pub mod replay;
 pub mod setup;
+pub mod versioning;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct IdentifierInfo {
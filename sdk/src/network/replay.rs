@@ -0,0 +1,213 @@
+//! Protection against replayed gossip messages.
+//!
+//! Every [`SignedGossipMessage`](crate::network::gossip::SignedGossipMessage) carries a random
+//! nonce and the time it was created, both covered by the sender's signature. The
+//! [`ReplayCache`] remembers the `(sender, nonce)` pairs seen within the message TTL, so that a
+//! captured message can be neither re-injected while it is fresh, nor after it has expired.
+
+use sp_core::ecdsa;
+use std::collections::{BTreeSet, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The default number of messages remembered by the [`ReplayCache`].
+pub const DEFAULT_REPLAY_CACHE_CAPACITY: usize = 16 * 1024;
+/// The default age after which a gossip message is considered stale and dropped.
+pub const DEFAULT_MESSAGE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// A bounded cache of recently seen gossip messages.
+#[derive(Debug)]
+pub struct ReplayCache {
+    capacity: usize,
+    ttl: Duration,
+    seen: BTreeSet<(ecdsa::Public, u64)>,
+    /// `(timestamp, sender, nonce)` in the order the messages were accepted
+    order: VecDeque<(u64, ecdsa::Public, u64)>,
+}
+
+impl ReplayCache {
+    /// Creates a cache remembering at most `capacity` messages, each for at most `ttl`.
+    ///
+    /// Once full, new messages are dropped until the remembered ones expire, since forgetting a
+    /// message within its TTL would let it be replayed. `capacity` should therefore comfortably
+    /// exceed the number of messages expected within `ttl`.
+    #[must_use]
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            seen: BTreeSet::new(),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// The number of messages currently remembered.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Whether no messages are currently remembered.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Records the message `nonce` from `sender`, created at `timestamp` (in milliseconds since
+    /// the UNIX epoch), as seen at time `now`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the message must be dropped, if it is stale, from the future, was
+    /// already seen, or the cache is full.
+    pub fn check(
+        &mut self,
+        sender: ecdsa::Public,
+        nonce: u64,
+        timestamp: u64,
+        now: u64,
+    ) -> Result<(), String> {
+        let ttl = u64::try_from(self.ttl.as_millis()).unwrap_or(u64::MAX);
+        if now.saturating_sub(timestamp) > ttl {
+            return Err(format!(
+                "Message from {sender} is older than the TTL: {timestamp}"
+            ));
+        }
+        // Allow for the same amount of clock drift into the future
+        if timestamp.saturating_sub(now) > ttl {
+            return Err(format!(
+                "Message from {sender} has a timestamp in the future: {timestamp}"
+            ));
+        }
+
+        self.evict(now, ttl);
+        if self.seen.contains(&(sender, nonce)) {
+            return Err(format!(
+                "Message from {sender} with nonce {nonce} was replayed"
+            ));
+        }
+        if self.order.len() >= self.capacity {
+            return Err(format!(
+                "Replay cache is full, dropping message from {sender}"
+            ));
+        }
+
+        let _ = self.seen.insert((sender, nonce));
+        self.order.push_back((timestamp, sender, nonce));
+        Ok(())
+    }
+
+    /// Forgets the messages older than the TTL, as those are rejected on their timestamp alone.
+    fn evict(&mut self, now: u64, ttl: u64) {
+        while let Some((timestamp, sender, nonce)) = self.order.front().copied() {
+            if now.saturating_sub(timestamp) <= ttl {
+                break;
+            }
+            let _ = self.order.pop_front();
+            let _ = self.seen.remove(&(sender, nonce));
+        }
+    }
+}
+
+/// The current time in milliseconds since the UNIX epoch.
+#[must_use]
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::gossip::{GossipMessage, SignedGossipMessage};
+    use sp_core::Pair;
+
+    fn signed_message() -> SignedGossipMessage {
+        let alice = ecdsa::Pair::from_seed(&[1u8; 32]);
+        SignedGossipMessage::sign(
+            GossipMessage {
+                topic: "/tangle/test/1.0.0".to_string(),
+                raw_payload: b"round 1 message".to_vec(),
+            },
+            &alice,
+        )
+    }
+
+    #[test]
+    fn test_replayed_message_is_ignored() {
+        let mut cache = ReplayCache::new(16, DEFAULT_MESSAGE_TTL);
+        let captured = signed_message();
+        let now = captured.timestamp;
+
+        assert!(cache
+            .check(captured.sender, captured.nonce, captured.timestamp, now)
+            .is_ok());
+        assert!(cache
+            .check(captured.sender, captured.nonce, captured.timestamp, now + 1)
+            .is_err());
+
+        // A new message from the same sender is still accepted
+        let fresh = signed_message();
+        assert!(cache
+            .check(fresh.sender, fresh.nonce, fresh.timestamp, now + 1)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_stale_message_is_ignored() {
+        let ttl = Duration::from_secs(60);
+        let mut cache = ReplayCache::new(16, ttl);
+        let captured = signed_message();
+        let ttl_millis = 60_000;
+
+        let later = captured.timestamp + ttl_millis + 1;
+        assert!(cache
+            .check(captured.sender, captured.nonce, captured.timestamp, later)
+            .is_err());
+
+        let earlier = captured.timestamp - ttl_millis - 1;
+        assert!(cache
+            .check(captured.sender, captured.nonce, captured.timestamp, earlier)
+            .is_err());
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let mut cache = ReplayCache::new(2, DEFAULT_MESSAGE_TTL);
+        let sender = signed_message().sender;
+        let now = now_millis();
+
+        for nonce in 0..2 {
+            assert!(cache.check(sender, nonce, now, now).is_ok());
+        }
+        assert!(cache.check(sender, 2, now, now).is_err());
+        assert_eq!(cache.len(), 2);
+
+        let expired = now + u64::try_from(DEFAULT_MESSAGE_TTL.as_millis()).unwrap() + 1;
+        assert!(cache.check(sender, 4, expired, expired).is_ok());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_flooding_the_cache_does_not_make_a_message_replayable() {
+        let mut cache = ReplayCache::new(4, DEFAULT_MESSAGE_TTL);
+        let captured = signed_message();
+        let now = captured.timestamp;
+        assert!(cache
+            .check(captured.sender, captured.nonce, captured.timestamp, now)
+            .is_ok());
+
+        // Another participant floods the cache with validly signed messages
+        let mallory = ecdsa::Pair::from_seed(&[2u8; 32]).public();
+        for nonce in 0..16 {
+            let _ = cache.check(mallory, nonce, now, now + 1);
+        }
+
+        // The captured message is still remembered while it is fresh
+        assert!(cache
+            .check(captured.sender, captured.nonce, captured.timestamp, now + 2)
+            .is_err());
+        assert_eq!(cache.len(), 4);
+    }
+}
@@ -1,8 +1,13 @@
 #![allow(unused_results, missing_docs)]
+use crate::network::encryption::SessionCipher;
 #[cfg(not(target_family = "wasm"))]
 use crate::network::gossip::{
-    GossipHandle, IntraNodePayload, MyBehaviour, NetworkServiceWithoutSwarm, MAX_MESSAGE_SIZE,
+    GossipHandle, IntraNodePayload, MyBehaviour, NetworkServiceWithoutSwarm, PeerProtocolVersions,
+    MAX_MESSAGE_SIZE,
 };
+use crate::network::replay::{ReplayCache, DEFAULT_MESSAGE_TTL, DEFAULT_REPLAY_CACHE_CAPACITY};
+#[cfg(not(target_family = "wasm"))]
+use crate::network::versioning::{implied_versions, VersionedProtocol};
 use futures::StreamExt;
 
 #[cfg(not(target_family = "wasm"))]
@@ -42,6 +47,17 @@ pub struct NetworkConfig {
     pub bind_ip: IpAddr,
     pub bind_port: u16,
     pub topics: Vec<String>,
+    /// Encrypts the payloads of every topic under this session key, see
+    /// [`GossipHandle::with_session_cipher`]
+    pub session_cipher: Option<SessionCipher>,
+    /// The maximum number of gossip messages remembered to detect replays
+    pub replay_cache_capacity: usize,
+    /// The age after which gossip messages are considered stale and dropped
+    pub message_ttl: Duration,
+    /// The major versions of each topic's protocol supported by this node, advertised in its
+    /// handshakes. Topics without an entry support the version in their name, see
+    /// [`implied_versions`](crate::network::versioning::implied_versions)
+    pub protocol_versions: BTreeMap<String, Vec<u32>>,
 }
 
 impl std::fmt::Debug for NetworkConfig {
@@ -52,6 +68,10 @@ impl std::fmt::Debug for NetworkConfig {
             .field("bind_ip", &self.bind_ip)
             .field("bind_port", &self.bind_port)
             .field("topics", &self.topics)
+            .field("encrypted", &self.session_cipher.is_some())
+            .field("replay_cache_capacity", &self.replay_cache_capacity)
+            .field("message_ttl", &self.message_ttl)
+            .field("protocol_versions", &self.protocol_versions)
             .finish_non_exhaustive()
     }
 }
@@ -75,9 +95,40 @@ impl NetworkConfig {
             bind_ip,
             bind_port,
             topics,
+            session_cipher: None,
+            replay_cache_capacity: DEFAULT_REPLAY_CACHE_CAPACITY,
+            message_ttl: DEFAULT_MESSAGE_TTL,
+            protocol_versions: BTreeMap::new(),
         }
     }
 
+    /// Encrypts the protocol payloads of all topics of this network with `cipher`.
+    #[must_use]
+    pub fn with_session_cipher(mut self, cipher: SessionCipher) -> Self {
+        self.session_cipher = Some(cipher);
+        self
+    }
+
+    /// Remember up to `capacity` gossip messages to detect replays, and drop messages older
+    /// than `ttl`. Once `capacity` messages are remembered, new ones are dropped until the oldest
+    /// expire.
+    #[must_use]
+    pub fn with_replay_protection(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.replay_cache_capacity = capacity;
+        self.message_ttl = ttl;
+        self
+    }
+
+    /// Advertise each of `versions` of the protocol of `topic` to peers, so that the protocol can
+    /// speak the highest version it has in common with each of them, see
+    /// [`GossipHandle::negotiated_version`](crate::network::gossip::GossipHandle::negotiated_version).
+    #[must_use]
+    pub fn with_protocol_versions<T: Into<String>>(mut self, topic: T, versions: &[u32]) -> Self {
+        self.protocol_versions
+            .insert(topic.into(), versions.to_vec());
+        self
+    }
+
     /// When constructing a network for a single service, the service name is used as the network name.
     /// Each service within a blueprint must have a unique network name.
     pub fn new_service_network<T: Into<String>>(
@@ -122,6 +173,40 @@ pub fn start_p2p_network(config: NetworkConfig) -> Result<GossipHandle, Box<dyn
     Ok(network)
 }
 
+/// Dials the `bootnodes` on startup, so that committee members find each other without waiting
+/// for mDNS or the DHT, e.g. in private networks.
+///
+/// Bootnodes given with their peer id, as `/ip4/.../tcp/.../p2p/<peer id>`, are also added to the
+/// DHT routing table. A bootnode that can't be dialed is logged and skipped.
+#[cfg(not(target_family = "wasm"))]
+fn dial_bootnodes(swarm: &mut libp2p::Swarm<MyBehaviour>, bootnodes: &[Multiaddr]) {
+    for bootnode in bootnodes {
+        let peer_id = bootnode.iter().find_map(|protocol| match protocol {
+            libp2p::multiaddr::Protocol::P2p(peer_id) => Some(peer_id),
+            _ => None,
+        });
+        let opts = match peer_id {
+            Some(peer_id) => {
+                swarm
+                    .behaviour_mut()
+                    .kadmelia
+                    .add_address(&peer_id, bootnode.clone());
+                DialOpts::peer_id(peer_id)
+                    .addresses(vec![bootnode.clone()])
+                    .build()
+            }
+            None => DialOpts::unknown_peer_id()
+                .address(bootnode.clone())
+                .build(),
+        };
+
+        match swarm.dial(opts) {
+            Ok(()) => crate::debug!("Dialing bootnode {bootnode}"),
+            Err(err) => crate::warn!("Failed to dial bootnode {bootnode}: {err}"),
+        }
+    }
+}
+
 pub type NetworkResult = Result<(BTreeMap<String, GossipHandle>, JoinHandle<()>), Box<dyn Error>>;
 
 #[allow(clippy::collapsible_else_if, clippy::too_many_lines)]
@@ -150,6 +235,10 @@ pub fn multiplexed_libp2p_network(config: NetworkConfig) -> NetworkResult {
         bind_port,
         topics,
         ecdsa_key,
+        session_cipher,
+        replay_cache_capacity,
+        message_ttl,
+        protocol_versions,
     } = config;
 
     // Ensure all topics are unique
@@ -166,6 +255,19 @@ pub fn multiplexed_libp2p_network(config: NetworkConfig) -> NetworkResult {
 
     let networks = topics;
 
+    // The versions of each topic's protocol advertised in the handshakes
+    let protocol_versions = networks
+        .iter()
+        .map(|n| {
+            let versions = protocol_versions
+                .get(n)
+                .cloned()
+                .unwrap_or_else(|| implied_versions(n));
+            (n.clone(), versions)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let peer_protocol_versions = PeerProtocolVersions::default();
+
     let mut swarm = libp2p::SwarmBuilder::with_existing_identity(identity)
         .with_tokio()
         .with_tcp(
@@ -273,6 +375,9 @@ pub fn multiplexed_libp2p_network(config: NetworkConfig) -> NetworkResult {
                 tx_to_outbound: tx_to_outbound.clone(),
                 rx_from_inbound: Arc::new(Mutex::new(inbound_rx)),
                 ecdsa_peer_id_to_libp2p_id: ecdsa_peer_id_to_libp2p_id.clone(),
+                session_cipher: session_cipher.clone(),
+                protocol: VersionedProtocol::new(&network, &protocol_versions[&network]),
+                peer_protocol_versions: peer_protocol_versions.clone(),
             },
         );
     }
@@ -299,14 +404,7 @@ pub fn multiplexed_libp2p_network(config: NetworkConfig) -> NetworkResult {
         swarm.listen_on(format!("/{ip_label}/{addr}/tcp/{bind_port}").parse()?)?;
     }
 
-    // Dial all bootnodes
-    for bootnode in &bootnodes {
-        swarm.dial(
-            DialOpts::unknown_peer_id()
-                .address(bootnode.clone())
-                .build(),
-        )?;
-    }
+    dial_bootnodes(&mut swarm, &bootnodes);
 
     let worker = async move {
         let span = tracing::debug_span!("network_worker");
@@ -315,6 +413,9 @@ pub fn multiplexed_libp2p_network(config: NetworkConfig) -> NetworkResult {
             inbound_mapping: &inbound_mapping,
             ecdsa_peer_id_to_libp2p_id,
             ecdsa_key: &ecdsa_key,
+            replay_cache: Mutex::new(ReplayCache::new(replay_cache_capacity, message_ttl)),
+            protocol_versions: &protocol_versions,
+            peer_protocol_versions,
             span: tracing::debug_span!(parent: &span, "network_service"),
         };
         loop {
@@ -333,3 +434,44 @@ pub fn multiplexed_libp2p_network(config: NetworkConfig) -> NetworkResult {
     let spawn_handle = spawn(worker);
     Ok((handles_ret, spawn_handle))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp_core::Pair;
+
+    #[tokio::test]
+    async fn test_bootnodes_are_dialed_on_startup() {
+        let bootnode = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bootnode_addr: Multiaddr = format!(
+            "/ip4/127.0.0.1/tcp/{}",
+            bootnode.local_addr().unwrap().port()
+        )
+        .parse()
+        .unwrap();
+
+        let unreachable = libp2p::identity::Keypair::generate_ed25519()
+            .public()
+            .to_peer_id();
+        let config = NetworkConfig::new_service_network(
+            libp2p::identity::Keypair::generate_ed25519(),
+            ecdsa::Pair::from_seed(&[1u8; 32]),
+            vec![
+                // Unreachable bootnodes don't prevent dialing the others
+                format!("/ip4/127.0.0.1/udp/1/quic-v1/p2p/{unreachable}")
+                    .parse()
+                    .unwrap(),
+                bootnode_addr,
+            ],
+            IpAddr::from_str("127.0.0.1").unwrap(),
+            0,
+            "/tangle/bootnodes/1.0.0",
+        );
+        let _network = start_p2p_network(config).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), bootnode.accept())
+            .await
+            .expect("The bootnode was not dialed")
+            .unwrap();
+    }
+}
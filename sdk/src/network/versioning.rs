@@ -0,0 +1,119 @@
+//! Protocol versioning for the topics of a network.
+//!
+//! The topic of a protocol, e.g. `/tangle/keygen/1.0.0`, is shared by all of its versions, since
+//! gossip only reaches the nodes subscribed to the very same topic. Instead, each node advertises
+//! the major versions it supports for each topic in its handshake, and the protocol asks its
+//! [`GossipHandle`](crate::network::gossip::GossipHandle) which version to speak with the sender
+//! or recipient of a message, the highest both of them support. This allows the keygen and
+//! signing protocols to be upgraded one node at a time.
+
+/// A protocol and the major versions of it this node supports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionedProtocol {
+    base: String,
+    /// Sorted from highest to lowest, without duplicates
+    versions: Vec<u32>,
+}
+
+impl VersionedProtocol {
+    /// Creates a protocol from its `name` and supported major `versions`.
+    ///
+    /// A trailing version segment in `name` (e.g. the `/1.0.0` in `/tangle/keygen/1.0.0`) is
+    /// stripped, so existing versioned network names can be used as is.
+    #[must_use]
+    pub fn new(name: &str, versions: &[u32]) -> Self {
+        let base = match name.rsplit_once('/') {
+            Some((base, version)) if !base.is_empty() && parse_version(version).is_some() => base,
+            _ => name,
+        };
+
+        let mut versions = versions.to_vec();
+        versions.sort_unstable_by(|a, b| b.cmp(a));
+        versions.dedup();
+
+        Self {
+            base: base.to_string(),
+            versions,
+        }
+    }
+
+    /// The name of the protocol, without a version.
+    #[must_use]
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// The supported versions, highest first.
+    #[must_use]
+    pub fn versions(&self) -> &[u32] {
+        &self.versions
+    }
+
+    /// The highest version supported by both this node and a peer supporting `remote`.
+    #[must_use]
+    pub fn negotiate(&self, remote: &[u32]) -> Option<u32> {
+        self.versions
+            .iter()
+            .copied()
+            .find(|version| remote.contains(version))
+    }
+}
+
+/// The versions of the protocol of topic `name` supported by a node that does not advertise any:
+/// the version in the name itself, e.g. 1 for `/tangle/keygen/1.0.0`, or 1 for an unversioned name.
+#[must_use]
+pub fn implied_versions(name: &str) -> Vec<u32> {
+    let version = name
+        .rsplit_once('/')
+        .and_then(|(_, version)| parse_version(version))
+        .unwrap_or(1);
+    vec![version]
+}
+
+/// Parses a `MAJOR.MINOR.PATCH` version segment, returning the major version.
+fn parse_version(version: &str) -> Option<u32> {
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    for _ in 0..2 {
+        let _: u32 = parts.next()?.parse().ok()?;
+    }
+    parts.next().is_none().then_some(major)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highest_common_version_is_negotiated() {
+        let old_node = VersionedProtocol::new("/tangle/keygen/1.0.0", &[1]);
+        let new_node = VersionedProtocol::new("/tangle/keygen/1.0.0", &[1, 2]);
+
+        assert_eq!(old_node.base(), "/tangle/keygen");
+        assert_eq!(new_node.versions(), &[2, 1]);
+
+        // Both sides fall back to v1 while the old node is not upgraded yet
+        assert_eq!(new_node.negotiate(old_node.versions()), Some(1));
+        assert_eq!(old_node.negotiate(new_node.versions()), Some(1));
+
+        // Once both are upgraded, they move to v2
+        let upgraded_node = VersionedProtocol::new("/tangle/keygen/1.0.0", &[2, 1]);
+        assert_eq!(new_node.negotiate(upgraded_node.versions()), Some(2));
+    }
+
+    #[test]
+    fn test_no_common_version() {
+        let v1 = VersionedProtocol::new("/tangle/keygen", &[1]);
+        let v2 = VersionedProtocol::new("/tangle/keygen", &[2]);
+
+        assert_eq!(v1.negotiate(v2.versions()), None);
+        assert_eq!(v1.negotiate(&[]), None);
+    }
+
+    #[test]
+    fn test_nodes_advertising_no_versions_speak_the_version_of_the_topic() {
+        assert_eq!(implied_versions("/tangle/keygen/1.0.0"), vec![1]);
+        assert_eq!(implied_versions("/tangle/keygen/2.0.0"), vec![2]);
+        assert_eq!(implied_versions("/tangle/keygen"), vec![1]);
+    }
+}
@@ -1,4 +1,7 @@
-use prometheus::{Gauge, Histogram, HistogramOpts, IntCounter, Registry};
+use prometheus::{
+    Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter, IntGauge, IntGaugeVec, Opts,
+    Registry,
+};
 use std::sync::LazyLock;
 
 /// The global Prometheus metrics registry.
@@ -33,3 +36,48 @@ pub static JOB_RUN_TIME: LazyLock<Histogram> = LazyLock::new(|| {
     Histogram::with_opts(HistogramOpts::new("job_runtime", "Job Runtime (s)"))
         .expect("metric can be created")
 });
+/// The time between observing a `JobCalled` event and successfully submitting its result,
+/// labeled by job id. Recorded by the handlers of jobs with `#[job(metrics)]`.
+pub static JOB_SUBMISSION_LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "job_submission_latency_seconds",
+            "Time between a job call and the submission of its result (s)",
+        )
+        .buckets(vec![
+            0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+        ]),
+        &["job_id"],
+    )
+    .expect("metric can be created")
+});
+/// The committee members connected on a protocol's gossip topic, including the local node, as of
+/// the last `GossipHandle::committee_readiness` check.
+pub static COMMITTEE_PEERS_CONNECTED: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "committee_peers_connected",
+        "Committee members connected on the protocol topic",
+    )
+    .expect("metric can be created")
+});
+/// The committee members a threshold protocol needs connected before it can make progress.
+pub static COMMITTEE_PEERS_REQUIRED: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new(
+        "committee_peers_required",
+        "Committee members required by the protocol",
+    )
+    .expect("metric can be created")
+});
+/// The gossip messages waiting in the queues between the network and the protocols, labeled by
+/// topic and direction (`inbound` or `outbound`). A depth that keeps rising means the protocol,
+/// or the network worker, can't keep up.
+pub static GOSSIP_QUEUE_DEPTH: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "gossip_queue_depth",
+            "Gossip messages waiting to be handled",
+        ),
+        &["topic", "direction"],
+    )
+    .expect("metric can be created")
+});
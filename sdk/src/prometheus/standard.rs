@@ -1,6 +1,9 @@
 use crate::error::Error;
 use crate::metrics;
-use crate::prometheus::shared::{BYTES_RECEIVED, BYTES_SENT, REGISTRY};
+use crate::prometheus::shared::{
+    BYTES_RECEIVED, BYTES_SENT, COMMITTEE_PEERS_CONNECTED, COMMITTEE_PEERS_REQUIRED,
+    GOSSIP_QUEUE_DEPTH, JOB_SUBMISSION_LATENCY, REGISTRY,
+};
 use alloc::string::ToString;
 use core::net::SocketAddr;
 use core::str::FromStr;
@@ -50,6 +53,30 @@ pub async fn setup(config: PrometheusConfig) -> Result<(), Error> {
         err: err.to_string(),
     })?;
 
+    let _ = metrics::register(JOB_SUBMISSION_LATENCY.clone(), &REGISTRY).map_err(|err| {
+        Error::Prometheus {
+            err: err.to_string(),
+        }
+    })?;
+
+    let _ = metrics::register(COMMITTEE_PEERS_CONNECTED.clone(), &REGISTRY).map_err(|err| {
+        Error::Prometheus {
+            err: err.to_string(),
+        }
+    })?;
+
+    let _ = metrics::register(COMMITTEE_PEERS_REQUIRED.clone(), &REGISTRY).map_err(|err| {
+        Error::Prometheus {
+            err: err.to_string(),
+        }
+    })?;
+
+    let _ = metrics::register(GOSSIP_QUEUE_DEPTH.clone(), &REGISTRY).map_err(|err| {
+        Error::Prometheus {
+            err: err.to_string(),
+        }
+    })?;
+
     metrics::init_prometheus(bind_addr, REGISTRY.clone())
         .await
         .map_err(|err| Error::Prometheus {
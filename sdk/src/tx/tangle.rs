@@ -1,7 +1,87 @@
-use crate::debug;
+use crate::{debug, info, warn};
+use backon::{BackoffBuilder, DefaultSleeper, ExponentialBuilder, Sleeper};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use futures::StreamExt;
+use subxt::backend::legacy::LegacyRpcMethods;
+use subxt::backend::rpc::RpcClient;
+use subxt::backend::StreamOfResults;
+use subxt::config::DefaultExtrinsicParamsBuilder;
+use subxt::utils::{AccountId32, Era, H256};
+use subxt::PolkadotConfig;
+
+/// The maximum number of times to reconnect while waiting for a submitted transaction.
+const MAX_RECONNECT_ATTEMPTS: usize = 5;
+/// The maximum delay between two reconnect attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// How many finalized blocks to search for a submitted transaction after reconnecting, when it is
+/// not known until which block it is valid, before giving up on it.
+const MAX_FINALIZED_BLOCKS_TO_POLL: usize = 50;
+/// How many blocks after the block it references a transaction sent with
+/// [`SendOptions::at_block`] stays valid for.
+pub const DEFAULT_BLOCK_REFERENCE_PERIOD: u64 = 64;
+
+/// How a transaction is submitted to the Tangle network, see [`send_with_options`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SendOptions {
+    /// Log the hex encoding of the signed extrinsic before it is submitted, to reproduce failed
+    /// submissions. Disabled by default, since it is verbose.
+    ///
+    /// A signed extrinsic only holds the public key and signature of its signer, never its
+    /// private key.
+    pub log_extrinsic_hex: bool,
+    /// Check that the runtime was not upgraded since the client connected before submitting, see
+    /// [`ensure_runtime_unchanged`].
+    pub check_runtime_unchanged: bool,
+    /// The URL of the node to reconnect to if the connection drops while waiting for the
+    /// transaction to be finalized. The transaction is then looked for in the blocks finalized
+    /// since it was submitted, rather than resubmitted. Without it, the connection error is
+    /// returned, even though the transaction may still be included.
+    pub reconnect_url: Option<String>,
+    /// The last block the transaction can be included in, if it is mortal. After a reconnect,
+    /// the transaction is looked for up to this block, or for a bounded number of blocks
+    /// otherwise. Derived from the [`SendOptions::mortality`] if not set.
+    pub valid_until: Option<u64>,
+    /// The lifetime of the transaction, immortal by default.
+    pub mortality: Mortality,
+    /// The block the content of the transaction was computed against, e.g. for a job result.
+    ///
+    /// The transaction is anchored at the referenced block: it signs the block hash, and is only
+    /// valid for the period of the [`SendOptions::mortality`] after it, or
+    /// [`DEFAULT_BLOCK_REFERENCE_PERIOD`] blocks if it is immortal. The runtime therefore rejects
+    /// results computed against a block that is too old, or that is no longer part of the chain.
+    pub at_block: Option<BlockReference>,
+    /// Check that the signer exists and has at least this much free balance before submitting,
+    /// see [`ensure_free_balance`]. This turns a transaction that would fail to pay its fees into
+    /// an actionable error, e.g. to fund the operator account.
+    pub min_free_balance: Option<u128>,
+}
+
+impl SendOptions {
+    /// The hex encoding of the signed `extrinsic`, if it is to be logged
+    fn extrinsic_hex(&self, extrinsic: &[u8]) -> Option<String> {
+        self.log_extrinsic_hex
+            .then(|| format!("0x{}", hex::encode(extrinsic)))
+    }
+
+    /// How many blocks after the block it is anchored at the transaction stays valid for, or
+    /// `None` if it never expires
+    fn period(&self) -> Option<u64> {
+        match (self.mortality, self.at_block) {
+            (Mortality::Mortal { period }, _) => Some(period),
+            (Mortality::Immortal, Some(_)) => Some(DEFAULT_BLOCK_REFERENCE_PERIOD),
+            (Mortality::Immortal, None) => None,
+        }
+    }
+}
 
 /// Send a transaction to the Tangle network.
 ///
+/// If the connection drops while waiting for the transaction to be finalized, the transaction
+/// is *not* resubmitted, and the connection error is returned. Use [`send_with_options`] with a
+/// [`SendOptions::reconnect_url`] to reconnect (with a jittered, capped exponential backoff) and
+/// look for the already-submitted transaction in the blocks finalized since, by hash.
+///
 /// # Errors
 ///
 /// Returns a [`subxt::Error`] if the transaction fails.
@@ -16,22 +96,866 @@ where
     S: subxt::tx::Signer<T>,
     X: subxt::tx::Payload,
     <T::ExtrinsicParams as subxt::config::ExtrinsicParams<T>>::Params: Default,
+{
+    send_with_params(
+        client,
+        signer,
+        xt,
+        Default::default(),
+        &SendOptions::default(),
+    )
+    .await
+}
+
+/// Send a transaction to the Tangle network, with the given [`SendOptions`].
+///
+/// Other than the options, this behaves exactly as [`send`]. Tangle uses the [`PolkadotConfig`].
+///
+/// # Errors
+///
+/// Returns [`Error::RuntimeUpgraded`](crate::Error::RuntimeUpgraded) if the runtime is checked
+/// and was upgraded since `client` connected,
+/// [`Error::AccountNotFound`](crate::Error::AccountNotFound) or
+/// [`Error::InsufficientBalance`](crate::Error::InsufficientBalance) if the balance is checked and
+/// the signer can't pay, or a [`crate::Error::Subxt`] if the transaction fails, e.g. because the
+/// referenced block is stale.
+#[tracing::instrument(skip_all)]
+pub async fn send_with_options<S, X>(
+    client: &subxt::OnlineClient<PolkadotConfig>,
+    signer: &S,
+    xt: &X,
+    options: &SendOptions,
+) -> Result<subxt::blocks::ExtrinsicEvents<PolkadotConfig>, crate::Error>
+where
+    S: subxt::tx::Signer<PolkadotConfig>,
+    X: subxt::tx::Payload,
+{
+    let (params, options) = prepare(client, signer, options).await?;
+    Ok(send_with_params(client, signer, xt, params, &options).await?)
+}
+
+/// Sign a transaction as [`send_with_options`] would, and have the node validate it without
+/// submitting it, e.g. to check that an operator is able to submit transactions at no cost.
+///
+/// # Errors
+///
+/// Returns [`Error::RuntimeUpgraded`](crate::Error::RuntimeUpgraded) if the runtime is checked
+/// and was upgraded since `client` connected, an error if the balance is checked and the signer
+/// can't pay, or a [`crate::Error::Subxt`] if the transaction could not be signed or the node could not be asked to validate it. A transaction the node
+/// considers invalid is not an error, but a [`ValidationResult`](subxt::tx::ValidationResult).
+#[tracing::instrument(skip_all)]
+pub async fn validate_with_options<S, X>(
+    client: &subxt::OnlineClient<PolkadotConfig>,
+    signer: &S,
+    xt: &X,
+    options: &SendOptions,
+) -> Result<subxt::tx::ValidationResult, crate::Error>
+where
+    S: subxt::tx::Signer<PolkadotConfig>,
+    X: subxt::tx::Payload,
+{
+    let (params, options) = prepare(client, signer, options).await?;
+    let extrinsic = sign(client, signer, xt, params, &options).await?;
+    Ok(extrinsic.validate().await?)
+}
+
+/// Runs the pre-flight checks of `options`, and builds the parameters of a transaction sent with
+/// them, along with the options to send it with, whose [`SendOptions::valid_until`] is set if it
+/// is mortal.
+async fn prepare<S>(
+    client: &subxt::OnlineClient<PolkadotConfig>,
+    signer: &S,
+    options: &SendOptions,
+) -> Result<(PolkadotParams, SendOptions), crate::Error>
+where
+    S: subxt::tx::Signer<PolkadotConfig>,
+{
+    if options.check_runtime_unchanged {
+        ensure_runtime_unchanged(client).await?;
+    }
+    if let Some(min_free_balance) = options.min_free_balance {
+        let _ = ensure_free_balance(client, &signer.account_id(), min_free_balance).await?;
+    }
+
+    let mut options = options.clone();
+    let params = match options.period() {
+        Some(period) => {
+            let at_block = match options.at_block {
+                Some(at_block) => at_block,
+                None => {
+                    let block = client.blocks().at_latest().await?;
+                    BlockReference::new(block.number().into(), block.hash())
+                }
+            };
+            let _ = options
+                .valid_until
+                .get_or_insert(at_block.valid_until(period));
+            at_block.params(period)
+        }
+        None => DefaultExtrinsicParamsBuilder::<PolkadotConfig>::new().build(),
+    };
+    Ok((params, options))
+}
+
+/// The lifetime of a signed transaction, see [`SendOptions::mortality`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mortality {
+    /// The transaction is valid for `period` blocks after the latest finalized block, or after
+    /// the [`SendOptions::at_block`] it is anchored at.
+    ///
+    /// The period is rounded up to a power of two, between 4 and 65536.
+    Mortal { period: u64 },
+    /// The transaction never expires, e.g. for long-lived pre-signed transactions.
+    ///
+    /// Only the account nonce prevents such a transaction from being replayed.
+    #[default]
+    Immortal,
+}
+
+impl Mortality {
+    /// The era encoded into a transaction signed at `current_block` with this mortality
+    #[must_use]
+    pub fn era(self, current_block: u64) -> Era {
+        match self {
+            Self::Mortal { period } => Era::mortal(period, current_block),
+            Self::Immortal => Era::Immortal,
+        }
+    }
+
+    /// The last block a transaction signed at `current_block` with this mortality can be
+    /// included in, at the latest, or `None` if it never expires
+    #[must_use]
+    pub fn valid_until(self, current_block: u64) -> Option<u64> {
+        match self {
+            Self::Mortal { period } => Some(current_block + rounded_period(period)),
+            Self::Immortal => None,
+        }
+    }
+}
+
+/// The validity period of a mortal [`Era`], which is rounded up to a power of two
+fn rounded_period(period: u64) -> u64 {
+    period
+        .checked_next_power_of_two()
+        .unwrap_or(1 << 16)
+        .clamp(4, 1 << 16)
+}
+
+/// The parameters of a transaction sent to the Tangle network
+pub type PolkadotParams =
+    <<PolkadotConfig as subxt::Config>::ExtrinsicParams as subxt::config::ExtrinsicParams<
+        PolkadotConfig,
+    >>::Params;
+
+/// A block whose state a transaction, e.g. a job result, was computed against, see
+/// [`SendOptions::at_block`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockReference {
+    /// The number of the block
+    pub number: u64,
+    /// The hash of the block, which the transaction signs
+    pub hash: H256,
+}
+
+impl BlockReference {
+    /// A reference to the block `number`, whose hash is `hash`
+    #[must_use]
+    pub fn new(number: u64, hash: H256) -> Self {
+        Self { number, hash }
+    }
+
+    /// The era of a transaction anchored at this block, valid for `period` blocks after it
+    #[must_use]
+    pub fn era(self, period: u64) -> Era {
+        Era::mortal(period, self.number)
+    }
+
+    /// The last block a transaction anchored at this block, valid for `period` blocks after it,
+    /// can be included in, at the latest
+    #[must_use]
+    pub fn valid_until(self, period: u64) -> u64 {
+        self.number + rounded_period(period)
+    }
+
+    /// The parameters of a transaction anchored at this block, valid for `period` blocks after it
+    #[must_use]
+    pub fn params(self, period: u64) -> PolkadotParams {
+        DefaultExtrinsicParamsBuilder::<PolkadotConfig>::new()
+            .mortal_unchecked(self.number, self.hash, period)
+            .build()
+    }
+}
+
+/// Checks that `account` exists and has at least `min_free_balance` free, returning its free
+/// balance.
+///
+/// Running this before submitting turns a transaction that would fail to pay its fees into an
+/// actionable error, e.g. to fund the operator account, see [`SendOptions::min_free_balance`].
+///
+/// # Errors
+///
+/// Returns [`Error::AccountNotFound`](crate::Error::AccountNotFound) or
+/// [`Error::InsufficientBalance`](crate::Error::InsufficientBalance) if the account can't pay, or
+/// a [`crate::Error::Subxt`] if its balance could not be queried.
+pub async fn ensure_free_balance(
+    client: &subxt::OnlineClient<PolkadotConfig>,
+    account: &AccountId32,
+    min_free_balance: u128,
+) -> Result<u128, crate::Error> {
+    let query = tangle_subxt::tangle_testnet_runtime::api::storage()
+        .system()
+        .account(account.clone());
+    let info = client.storage().at_latest().await?.fetch(&query).await?;
+    check_free_balance(account, info.map(|info| info.data.free), min_free_balance)
+}
+
+/// Checks that the runtime of the chain is still the one `client` was connected to.
+///
+/// The client encodes transactions and decodes events with the metadata of the runtime it was
+/// connected to, so after a runtime upgrade its submissions may fail or be misinterpreted until
+/// it reconnects, and the node is running types generated for the new runtime.
+///
+/// # Errors
+///
+/// Returns [`Error::RuntimeUpgraded`](crate::Error::RuntimeUpgraded) if the spec version of the
+/// chain's runtime changed, or a [`crate::Error::Subxt`] if it could not be queried.
+pub async fn ensure_runtime_unchanged<T: subxt::Config>(
+    client: &subxt::OnlineClient<T>,
+) -> Result<(), crate::Error> {
+    let current = client.backend().current_runtime_version().await?;
+    check_runtime_version(&client.runtime_version(), &current)
+}
+
+/// Checks that the `current` runtime of the chain is still the one the client `connected` to.
+fn check_runtime_version(
+    connected: &subxt::client::RuntimeVersion,
+    current: &subxt::backend::RuntimeVersion,
+) -> Result<(), crate::Error> {
+    check_spec_version(connected.spec_version, current.spec_version)
+}
+
+/// Checks that the `current` spec version of the chain's runtime is still the `expected` one.
+fn check_spec_version(expected: u32, current: u32) -> Result<(), crate::Error> {
+    if current != expected {
+        warn!("Runtime upgraded from spec version {expected} to {current}");
+        return Err(crate::Error::RuntimeUpgraded {
+            from: expected,
+            to: current,
+        });
+    }
+    Ok(())
+}
+
+/// Checks the `free` balance of `account`, which is `None` if the account does not exist.
+fn check_free_balance(
+    account: &AccountId32,
+    free: Option<u128>,
+    min_free_balance: u128,
+) -> Result<u128, crate::Error> {
+    let Some(free) = free else {
+        return Err(crate::Error::AccountNotFound {
+            account: account.to_string(),
+        });
+    };
+    if free < min_free_balance {
+        return Err(crate::Error::InsufficientBalance {
+            account: account.to_string(),
+            free,
+            required: min_free_balance,
+        });
+    }
+    Ok(free)
+}
+
+/// A pool of signers that transactions are spread across, round-robin.
+///
+/// Every signer is a separate account with its own nonce and balance, so spreading transactions
+/// avoids nonce contention and spreads fees. However, transactions signed by different signers
+/// are not ordered relative to each other, and may be included in a different order than they
+/// were sent in. Transactions that depend on each other must be sent with the same signer.
+///
+/// To send a transaction, pass the [`SignerPool::next_signer`] to [`send`] or
+/// [`send_with_options`].
+#[derive(Debug)]
+pub struct SignerPool<S> {
+    signers: Vec<S>,
+    next: AtomicUsize,
+}
+
+impl<S> SignerPool<S> {
+    /// Creates a pool of `signers`, returning `None` if there are none.
+    #[must_use]
+    pub fn new(signers: Vec<S>) -> Option<Self> {
+        if signers.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            signers,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// The signers in the pool.
+    #[must_use]
+    pub fn signers(&self) -> &[S] {
+        &self.signers
+    }
+
+    /// The signer to use for the next transaction.
+    pub fn next_signer(&self) -> &S {
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.signers[next % self.signers.len()]
+    }
+}
+
+async fn sign<T, S, X>(
+    client: &subxt::OnlineClient<T>,
+    signer: &S,
+    xt: &X,
+    params: <T::ExtrinsicParams as subxt::config::ExtrinsicParams<T>>::Params,
+    options: &SendOptions,
+) -> Result<subxt::tx::SubmittableExtrinsic<T, subxt::OnlineClient<T>>, subxt::Error>
+where
+    T: subxt::Config,
+    S: subxt::tx::Signer<T>,
+    X: subxt::tx::Payload,
 {
     if let Some(details) = xt.validation_details() {
         debug!("Calling {}.{}", details.pallet_name, details.call_name);
     }
 
+    let extrinsic = client.tx().create_signed(xt, signer, params).await?;
+    if let Some(encoded) = options.extrinsic_hex(extrinsic.encoded()) {
+        info!("Signed extrinsic {:?}: {encoded}", extrinsic.hash());
+    }
+    Ok(extrinsic)
+}
+
+async fn send_with_params<T, S, X>(
+    client: &subxt::OnlineClient<T>,
+    signer: &S,
+    xt: &X,
+    params: <T::ExtrinsicParams as subxt::config::ExtrinsicParams<T>>::Params,
+    options: &SendOptions,
+) -> Result<subxt::blocks::ExtrinsicEvents<T>, subxt::Error>
+where
+    T: subxt::Config,
+    S: subxt::tx::Signer<T>,
+    X: subxt::tx::Payload,
+{
+    let extrinsic = sign(client, signer, xt, params, options).await?;
+    let extrinsic_hash = extrinsic.hash();
+
+    // After a reconnect, the transaction is looked for in the blocks finalized after this one
+    let last_finalized: Option<u64> = match options.reconnect_url {
+        Some(_) => Some(client.blocks().at_latest().await?.number().into()),
+        None => None,
+    };
+
     debug!("Waiting for the transaction to be included in a finalized block");
-    let progress = client
-        .tx()
-        .sign_and_submit_then_watch_default(xt, signer)
-        .await?;
+    let progress = extrinsic.submit_and_watch().await?;
 
     debug!("Waiting for finalized success ...");
-    let result = progress.wait_for_finalized_success().await?;
+    let result = match progress.wait_for_finalized_success().await {
+        Ok(result) => result,
+        Err(err) if is_connection_error(&err) => {
+            let (Some(url), Some(last_finalized)) = (&options.reconnect_url, last_finalized) else {
+                warn!("Connection lost while waiting for transaction {extrinsic_hash:?}, which may still be included: {err}");
+                return Err(err);
+            };
+            warn!("Connection lost while waiting for transaction {extrinsic_hash:?}, reconnecting to {url}: {err}");
+            let valid_until = options.valid_until;
+            let last_block =
+                valid_until.unwrap_or(last_finalized + MAX_FINALIZED_BLOCKS_TO_POLL as u64);
+            let delays = ExponentialBuilder::default()
+                .with_jitter()
+                .with_max_delay(MAX_RECONNECT_DELAY)
+                .with_max_times(MAX_RECONNECT_ATTEMPTS)
+                .build();
+            let mut blocks = NodeBlocks::<T>::new(url, extrinsic_hash);
+            match find_after_reconnect(&mut blocks, last_finalized, last_block, delays).await? {
+                Some(events) => events,
+                None if valid_until.is_some() => {
+                    return Err(subxt::Error::Other(format!(
+                        "Transaction {extrinsic_hash:?} expired without being included in a finalized block"
+                    )));
+                }
+                None => {
+                    return Err(subxt::Error::Other(format!(
+                        "Transaction {extrinsic_hash:?} was not found in the {MAX_FINALIZED_BLOCKS_TO_POLL} blocks finalized after it was submitted, and may still be included"
+                    )));
+                }
+            }
+        }
+        Err(err) => return Err(err),
+    };
     debug!(
         "Transaction with hash: {:?} has been finalized",
         result.extrinsic_hash()
     );
     Ok(result)
 }
+
+/// The finalized blocks of a chain, in which a submitted transaction is looked for, see
+/// [`find_after_reconnect`]
+trait FinalizedBlocks {
+    /// The outcome of the transaction, once found
+    type Found;
+
+    /// (Re)connects to the node
+    async fn connect(&mut self) -> Result<(), subxt::Error>;
+
+    /// The number of the latest finalized block
+    async fn latest_finalized(&mut self) -> Result<u64, subxt::Error>;
+
+    /// Looks for the transaction in the finalized block `number`
+    async fn find_in_block(&mut self, number: u64) -> Result<Option<Self::Found>, subxt::Error>;
+
+    /// Waits until another block is finalized
+    async fn wait_for_next(&mut self) -> Result<(), subxt::Error>;
+}
+
+/// Reconnects and looks for the transaction in the finalized blocks after `last_finalized`, up to
+/// `last_block`, returning `None` if it is in none of them.
+///
+/// Whenever the connection drops again, this reconnects after the next of the `delays`, and
+/// resumes the search where it left off, so that a transaction finalized while disconnected is
+/// still found. The connection error is returned once the `delays` are used up.
+async fn find_after_reconnect<B: FinalizedBlocks>(
+    blocks: &mut B,
+    last_finalized: u64,
+    last_block: u64,
+    mut delays: impl Iterator<Item = Duration>,
+) -> Result<Option<B::Found>, subxt::Error> {
+    let mut searched = last_finalized;
+    loop {
+        let result = match blocks.connect().await {
+            Ok(()) => find_finalized_extrinsic(blocks, &mut searched, last_block).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Err(err) if is_connection_error(&err) => {
+                let Some(delay) = delays.next() else {
+                    return Err(err);
+                };
+                warn!("Reconnect failed ({err}), retrying in {delay:?}");
+                DefaultSleeper::default().sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Looks for the transaction in the finalized blocks after `searched`, up to `last_block`,
+/// following newly finalized blocks until then. `searched` is advanced past every block the
+/// transaction is not in.
+async fn find_finalized_extrinsic<B: FinalizedBlocks>(
+    blocks: &mut B,
+    searched: &mut u64,
+    last_block: u64,
+) -> Result<Option<B::Found>, subxt::Error> {
+    loop {
+        let latest = blocks.latest_finalized().await?;
+        while *searched < latest.min(last_block) {
+            if let Some(found) = blocks.find_in_block(*searched + 1).await? {
+                return Ok(Some(found));
+            }
+            *searched += 1;
+        }
+        if *searched >= last_block {
+            return Ok(None);
+        }
+        blocks.wait_for_next().await?;
+    }
+}
+
+/// The finalized blocks of the node at `url`, over a connection of their own, in which the
+/// extrinsic with the given hash is looked for
+struct NodeBlocks<'a, T: subxt::Config> {
+    url: &'a str,
+    extrinsic_hash: T::Hash,
+    connection: Option<NodeConnection<T>>,
+}
+
+/// A connection to the node, along with its subscription to the finalized blocks
+struct NodeConnection<T: subxt::Config> {
+    client: subxt::OnlineClient<T>,
+    rpc: LegacyRpcMethods<T>,
+    finalized: StreamOfResults<subxt::blocks::Block<T, subxt::OnlineClient<T>>>,
+}
+
+impl<'a, T: subxt::Config> NodeBlocks<'a, T> {
+    fn new(url: &'a str, extrinsic_hash: T::Hash) -> Self {
+        Self {
+            url,
+            extrinsic_hash,
+            connection: None,
+        }
+    }
+
+    fn connection(&mut self) -> Result<&mut NodeConnection<T>, subxt::Error> {
+        self.connection.as_mut().ok_or(subxt::Error::Rpc(
+            subxt::error::RpcError::SubscriptionDropped,
+        ))
+    }
+}
+
+impl<T: subxt::Config> FinalizedBlocks for NodeBlocks<'_, T> {
+    type Found = subxt::blocks::ExtrinsicEvents<T>;
+
+    async fn connect(&mut self) -> Result<(), subxt::Error> {
+        let rpc = RpcClient::from_url(self.url).await?;
+        let client = subxt::OnlineClient::<T>::from_rpc_client(rpc.clone()).await?;
+        // Subscribed once per connection, rather than for every block waited for
+        let finalized = client.blocks().subscribe_finalized().await?;
+        self.connection = Some(NodeConnection {
+            client,
+            rpc: LegacyRpcMethods::new(rpc),
+            finalized,
+        });
+        Ok(())
+    }
+
+    async fn latest_finalized(&mut self) -> Result<u64, subxt::Error> {
+        let connection = self.connection()?;
+        Ok(connection
+            .client
+            .blocks()
+            .at_latest()
+            .await?
+            .number()
+            .into())
+    }
+
+    async fn find_in_block(&mut self, number: u64) -> Result<Option<Self::Found>, subxt::Error> {
+        let extrinsic_hash = self.extrinsic_hash;
+        let connection = self.connection()?;
+        let Some(hash) = connection
+            .rpc
+            .chain_get_block_hash(Some(number.into()))
+            .await?
+        else {
+            return Ok(None);
+        };
+        let block = connection.client.blocks().at(hash).await?;
+        for extrinsic in block.extrinsics().await?.iter() {
+            let extrinsic = extrinsic?;
+            if extrinsic.hash() != extrinsic_hash {
+                continue;
+            }
+
+            let events = extrinsic.events().await?;
+            for event in events.iter() {
+                let event = event?;
+                if event.pallet_name() == "System" && event.variant_name() == "ExtrinsicFailed" {
+                    return Err(subxt::Error::Other(format!(
+                        "Transaction {extrinsic_hash:?} failed in block {hash:?}"
+                    )));
+                }
+            }
+            return Ok(Some(events));
+        }
+        Ok(None)
+    }
+
+    async fn wait_for_next(&mut self) -> Result<(), subxt::Error> {
+        let connection = self.connection()?;
+        match connection.finalized.next().await {
+            Some(block) => block.map(|_| ()),
+            None => Err(subxt::Error::Rpc(
+                subxt::error::RpcError::SubscriptionDropped,
+            )),
+        }
+    }
+}
+
+/// Whether `err` was caused by the connection to the node rather than by the transaction itself.
+fn is_connection_error(err: &subxt::Error) -> bool {
+    matches!(
+        err,
+        subxt::Error::Io(_)
+            | subxt::Error::Rpc(
+                subxt::error::RpcError::ClientError(_)
+                    | subxt::error::RpcError::SubscriptionDropped
+            )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_errors_are_retried() {
+        let dropped = subxt::Error::Rpc(subxt::error::RpcError::SubscriptionDropped);
+        assert!(is_connection_error(&dropped));
+
+        let io = subxt::Error::Io(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        assert!(is_connection_error(&io));
+
+        let other = subxt::Error::Other("Transaction failed".to_string());
+        assert!(!is_connection_error(&other));
+    }
+
+    /// A chain finalizing the blocks in `finalized`, each holding the ids of its transactions, up
+    /// to `latest`, whose connection drops at the given steps
+    struct MockBlocks {
+        finalized: Vec<Vec<u32>>,
+        latest: u64,
+        transaction: u32,
+        drops: Vec<usize>,
+        steps: usize,
+        connected: bool,
+        searched: Vec<u64>,
+    }
+
+    impl MockBlocks {
+        fn step(&mut self) -> Result<(), subxt::Error> {
+            self.steps += 1;
+            if !self.connected || self.drops.contains(&self.steps) {
+                self.connected = false;
+                return Err(subxt::Error::Rpc(
+                    subxt::error::RpcError::SubscriptionDropped,
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    impl FinalizedBlocks for MockBlocks {
+        type Found = u64;
+
+        async fn connect(&mut self) -> Result<(), subxt::Error> {
+            self.connected = true;
+            self.step()
+        }
+
+        async fn latest_finalized(&mut self) -> Result<u64, subxt::Error> {
+            self.step()?;
+            Ok(self.latest)
+        }
+
+        async fn find_in_block(&mut self, number: u64) -> Result<Option<u64>, subxt::Error> {
+            self.step()?;
+            self.searched.push(number);
+            let block = &self.finalized[number as usize];
+            Ok(block.contains(&self.transaction).then_some(number))
+        }
+
+        async fn wait_for_next(&mut self) -> Result<(), subxt::Error> {
+            self.step()?;
+            self.latest += 1;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transaction_finalized_while_disconnected_is_found() {
+        // Submitted after block 10, and finalized in block 12 while the connection was down
+        let mut finalized = vec![vec![]; 20];
+        finalized[12] = vec![7];
+        let mut blocks = MockBlocks {
+            finalized: finalized.clone(),
+            latest: 13,
+            transaction: 7,
+            // The first reconnect fails
+            drops: vec![1],
+            steps: 0,
+            connected: false,
+            searched: vec![],
+        };
+        let delays = std::iter::repeat(Duration::ZERO).take(3);
+        let found = find_after_reconnect(&mut blocks, 10, 16, delays)
+            .await
+            .unwrap();
+        assert_eq!(found, Some(12));
+        assert_eq!(blocks.searched, vec![11, 12]);
+
+        // A transaction that is never included is given up on once it expired, without
+        // searching any block twice across reconnects
+        let mut blocks = MockBlocks {
+            finalized,
+            latest: 13,
+            transaction: 8,
+            drops: vec![4, 8],
+            steps: 0,
+            connected: false,
+            searched: vec![],
+        };
+        let delays = std::iter::repeat(Duration::ZERO).take(3);
+        let found = find_after_reconnect(&mut blocks, 10, 16, delays)
+            .await
+            .unwrap();
+        assert_eq!(found, None);
+        assert_eq!(blocks.searched, vec![11, 12, 13, 14, 15, 16]);
+
+        // The connection error is returned once the reconnects are used up
+        let mut blocks = MockBlocks {
+            finalized: vec![vec![]; 20],
+            latest: 13,
+            transaction: 7,
+            drops: (1..10).collect(),
+            steps: 0,
+            connected: false,
+            searched: vec![],
+        };
+        let delays = std::iter::repeat(Duration::ZERO).take(3);
+        let err = find_after_reconnect(&mut blocks, 10, 16, delays)
+            .await
+            .unwrap_err();
+        assert!(is_connection_error(&err));
+    }
+
+    #[test]
+    fn test_mortality_is_encoded_as_requested() {
+        use subxt::ext::codec::{Decode, Encode};
+
+        let immortal = Mortality::Immortal.era(100);
+        assert_eq!(immortal, Era::Immortal);
+        assert_eq!(immortal.encode(), vec![0]);
+
+        let mortal = Mortality::Mortal { period: 64 }.era(100);
+        assert_ne!(mortal, Era::Immortal);
+        assert_eq!(mortal, Era::mortal(64, 100));
+        let encoded = mortal.encode();
+        assert_eq!(encoded.len(), 2);
+        assert_eq!(Era::decode(&mut &encoded[..]).unwrap(), mortal);
+    }
+
+    #[test]
+    fn test_block_reference_makes_transaction_mortal() {
+        assert_eq!(SendOptions::default().period(), None);
+
+        let mortal = SendOptions {
+            mortality: Mortality::Mortal { period: 16 },
+            ..Default::default()
+        };
+        assert_eq!(mortal.period(), Some(16));
+
+        let at_block = SendOptions {
+            at_block: Some(BlockReference::new(100, H256::repeat_byte(1))),
+            ..Default::default()
+        };
+        assert_eq!(at_block.period(), Some(DEFAULT_BLOCK_REFERENCE_PERIOD));
+        assert_eq!(
+            SendOptions {
+                mortality: Mortality::Mortal { period: 16 },
+                ..at_block
+            }
+            .period(),
+            Some(16)
+        );
+    }
+
+    #[test]
+    fn test_block_reference_is_encoded_into_the_era() {
+        use subxt::ext::codec::Encode;
+
+        let at_block = BlockReference::new(100, H256::repeat_byte(1));
+        let era = at_block.era(DEFAULT_BLOCK_REFERENCE_PERIOD);
+        assert_eq!(era, Era::mortal(DEFAULT_BLOCK_REFERENCE_PERIOD, 100));
+        // The era pins the referenced block number, not just the validity period
+        assert_ne!(
+            era.encode(),
+            BlockReference::new(101, at_block.hash)
+                .era(DEFAULT_BLOCK_REFERENCE_PERIOD)
+                .encode()
+        );
+    }
+
+    #[test]
+    fn test_zero_balance_account_is_rejected() {
+        let account = AccountId32([7; 32]);
+
+        let err = check_free_balance(&account, Some(0), 1).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::InsufficientBalance {
+                free: 0,
+                required: 1,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            format!("Insufficient free balance for {account}: 0 available, 1 required")
+        );
+
+        assert!(matches!(
+            check_free_balance(&account, None, 0),
+            Err(crate::Error::AccountNotFound { .. })
+        ));
+        assert_eq!(check_free_balance(&account, Some(0), 0).unwrap(), 0);
+        assert_eq!(check_free_balance(&account, Some(10), 5).unwrap(), 10);
+    }
+
+    #[test]
+    fn test_spec_version_bump_is_detected() {
+        assert!(check_spec_version(1000, 1000).is_ok());
+
+        let err = check_spec_version(1000, 1001).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::RuntimeUpgraded {
+                from: 1000,
+                to: 1001
+            }
+        ));
+        assert!(err
+            .to_string()
+            .starts_with("The runtime was upgraded from spec version 1000 to 1001"));
+    }
+
+    #[test]
+    fn test_mismatched_runtime_version_is_rejected() {
+        let connected = subxt::client::RuntimeVersion {
+            spec_version: 1000,
+            transaction_version: 1,
+        };
+        let unchanged = subxt::backend::RuntimeVersion {
+            spec_version: 1000,
+            transaction_version: 1,
+        };
+        assert!(check_runtime_version(&connected, &unchanged).is_ok());
+
+        let upgraded = subxt::backend::RuntimeVersion {
+            spec_version: 1001,
+            transaction_version: 2,
+        };
+        assert!(matches!(
+            check_runtime_version(&connected, &upgraded),
+            Err(crate::Error::RuntimeUpgraded {
+                from: 1000,
+                to: 1001
+            })
+        ));
+    }
+
+    #[test]
+    fn test_extrinsic_hex_is_only_logged_when_enabled() {
+        let extrinsic = [0x84, 0x00, 0xde, 0xad];
+        assert_eq!(SendOptions::default().extrinsic_hex(&extrinsic), None);
+
+        let options = SendOptions {
+            log_extrinsic_hex: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.extrinsic_hex(&extrinsic).as_deref(),
+            Some("0x8400dead")
+        );
+    }
+
+    #[test]
+    fn test_signer_pool_is_round_robin() {
+        assert!(SignerPool::<u8>::new(vec![]).is_none());
+
+        let pool = SignerPool::new(vec!["alice", "bob", "charlie"]).unwrap();
+        let picked = (0..7).map(|_| *pool.next_signer()).collect::<Vec<_>>();
+        assert_eq!(
+            picked,
+            vec!["alice", "bob", "charlie", "alice", "bob", "charlie", "alice"]
+        );
+    }
+}